@@ -1,13 +1,10 @@
 use crate::opcode::VariableOp;
-use crate::{ObjectList, ObjectType, OpCode, RcMut, RoxObject, RoxString, Table, DEBUG_MODE};
+use crate::{
+    ObjectList, ObjectType, OpCode, RcMut, RoxMap, RoxNumber, RoxObject, RoxString, Table,
+};
 use crate::{Value, Values};
 use std::cell::RefCell;
 use std::rc::Rc;
-use string_interner::StringInterner;
-
-thread_local! {
-    static INTERNER: StringInterner = StringInterner::default();
-}
 
 ///The Chunk type corresponds to the basic block
 ///of code with a size of count and capacity.
@@ -18,9 +15,12 @@ pub struct Chunk {
     count: usize,
     pub code: Vec<OpCode>,
     pub constants: Values,
-    pub lines: Vec<String>,
-    objects: Rc<RefCell<ObjectList>>,
+    pub lines: Vec<usize>, // the source line of each instruction in code
+    pub(crate) objects: Rc<RefCell<ObjectList>>,
     global_indices: RcMut<Table<RoxString, usize>>,
+    strings: RcMut<Table<RoxString, Rc<RoxString>>>,
+    // the global slot each name constant resolved to, filled in as globals are first accessed
+    global_slots: Vec<Option<usize>>,
 }
 
 impl Chunk {
@@ -31,6 +31,7 @@ impl Chunk {
     pub fn new(
         objects: Rc<RefCell<ObjectList>>,
         global_indices: RcMut<Table<RoxString, usize>>,
+        strings: RcMut<Table<RoxString, Rc<RoxString>>>,
     ) -> Chunk {
         Chunk {
             count: 0,
@@ -39,7 +40,38 @@ impl Chunk {
             lines: vec![],
             objects,
             global_indices,
+            strings,
+            global_slots: vec![],
+        }
+    }
+
+    ///
+    /// Creates an empty chunk for compiling a function body. The
+    /// new chunk tracks its allocations in the same object list and
+    /// interns into the same string pool but keeps its own identifier
+    /// indices, since those index into its own constants array.
+    ///
+    pub fn new_function_chunk(&self) -> Chunk {
+        Chunk::new(
+            Rc::clone(&self.objects),
+            Rc::new(RefCell::new(Table::new())),
+            Rc::clone(&self.strings),
+        )
+    }
+
+    ///
+    /// Returns the pooled copy of the given string, adding it to the
+    /// pool first if no identical string has been seen yet.
+    ///
+    pub fn intern(&self, string: RoxString) -> Rc<RoxString> {
+        let mut strings = self.strings.borrow_mut();
+        if let Some(interned) = strings.get(&string) {
+            return Rc::clone(interned);
         }
+
+        let interned = Rc::new(string.clone());
+        strings.set(&string, &interned);
+        interned
     }
 
     pub fn reset(&mut self) {
@@ -50,6 +82,23 @@ impl Chunk {
         //self.global_indices.borrow_mut().reset();
     }
 
+    /// The global slot cached for the name constant at the given index.
+    pub fn cached_global_slot(&self, name_index: usize) -> Option<usize> {
+        self.global_slots.get(name_index).copied().flatten()
+    }
+
+    ///
+    /// Caches the global slot for a name constant. Constants outlive a
+    /// reset and a global's slot never changes, so the cache is never
+    /// invalidated.
+    ///
+    pub fn cache_global_slot(&mut self, name_index: usize, slot: usize) {
+        if self.global_slots.len() <= name_index {
+            self.global_slots.resize(name_index + 1, None);
+        }
+        self.global_slots[name_index] = Some(slot);
+    }
+
     pub fn count(&self) -> usize {
         self.count
     }
@@ -62,78 +111,46 @@ impl Chunk {
     ///
     pub fn write_chunk(&mut self, byte: OpCode, line: usize) {
         self.code.push(byte);
-        Chunk::write_line_info(self, line, &byte);
+        self.lines.push(line);
         self.count += 1;
     }
 
     ///
-    ///Writes the line info for each byte code instruction to the chunk's
-    ///line vector for keeping track of line data.
+    /// Returns the source line of the instruction at the given offset
+    /// and panics if there is no instruction there.
     ///
-    fn write_line_info(chunk: &mut Chunk, line: usize, byte: &OpCode) {
-        if line > chunk.lines.len() {
-            // possibly add blank lines?
-            let num_blank_lines = line - (chunk.lines.len() + 1);
-
-            // this is a new line
-            let new_count = match chunk.lines.last() {
-                // add up the previous lines count
-                Some(val) => val.parse::<i32>().unwrap() + 1,
-                None => 1,
-            };
-
-            // TODO -- how not to pad lines with count for empty lines?
-            for _ in 0..num_blank_lines {
-                chunk.lines.push((new_count - 1).to_string());
-            }
-
-            chunk.lines.push(new_count.to_string());
-        } else {
-            // increment number of instructions in current line
-            let current_line = match chunk.lines.pop() {
-                Some(val) => val,
-                None => panic!(
-                    "No string index value for the current line {} and byte {}!",
-                    line, byte
-                ),
-            };
-            let mut num_bytes_in_line = current_line.parse::<i32>().unwrap();
-            num_bytes_in_line += 1;
-            chunk.lines.push(num_bytes_in_line.to_string());
+    pub fn line_at(&self, offset: usize) -> usize {
+        match self.lines.get(offset) {
+            Some(line) => *line,
+            None => panic!("Offset {} not in lines list!", offset),
         }
     }
 
     ///
-    /// Searches in the lines array for the first element
-    /// that is greater than the index of the chunk byte
-    /// being searched for and panics if not found.
+    ///This debug function disassembles the Chunk by iterating through
+    ///its list of bytecode instructions and printing them.
     ///
-    pub fn get_line(&self, index: usize) -> usize {
-        let result = self
-            .lines
-            .iter()
-            .enumerate()
-            .find(|(_, line)| index < line.parse::<usize>().unwrap());
-        if let Some(val) = result {
-            return val.0 + 1;
-        }
-        panic!("Index {} not in lines list!", index);
+    pub fn disassemble_chunk(&self, name: &str) {
+        println!("{}", self.disassemble(name));
     }
 
     ///
-    ///This debug function disassembles the Chunk by iterating through
-    ///its list of bytecode instructions.
+    /// Disassembles every instruction in the Chunk, one per line, with
+    /// its offset, line number and operands, and returns the listing.
     ///
-    pub fn disassemble_chunk(&self, name: &str) {
-        println!("=+=+=+= {} =+=+=+=", name);
+    pub fn disassemble(&self, name: &str) -> String {
+        let mut listing = format!("=+=+=+= {} =+=+=+=\n", name);
 
-        for (offset, byte) in self.code.iter().enumerate() {
-            // offset into code vector is just the index
-            Chunk::disassemble_instruction(byte, offset, self);
-            println!();
+        let mut offset = 0;
+        while offset < self.code.len() {
+            let (instruction, next_offset) = self.disassemble_instruction(offset);
+            listing.push_str(&instruction);
+            listing.push('\n');
+            offset = next_offset;
         }
 
-        println!("=+=+=+= Finished printing opcodes! =+=+=+=\n")
+        listing.push_str("=+=+=+= Finished printing opcodes! =+=+=+=\n");
+        listing
     }
 
     ///
@@ -148,7 +165,40 @@ impl Chunk {
             self.objects.borrow_mut().add_object(obj);
         }
 
-        self.write_chunk(OpCode::OpConstant(index), line);
+        // indices past a byte get the long form so the short form can be compacted
+        if index > u8::MAX as usize {
+            self.write_chunk(OpCode::OpConstantLong(index), line);
+        } else {
+            self.write_chunk(OpCode::OpConstant(index), line);
+        }
+    }
+
+    /// The number pushed by the constant instruction at offset, if it pushes one.
+    pub fn number_constant_at(&self, offset: usize) -> Option<RoxNumber> {
+        match self.code.get(offset)? {
+            OpCode::OpConstant(index) | OpCode::OpConstantLong(index) => {
+                match self.constants.values.get(*index)? {
+                    Value::Number(number) => Some(*number),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    ///
+    /// Removes the last instruction, which must be a constant push, along
+    /// with its constant when nothing was added to the constants after it.
+    ///
+    pub fn pop_constant(&mut self) {
+        if let Some(OpCode::OpConstant(index) | OpCode::OpConstantLong(index)) = self.code.pop() {
+            if index + 1 == self.constants.values.len() {
+                self.constants.values.pop();
+                self.constants.count -= 1;
+            }
+        }
+        self.lines.pop();
+        self.count -= 1;
     }
 
     pub fn add_identifier_constant(
@@ -158,13 +208,11 @@ impl Chunk {
         variable_op: VariableOp,
     ) -> usize {
         let (index, value_ref) = self.constants.write_value(
-            Value::Object(RoxObject::new(ObjectType::ObjString(string_value.clone()))),
+            Value::Object(RoxObject::new(ObjectType::ObjString(
+                self.intern(string_value.clone()),
+            ))),
             Some(&mut self.global_indices.borrow_mut()),
         );
-        if DEBUG_MODE {
-            println!("Added id {} at index {} to values", value_ref, index);
-        }
-
         if let Value::Object(obj) = value_ref {
             self.objects.borrow_mut().add_object(obj);
         }
@@ -179,31 +227,47 @@ impl Chunk {
         index
     }
 
-    ///Helper function for disassembling bytecode instructions instructions
-    ///in the bytecode vector for Chunk.
-    pub fn disassemble_instruction(instr: &OpCode, offset: usize, chunk: &Chunk) {
-        print!("| {:0>4} ", offset);
-        print!("| {:>4} | ", chunk.get_line(offset));
-
-        match instr {
+    ///
+    ///Disassembles the instruction at the given offset in the bytecode
+    ///vector for Chunk. Returns the formatted instruction along with the
+    ///offset of the next instruction.
+    ///
+    pub fn disassemble_instruction(&self, offset: usize) -> (String, usize) {
+        let operation = match &self.code[offset] {
             OpCode::OpReturn(_) => Chunk::simple_instruction("OP_RETURN"),
             OpCode::OpConstant(constants_index) => {
-                Chunk::constant_instruction("OP_CONSTANT", *constants_index, chunk)
+                self.constant_instruction("OP_CONSTANT", *constants_index)
+            }
+            OpCode::OpConstantLong(constants_index) => {
+                self.constant_instruction("OP_CONSTANT_LONG", *constants_index)
             }
             OpCode::OpNegate => Chunk::simple_instruction("OP_NEGATE"),
+            OpCode::OpUnaryPlus => Chunk::simple_instruction("OP_UNARY_PLUS"),
             OpCode::OpAdd => Chunk::simple_instruction("OP_ADD"),
             OpCode::OpSubtract => Chunk::simple_instruction("OP_SUBTRACT"),
             OpCode::OpMultiply => Chunk::simple_instruction("OP_MULTIPLY"),
             OpCode::OpDivide => Chunk::simple_instruction("OP_DIVIDE"),
+            OpCode::OpModulo => Chunk::simple_instruction("OP_MODULO"),
+            OpCode::OpFloorDiv => Chunk::simple_instruction("OP_FLOOR_DIV"),
+            OpCode::OpPower => Chunk::simple_instruction("OP_POWER"),
+            OpCode::OpBitAnd => Chunk::simple_instruction("OP_BIT_AND"),
+            OpCode::OpBitOr => Chunk::simple_instruction("OP_BIT_OR"),
+            OpCode::OpBitXor => Chunk::simple_instruction("OP_BIT_XOR"),
+            OpCode::OpShl => Chunk::simple_instruction("OP_SHL"),
+            OpCode::OpShr => Chunk::simple_instruction("OP_SHR"),
             OpCode::OpNil => Chunk::simple_instruction("OP_NIL"),
             OpCode::OpTrue => Chunk::simple_instruction("OP_TRUE"),
             OpCode::OpFalse => Chunk::simple_instruction("OP_FALSE"),
             OpCode::OpNot => Chunk::simple_instruction("OP_NOT"),
             OpCode::OpGreater => Chunk::simple_instruction("OP_GREATER"),
             OpCode::OpEqual => Chunk::simple_instruction("OP_EQUAL"),
+            OpCode::OpStrictEqual => Chunk::simple_instruction("OP_STRICT_EQUAL"),
             OpCode::OpLess => Chunk::simple_instruction("OP_LESS"),
             OpCode::OpPrint => Chunk::simple_instruction("OP_PRINT"),
             OpCode::OpPop => Chunk::simple_instruction("OP_POP"),
+            OpCode::OpPopN(count) => {
+                Chunk::simple_instruction(format!("OP_POP_N {}", count).as_str())
+            }
             OpCode::OpDefineGlobal(_) => Chunk::simple_instruction("OP_DEFINE_GLOBAL"),
             OpCode::OpGetGlobal(_) => Chunk::simple_instruction("OP_GET_GLOBAL"),
             OpCode::OpSetGlobal(_) => Chunk::simple_instruction("OP_SET_GLOBAL"),
@@ -218,19 +282,60 @@ impl Chunk {
             OpCode::OpLoop(offset) => {
                 Chunk::simple_instruction(format!("OP_LOOP {}", offset).as_str())
             }
+            OpCode::OpEcho => Chunk::simple_instruction("OP_ECHO"),
+            OpCode::OpPushLine => Chunk::simple_instruction("OP_PUSH_LINE"),
+            OpCode::OpPushHandler(catch_offset, finally_offset) => Chunk::simple_instruction(
+                format!(
+                    "OP_PUSH_HANDLER {} {}",
+                    catch_offset.map_or("-".to_string(), |offset| offset.to_string()),
+                    finally_offset.map_or("-".to_string(), |offset| offset.to_string())
+                )
+                .as_str(),
+            ),
+            OpCode::OpPopHandler => Chunk::simple_instruction("OP_POP_HANDLER"),
+            OpCode::OpLeaveTry => Chunk::simple_instruction("OP_LEAVE_TRY"),
+            OpCode::OpEndFinally => Chunk::simple_instruction("OP_END_FINALLY"),
+            OpCode::OpThrow => Chunk::simple_instruction("OP_THROW"),
+            OpCode::OpCall(arg_count) => {
+                Chunk::simple_instruction(format!("OP_CALL {}", arg_count).as_str())
+            }
+            OpCode::OpGetProperty(index) => {
+                Chunk::simple_instruction(format!("OP_GET_PROPERTY {}", index).as_str())
+            }
+            OpCode::OpBuildArray(count) => {
+                Chunk::simple_instruction(format!("OP_BUILD_ARRAY {}", count).as_str())
+            }
+            OpCode::OpIndexGet => Chunk::simple_instruction("OP_INDEX_GET"),
+            OpCode::OpIndexSet => Chunk::simple_instruction("OP_INDEX_SET"),
+            OpCode::OpClosure => Chunk::simple_instruction("OP_CLOSURE"),
+            OpCode::OpGetUpvalue(index) => {
+                Chunk::simple_instruction(format!("OP_GET_UPVALUE {}", index).as_str())
+            }
+            OpCode::OpSetUpvalue(index) => {
+                Chunk::simple_instruction(format!("OP_SET_UPVALUE {}", index).as_str())
+            }
+            OpCode::OpCloseUpvalue => Chunk::simple_instruction("OP_CLOSE_UPVALUE"),
         };
+
+        (
+            format!(
+                "| {:0>4} | {:>4} | {}",
+                offset,
+                self.line_at(offset),
+                operation
+            ),
+            offset + 1,
+        )
     }
 
-    fn constant_instruction(name: &str, index: usize, chunk: &Chunk) {
-        print!("{:>11} {:<4}'", name, index);
-        match chunk.constants.values.get(index) {
-            Some(val) => print!("{:>4}", val),
+    fn constant_instruction(&self, name: &str, index: usize) -> String {
+        match self.constants.values.get(index) {
+            Some(val) => format!("{:>11} {:<4}'{:>4}' |", name, index, val),
             None => panic!("No constant value at that index!"),
-        };
-        print!("' |");
+        }
     }
 
-    fn simple_instruction(name: &str) {
-        print!("{:<25} |", name);
+    fn simple_instruction(name: &str) -> String {
+        format!("{:<25} |", name)
     }
 }
@@ -1,178 +1,272 @@
-use crate::frontend::{Local, Locals, LOCALS_COUNT};
+use crate::frontend::{Locals, LOCALS_COUNT};
 use crate::opcode::VariableOp;
 use crate::{
-    Chunk, ObjectType, OpCode, Precedence, RoxNumber, RoxObject, RoxString, Token, TokenType,
-    Value, DEBUG_MODE,
+    Chunk, CompileError, ObjectType, OpCode, Precedence, RcMut, RoxFunction, RoxMap, RoxNumber,
+    RoxObject, RoxString, Table, Token, TokenType, Tracer, Upvalue, Value, MAX_CONSTANTS,
 };
 use std::cell::RefCell;
 use std::iter::Peekable;
 use std::rc::Rc;
 use std::slice::Iter;
 
+/// Name of the builtin that evaluates to the current source line.
+const LINE_BUILTIN: &str = "__line__";
+
+/// Name given to the function wrapping top-level code.
+pub const SCRIPT_NAME: &str = "script";
+
+/// Name given to functions created by a fun expression.
+pub const ANONYMOUS_NAME: &str = "anonymous";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FunctionType {
+    Script,
+    Function,
+}
+
+/// The compiler state of a function whose compilation was
+/// suspended to compile a function declared inside it.
+struct EnclosingFunction {
+    chunk: RcMut<Chunk>,
+    locals: Locals,
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
+    function_type: FunctionType,
+    loops: Vec<LoopContext>,
+    tries: Vec<usize>,
+}
+
+/// A loop being compiled, used to resolve its break and continue statements.
+struct LoopContext {
+    // None when the continue target comes after the body, as in do-while
+    continue_target: Option<usize>,
+    local_count: usize,
+    try_count: usize,
+    break_jumps: Vec<usize>,
+    continue_jumps: Vec<usize>,
+}
+
 pub struct Compiler<'a> {
-    chunk: Rc<RefCell<Chunk>>,
+    chunk: RefCell<RcMut<Chunk>>,
     tokens: RefCell<Peekable<Iter<'a, Token>>>,
     previous: RefCell<Option<&'a Token>>,
     current: RefCell<Option<&'a Token>>,
     pub had_error: RefCell<bool>,
     pub panic_mode: RefCell<bool>,
+    // echo the value of top-level expression statements, for the REPL
+    pub echo_expressions: RefCell<bool>,
+    // the most constants a single chunk may hold before compiling fails
+    pub max_constants: RefCell<usize>,
 
     locals: RefCell<Locals>,
+    // the variables captured from enclosing functions by the one being compiled
+    upvalues: RefCell<Vec<Upvalue>>,
     scope_depth: RefCell<usize>,
+    function_type: RefCell<FunctionType>,
+    enclosing: RefCell<Vec<EnclosingFunction>>,
+    loops: RefCell<Vec<LoopContext>>,
+    // the local count at the start of each try block being compiled
+    tries: RefCell<Vec<usize>>,
+    // each global function declared so far, for checking the arity of direct calls
+    functions: RefCell<Table<RoxString, Rc<RoxFunction>>>,
+    // globals declared const so far, which can't be assigned to
+    const_globals: RefCell<Table<RoxString, bool>>,
+    // the offset and name of the most recently emitted global read
+    global_callee: RefCell<Option<(usize, RoxString)>>,
+    // the offset most recently patched in as a jump target
+    jump_target: RefCell<Option<usize>>,
+    errors: RefCell<Vec<CompileError>>,
+    tracer: Tracer,
 }
 
 type ParseFn<'a> = Box<dyn FnOnce(bool) + 'a>;
 
 struct ParseRule<'a> {
     precedence: Precedence,
+    associativity: Associativity,
     infix_fn: Option<ParseFn<'a>>,
     prefix_fn: Option<ParseFn<'a>>,
 }
 
+/// Which side of an infix operator binds a chain of them, as in `2 ** 3 ** 2`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Associativity {
+    Left,
+    Right,
+}
+
+/// How an expression starting with a token is parsed.
+#[derive(Debug, Clone, Copy)]
+enum Prefix {
+    Number,
+    String,
+    Variable,
+    Literal,
+    Unary,
+    Grouping,
+    Array,
+    Function,
+    Increment,
+}
+
+/// How an expression continues after a token following an operand.
+#[derive(Debug, Clone, Copy)]
+enum Infix {
+    Binary,
+    And,
+    Or,
+    Conditional,
+    Call,
+    Dot,
+    Index,
+}
+
+/// A row of the parse table, describing how a kind of token is parsed.
+struct RuleRow {
+    precedence: Precedence,
+    associativity: Associativity,
+    prefix: Option<Prefix>,
+    infix: Option<Infix>,
+}
+
+const fn row(precedence: Precedence, prefix: Option<Prefix>, infix: Option<Infix>) -> RuleRow {
+    RuleRow {
+        precedence,
+        associativity: Associativity::Left,
+        prefix,
+        infix,
+    }
+}
+
+///
+/// The Pratt parse table. A new operator needs a row here, naming its
+/// precedence and parse functions, and binary operators an opcode in
+/// binary. Every other token gets an empty row, so parse reports it
+/// as an error when it turns up where an expression was expected.
+///
+fn rule_row(t_type: &TokenType) -> RuleRow {
+    use Precedence::*;
+    match t_type {
+        TokenType::LeftParen => row(PrecCall, Some(Prefix::Grouping), Some(Infix::Call)),
+        TokenType::LeftBracket => row(PrecCall, Some(Prefix::Array), Some(Infix::Index)),
+        TokenType::Dot => row(PrecCall, None, Some(Infix::Dot)),
+        TokenType::StarStar => RuleRow {
+            associativity: Associativity::Right,
+            ..row(PrecPower, None, Some(Infix::Binary))
+        },
+        TokenType::Star | TokenType::Slash | TokenType::SlashSlash | TokenType::Percent => {
+            row(PrecFactor, None, Some(Infix::Binary))
+        }
+        TokenType::Plus | TokenType::Minus => {
+            row(PrecTerm, Some(Prefix::Unary), Some(Infix::Binary))
+        }
+        TokenType::LessLess | TokenType::GreaterGreater => {
+            row(PrecShift, None, Some(Infix::Binary))
+        }
+        TokenType::Ampersand => row(PrecBitAnd, None, Some(Infix::Binary)),
+        TokenType::Caret => row(PrecBitXor, None, Some(Infix::Binary)),
+        TokenType::Pipe => row(PrecBitOr, None, Some(Infix::Binary)),
+        TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual => {
+            row(PrecComparison, None, Some(Infix::Binary))
+        }
+        TokenType::BangEqual | TokenType::EqualEqual | TokenType::EqualEqualEqual => {
+            row(PrecEquality, None, Some(Infix::Binary))
+        }
+        TokenType::And => row(PrecAnd, None, Some(Infix::And)),
+        TokenType::Or => row(PrecOr, None, Some(Infix::Or)),
+        TokenType::Question => row(PrecConditional, None, Some(Infix::Conditional)),
+        TokenType::Bang => row(PrecNone, Some(Prefix::Unary), None),
+        TokenType::PlusPlus | TokenType::MinusMinus => row(PrecNone, Some(Prefix::Increment), None),
+        TokenType::Number(_) => row(PrecNone, Some(Prefix::Number), None),
+        TokenType::StringLiteral(_) => row(PrecNone, Some(Prefix::String), None),
+        TokenType::Identifier(_) => row(PrecNone, Some(Prefix::Variable), None),
+        TokenType::True | TokenType::False | TokenType::Nil => {
+            row(PrecNone, Some(Prefix::Literal), None)
+        }
+        TokenType::Fun => row(PrecNone, Some(Prefix::Function), None),
+        _ => row(PrecNone, None, None),
+    }
+}
+
 impl<'a> Compiler<'a> {
     pub fn new(
         chunk: Rc<RefCell<Chunk>>,
         tokens: RefCell<Peekable<Iter<'a, Token>>>,
+        tracer: Tracer,
     ) -> Compiler<'a> {
         Compiler {
-            chunk,
+            chunk: RefCell::new(chunk),
             tokens,
             had_error: RefCell::new(false),
             panic_mode: RefCell::new(false),
+            echo_expressions: RefCell::new(false),
+            max_constants: RefCell::new(MAX_CONSTANTS),
             previous: RefCell::new(None),
             current: RefCell::new(None),
             scope_depth: RefCell::new(0),
             locals: RefCell::new(Locals::new()),
+            upvalues: RefCell::new(vec![]),
+            function_type: RefCell::new(FunctionType::Script),
+            enclosing: RefCell::new(vec![]),
+            loops: RefCell::new(vec![]),
+            tries: RefCell::new(vec![]),
+            functions: RefCell::new(Table::new()),
+            const_globals: RefCell::new(Table::new()),
+            global_callee: RefCell::new(None),
+            jump_target: RefCell::new(None),
+            errors: RefCell::new(vec![]),
+            tracer,
         }
     }
 
-    fn get_rule(&'a self, token: &'a Token) -> ParseRule {
+    /// The chunk of the function currently being compiled.
+    fn current_chunk(&self) -> RcMut<Chunk> {
+        Rc::clone(&self.chunk.borrow())
+    }
+
+    fn get_rule(&'a self, token: &'a Token) -> ParseRule<'a> {
         let t_type = &token.token_type;
         let line = token.line;
-
-        match t_type {
-            TokenType::And => ParseRule {
-                precedence: Precedence::PrecAnd,
-                infix_fn: Some(Box::new(|can_assign| self.and_(can_assign))),
-                prefix_fn: None,
-            },
-            TokenType::Or => ParseRule {
-                precedence: Precedence::PrecOr,
-                infix_fn: Some(Box::new(|can_assign| self.or(can_assign))),
-                prefix_fn: None,
-            },
-            TokenType::Plus => ParseRule {
-                precedence: Precedence::PrecTerm,
-                infix_fn: Some(Box::new(|can_assign| self.binary(can_assign))),
-                prefix_fn: None,
-            },
-            TokenType::Minus => ParseRule {
-                precedence: Precedence::PrecTerm,
-                infix_fn: Some(Box::new(|can_assign| self.binary(can_assign))),
-                prefix_fn: Some(Box::new(|can_assign| self.unary(can_assign))),
-            },
-            TokenType::Star => ParseRule {
-                precedence: Precedence::PrecFactor,
-                prefix_fn: None,
-                infix_fn: Some(Box::new(|can_assign| self.binary(can_assign))),
-            },
-            TokenType::Slash => ParseRule {
-                precedence: Precedence::PrecFactor,
-                prefix_fn: None,
-                infix_fn: Some(Box::new(|can_assign| self.binary(can_assign))),
-            },
-            TokenType::Number(num) => ParseRule {
-                precedence: Precedence::PrecNone,
-                prefix_fn: Some(Box::new(move |can_assign| {
-                    self.number(*num, line, can_assign)
-                })),
-                infix_fn: None,
-            },
-            TokenType::True => ParseRule {
-                precedence: Precedence::PrecNone,
-                prefix_fn: Some(Box::new(|can_assign| self.literal(can_assign))),
-                infix_fn: None,
-            },
-            TokenType::False => ParseRule {
-                precedence: Precedence::PrecNone,
-                prefix_fn: Some(Box::new(|can_assign| self.literal(can_assign))),
-                infix_fn: None,
-            },
-            TokenType::Nil => ParseRule {
-                precedence: Precedence::PrecNone,
-                prefix_fn: Some(Box::new(|can_assign| self.literal(can_assign))),
-                infix_fn: None,
-            },
-            TokenType::Bang => ParseRule {
-                precedence: Precedence::PrecNone,
-                prefix_fn: Some(Box::new(|can_assign| self.unary(can_assign))),
-                infix_fn: None,
-            },
-            TokenType::BangEqual => ParseRule {
-                precedence: Precedence::PrecEquality,
-                prefix_fn: None,
-                infix_fn: Some(Box::new(|can_assign| self.binary(can_assign))),
-            },
-            TokenType::EqualEqual => ParseRule {
-                precedence: Precedence::PrecEquality,
-                prefix_fn: None,
-                infix_fn: Some(Box::new(|can_assign| self.binary(can_assign))),
-            },
-            TokenType::Greater => ParseRule {
-                precedence: Precedence::PrecComparison,
-                prefix_fn: None,
-                infix_fn: Some(Box::new(|can_assign| self.binary(can_assign))),
-            },
-            TokenType::GreaterEqual => ParseRule {
-                precedence: Precedence::PrecComparison,
-                prefix_fn: None,
-                infix_fn: Some(Box::new(|can_assign| self.binary(can_assign))),
-            },
-            TokenType::Less => ParseRule {
-                precedence: Precedence::PrecComparison,
-                prefix_fn: None,
-                infix_fn: Some(Box::new(|can_assign| self.binary(can_assign))),
-            },
-            TokenType::LessEqual => ParseRule {
-                precedence: Precedence::PrecComparison,
-                prefix_fn: None,
-                infix_fn: Some(Box::new(|can_assign| self.binary(can_assign))),
-            },
-            TokenType::LeftParen => ParseRule {
-                precedence: Precedence::PrecNone,
-                prefix_fn: Some(Box::new(|can_assign| self.grouping(can_assign))),
-                infix_fn: None,
-            },
-            TokenType::RightParen => ParseRule {
-                precedence: Precedence::PrecNone,
-                prefix_fn: None,
-                infix_fn: None,
-            },
-            TokenType::Semicolon => ParseRule {
-                precedence: Precedence::PrecNone,
-                prefix_fn: None,
-                infix_fn: None,
-            },
-            TokenType::Identifier(id) => ParseRule {
-                precedence: Precedence::PrecNone,
-                prefix_fn: Some(Box::new(move |can_assign| {
-                    self.variable(id, line, can_assign)
-                })),
-                infix_fn: None,
-            },
-            TokenType::StringLiteral(str) => ParseRule {
-                precedence: Precedence::PrecNone,
-                prefix_fn: Some(Box::new(move |can_assign| {
-                    self.string(str, line, can_assign)
-                })),
-                infix_fn: None,
-            },
-            TokenType::EOF => ParseRule {
-                precedence: Precedence::PrecNone,
-                prefix_fn: None,
-                infix_fn: None,
-            },
-            _ => todo!("Unimplemented token type: {:?}", t_type),
+        let row = rule_row(t_type);
+
+        ParseRule {
+            precedence: row.precedence,
+            associativity: row.associativity,
+            prefix_fn: row.prefix.map(|prefix| -> ParseFn<'a> {
+                match (prefix, t_type) {
+                    (Prefix::Number, TokenType::Number(num)) => {
+                        Box::new(move |can_assign| self.number(*num, line, can_assign))
+                    }
+                    (Prefix::String, TokenType::StringLiteral(str)) => {
+                        Box::new(move |can_assign| self.string(str, line, can_assign))
+                    }
+                    (Prefix::Variable, TokenType::Identifier(id)) => {
+                        Box::new(move |can_assign| self.variable(id, line, can_assign))
+                    }
+                    (Prefix::Literal, _) => Box::new(|can_assign| self.literal(can_assign)),
+                    (Prefix::Unary, _) => Box::new(|can_assign| self.unary(can_assign)),
+                    (Prefix::Grouping, _) => Box::new(|can_assign| self.grouping(can_assign)),
+                    (Prefix::Array, _) => Box::new(|can_assign| self.array(can_assign)),
+                    (Prefix::Function, _) => {
+                        Box::new(|can_assign| self.anonymous_function(can_assign))
+                    }
+                    (Prefix::Increment, _) => {
+                        Box::new(|can_assign| self.prefix_increment(can_assign))
+                    }
+                    (prefix, _) => panic!("{:?} rule given a {:?} token", prefix, t_type),
+                }
+            }),
+            infix_fn: row.infix.map(|infix| -> ParseFn<'a> {
+                match infix {
+                    Infix::Binary => Box::new(|can_assign| self.binary(can_assign)),
+                    Infix::And => Box::new(|can_assign| self.and_(can_assign)),
+                    Infix::Or => Box::new(|can_assign| self.or(can_assign)),
+                    Infix::Conditional => Box::new(|can_assign| self.conditional(can_assign)),
+                    Infix::Call => Box::new(|can_assign| self.call(can_assign)),
+                    Infix::Dot => Box::new(|can_assign| self.dot(can_assign)),
+                    Infix::Index => Box::new(|can_assign| self.index(can_assign)),
+                }
+            }),
         }
     }
 
@@ -189,9 +283,8 @@ impl<'a> Compiler<'a> {
                 None => return, //  panic!("Error getting next token in advance!"),
             };
 
-            if DEBUG_MODE {
-                println!("Advanced to Token: {}", next_token);
-            }
+            self.tracer
+                .compiler(format_args!("Advanced to Token: {}", next_token));
 
             *(self.current.borrow_mut()) = Some(next_token);
 
@@ -214,13 +307,20 @@ impl<'a> Compiler<'a> {
     }
 
     /// Helper function to check that the current token's
-    /// type is equal to t_type.
+    /// type is equal to t_type. There's no current token
+    /// before the first advance, which matches nothing.
     fn check_token(&self, t_type: TokenType) -> bool {
         self.current
             .borrow()
-            .expect("Error borrowing next token in parser check!")
-            .token_type
-            == t_type
+            .is_some_and(|token| token.token_type == t_type)
+    }
+
+    /// Checks the type of the token after the current one.
+    fn check_next_token(&self, t_type: TokenType) -> bool {
+        self.tokens
+            .borrow_mut()
+            .peek()
+            .is_some_and(|token| token.token_type == t_type)
     }
 
     fn consume(&self, t_type: TokenType, message: &str) {
@@ -229,9 +329,8 @@ impl<'a> Compiler<'a> {
             .borrow()
             .expect("Error consuming current token!");
         if current_tok.token_type == t_type {
-            if DEBUG_MODE {
-                println!("Consuming token {}", current_tok);
-            }
+            self.tracer
+                .compiler(format_args!("Consuming token {}", current_tok));
             self.advance();
             return;
         }
@@ -265,10 +364,15 @@ impl<'a> Compiler<'a> {
 
         *self.panic_mode.borrow_mut() = true;
 
-        eprintln!(
-            "Error at [{}, {}] with message: {}",
-            token.line, token.column, message
-        );
+        let error = CompileError {
+            line: token.line,
+            column: token.column,
+            message: message.to_string(),
+        };
+        if self.tracer.config().trace_compiler {
+            eprintln!("{}", error);
+        }
+        self.errors.borrow_mut().push(error);
         *self.had_error.borrow_mut() = true;
     }
 
@@ -295,10 +399,16 @@ impl<'a> Compiler<'a> {
                 TokenType::Class
                 | TokenType::Fun
                 | TokenType::Var
+                | TokenType::Const
                 | TokenType::For
                 | TokenType::If
                 | TokenType::While
+                | TokenType::Do
                 | TokenType::Print
+                | TokenType::Try
+                | TokenType::Throw
+                | TokenType::Break
+                | TokenType::Continue
                 | TokenType::Return => return,
                 _ => (),
             }
@@ -317,19 +427,191 @@ impl<'a> Compiler<'a> {
         self.parse(&Precedence::PrecAssign);
     }
 
-    fn declaration(&'a self) {
-        if self.match_token(TokenType::Var) {
+    /// Compiles a declaration, or a statement when it isn't one. See statement for in_body.
+    fn declaration(&'a self, in_body: bool) {
+        // a fun followed by '(' starts an anonymous function expression
+        if self.check_token(TokenType::Fun) && !self.check_next_token(TokenType::LeftParen) {
+            self.advance();
+            self.fun_declaration();
+        } else if self.match_token(TokenType::Var) {
             self.var_declaration();
+        } else if self.match_token(TokenType::Const) {
+            self.const_declaration();
         } else {
-            self.statement();
+            self.statement(in_body);
         }
         if *self.panic_mode.borrow() {
             self.synchronize();
         }
     }
 
+    fn fun_declaration(&'a self) {
+        let index = self.parse_variable("Expect function name.", false);
+        let name = match &self
+            .previous
+            .borrow()
+            .expect("Error borrowing previous token for function name.")
+            .token_type
+        {
+            TokenType::Identifier(name) => RoxString::clone(name),
+            _ => RoxString::new(""),
+        };
+
+        let function = self.function(name.clone());
+        if *self.scope_depth.borrow() == 0 {
+            self.functions.borrow_mut().set(&name, &function);
+        }
+        self.define_variable(index);
+    }
+
+    /// Compiles the parameter list and body of a function into a new
+    /// chunk, emits the resulting function as a constant and returns it.
+    fn function(&'a self, name: RoxString) -> Rc<RoxFunction> {
+        self.begin_function(FunctionType::Function);
+        self.begin_scope();
+
+        self.consume(TokenType::LeftParen, "Expect '(' after function name.");
+        let mut arity = 0;
+        let mut defaults = vec![];
+        let mut variadic = false;
+        if !self.check_token(TokenType::RightParen) {
+            loop {
+                arity += 1;
+                if arity > 255 {
+                    self.error_at_current_token("Can't have more than 255 parameters.");
+                }
+                if variadic {
+                    self.error_at_current_token("A rest parameter must be the last parameter.");
+                }
+                variadic = self.match_token(TokenType::DotDotDot);
+                if variadic && !defaults.is_empty() {
+                    self.error("A rest parameter can't follow parameters with defaults.");
+                }
+                let index = self.parse_variable("Expect parameter name.", false);
+                self.define_variable(index);
+                if !variadic {
+                    if self.match_token(TokenType::Equal) {
+                        defaults.push(self.default_value());
+                    } else if !defaults.is_empty() {
+                        self.error("Parameters with defaults must come last.");
+                    }
+                }
+
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after parameters.");
+        self.consume(TokenType::LeftBrace, "Expect '{' before function body.");
+        self.function_body();
+
+        let (chunk, upvalues) = self.end_function(&name);
+        let is_closure = !upvalues.is_empty();
+        let mut function = RoxFunction::with_upvalues(name, arity, chunk, upvalues);
+        function.defaults = defaults;
+        function.variadic = variadic;
+        let function = Rc::new(function);
+        let line = self
+            .previous
+            .borrow()
+            .expect("Error borrowing previous token after function body.")
+            .line;
+        self.emit_constant(
+            Value::Object(RoxObject::new(ObjectType::ObjFunction(Rc::clone(
+                &function,
+            )))),
+            line,
+        );
+        // only functions that capture variables need a closure
+        if is_closure {
+            self.emit_byte(OpCode::OpClosure);
+        }
+
+        function
+    }
+
+    ///
+    /// Parses the default of a parameter, which must be a literal so
+    /// that it can be stored on the function rather than compiled.
+    ///
+    fn default_value(&'a self) -> Value {
+        let negate = self.match_token(TokenType::Minus);
+        self.advance();
+        let previous = self
+            .previous
+            .borrow()
+            .expect("Error borrowing previous token for parameter default.");
+        match (&previous.token_type, negate) {
+            (TokenType::Number(num), _) => {
+                Value::Number(if negate { RoxNumber(-num.0) } else { *num })
+            }
+            (TokenType::StringLiteral(string), false) => {
+                let interned = self.current_chunk().borrow().intern((**string).clone());
+                Value::Object(RoxObject::new(ObjectType::ObjString(interned)))
+            }
+            (TokenType::True, false) => Value::Boolean(true),
+            (TokenType::False, false) => Value::Boolean(false),
+            (TokenType::Nil, false) => Value::Nil,
+            _ => {
+                self.error("Default parameter value must be a literal.");
+                Value::Nil
+            }
+        }
+    }
+
+    /// Suspends the function being compiled and starts compiling
+    /// a new one into a fresh chunk with its own locals.
+    fn begin_function(&self, function_type: FunctionType) {
+        let chunk = Rc::new(RefCell::new(
+            self.current_chunk().borrow().new_function_chunk(),
+        ));
+        let mut locals = Locals::new();
+        locals.reserve_slot();
+
+        self.global_callee.replace(None);
+        let enclosing = EnclosingFunction {
+            chunk: self.chunk.replace(chunk),
+            locals: self.locals.replace(locals),
+            upvalues: self.upvalues.replace(vec![]),
+            scope_depth: self.scope_depth.replace(0),
+            function_type: self.function_type.replace(function_type),
+            loops: self.loops.replace(vec![]),
+            tries: self.tries.replace(vec![]),
+        };
+        self.enclosing.borrow_mut().push(enclosing);
+    }
+
+    /// Finishes the function being compiled, resumes compiling the
+    /// enclosing function and returns the finished function's chunk
+    /// and the variables it captures.
+    fn end_function(&self, name: &RoxString) -> (RcMut<Chunk>, Vec<Upvalue>) {
+        self.emit_return();
+
+        let enclosing = self
+            .enclosing
+            .borrow_mut()
+            .pop()
+            .expect("Error ending function compilation without an enclosing function.");
+        self.locals.replace(enclosing.locals);
+        let upvalues = self.upvalues.replace(enclosing.upvalues);
+        self.scope_depth.replace(enclosing.scope_depth);
+        self.function_type.replace(enclosing.function_type);
+        self.loops.replace(enclosing.loops);
+        self.tries.replace(enclosing.tries);
+        self.global_callee.replace(None);
+        let chunk = self.chunk.replace(enclosing.chunk);
+
+        if self.tracer.config().trace_compiler {
+            self.tracer
+                .compiler(format_args!("{}", chunk.borrow().disassemble(name)));
+        }
+
+        (chunk, upvalues)
+    }
+
     fn var_declaration(&'a self) {
-        let index = self.parse_variable("Expect variable name.");
+        let index = self.parse_variable("Expect variable name.", false);
 
         if self.match_token(TokenType::Equal) {
             self.expression();
@@ -345,13 +627,39 @@ impl<'a> Compiler<'a> {
         self.define_variable(index);
     }
 
-    fn declare_variable(&'a self) {
+    fn const_declaration(&'a self) {
+        let index = self.parse_variable("Expect constant name.", true);
+        let name = match &self
+            .previous
+            .borrow()
+            .expect("Error borrowing previous token for constant name.")
+            .token_type
+        {
+            TokenType::Identifier(name) => RoxString::clone(name),
+            _ => RoxString::new(""),
+        };
+
+        // a const can never be assigned later, so it must be initialized here
+        self.consume(TokenType::Equal, "Expect '=' after constant name.");
+        self.expression();
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after constant declaration.",
+        );
+
+        if *self.scope_depth.borrow() == 0 {
+            self.const_globals.borrow_mut().set(&name, &true);
+        }
+        self.define_variable(index);
+    }
+
+    fn declare_variable(&'a self, is_const: bool) {
         // for globals
         if *self.scope_depth.borrow() == 0 {
             return;
         }
 
-        let token = &*self
+        let token = self
             .previous
             .borrow()
             .expect("Error borrowing previous token when declaring local variable.");
@@ -366,10 +674,10 @@ impl<'a> Compiler<'a> {
             return;
         }
 
-        self.add_local(token);
+        self.add_local(token, is_const);
     }
 
-    fn add_local(&'a self, token: &Token) {
+    fn add_local(&'a self, token: &Token, is_const: bool) {
         let locals_count = self.locals.borrow().size();
         if locals_count == LOCALS_COUNT {
             self.error("Too many local variables in function.");
@@ -378,7 +686,11 @@ impl<'a> Compiler<'a> {
 
         self.locals
             .borrow_mut()
-            .add_local(token, *self.scope_depth.borrow());
+            .add_local(token, *self.scope_depth.borrow(), is_const);
+        self.tracer.compiler(format_args!(
+            "Added local variable at index {}",
+            locals_count
+        ));
     }
 
     fn define_variable(&'a self, index: usize) {
@@ -391,21 +703,38 @@ impl<'a> Compiler<'a> {
         self.emit_byte(OpCode::OpDefineGlobal(index));
     }
 
-    fn statement(&'a self) {
+    ///
+    /// Compiles a statement. in_body is set for statements directly in a
+    /// function body, where a trailing expression is returned; the
+    /// statements nested in this one never are.
+    ///
+    fn statement(&'a self, in_body: bool) {
         if self.match_token(TokenType::Print) {
             self.print_statement();
         } else if self.match_token(TokenType::For) {
             self.for_statement();
         } else if self.match_token(TokenType::If) {
             self.if_statement();
+        } else if self.match_token(TokenType::Return) {
+            self.return_statement();
         } else if self.match_token(TokenType::While) {
             self.while_statement();
+        } else if self.match_token(TokenType::Do) {
+            self.do_while_statement();
+        } else if self.match_token(TokenType::Break) {
+            self.break_statement();
+        } else if self.match_token(TokenType::Continue) {
+            self.continue_statement();
+        } else if self.match_token(TokenType::Try) {
+            self.try_statement();
+        } else if self.match_token(TokenType::Throw) {
+            self.throw_statement();
         } else if self.match_token(TokenType::LeftBrace) {
             self.begin_scope();
             self.block();
             self.end_scope();
         } else {
-            self.expression_statement();
+            self.expression_statement(in_body);
         }
     }
 
@@ -415,13 +744,140 @@ impl<'a> Compiler<'a> {
         self.emit_byte(OpCode::OpPrint);
     }
 
-    fn expression_statement(&'a self) {
+    fn return_statement(&'a self) {
+        if *self.function_type.borrow() == FunctionType::Script {
+            self.error("Can't return from top-level code.");
+        }
+
+        if self.match_token(TokenType::Semicolon) {
+            self.emit_bytes(OpCode::OpNil, OpCode::OpReturn(0));
+        } else {
+            self.expression();
+            self.consume(TokenType::Semicolon, "Expect ';' after return value.");
+            self.emit_byte(OpCode::OpReturn(0));
+        }
+    }
+
+    fn break_statement(&'a self) {
+        self.consume(TokenType::Semicolon, "Expect ';' after 'break'.");
+
+        let (local_count, try_count) = match self.loops.borrow().last() {
+            Some(loop_context) => (loop_context.local_count, loop_context.try_count),
+            None => {
+                self.error("Can't use 'break' outside of a loop.");
+                return;
+            }
+        };
+
+        self.exit_loop_body(local_count, try_count);
+        let jump = self.emit_jump(OpCode::OpJump(None));
+        if let Some(loop_context) = self.loops.borrow_mut().last_mut() {
+            loop_context.break_jumps.push(jump);
+        }
+    }
+
+    fn continue_statement(&'a self) {
+        self.consume(TokenType::Semicolon, "Expect ';' after 'continue'.");
+
+        let (local_count, try_count, continue_target) = match self.loops.borrow().last() {
+            Some(loop_context) => (
+                loop_context.local_count,
+                loop_context.try_count,
+                loop_context.continue_target,
+            ),
+            None => {
+                self.error("Can't use 'continue' outside of a loop.");
+                return;
+            }
+        };
+
+        self.exit_loop_body(local_count, try_count);
+        match continue_target {
+            Some(continue_target) => self.emit_loop(continue_target),
+            None => {
+                let jump = self.emit_jump(OpCode::OpJump(None));
+                if let Some(loop_context) = self.loops.borrow_mut().last_mut() {
+                    loop_context.continue_jumps.push(jump);
+                }
+            }
+        }
+    }
+
+    ///
+    /// Pops the locals declared inside the loop body and leaves any try
+    /// blocks entered since, running their finally blocks on the way out.
+    ///
+    fn exit_loop_body(&'a self, local_count: usize, try_count: usize) {
+        let tries = self.tries.borrow()[try_count..].to_vec();
+        let mut locals = self.locals.borrow().size();
+
+        for try_locals in tries.into_iter().rev() {
+            self.emit_local_pops(locals, try_locals);
+            locals = try_locals;
+            self.emit_byte(OpCode::OpLeaveTry);
+        }
+
+        self.emit_local_pops(locals, local_count);
+    }
+
+    fn begin_loop(&'a self, continue_target: Option<usize>) {
+        let loop_context = LoopContext {
+            continue_target,
+            local_count: self.locals.borrow().size(),
+            try_count: self.tries.borrow().len(),
+            break_jumps: vec![],
+            continue_jumps: vec![],
+        };
+        self.loops.borrow_mut().push(loop_context);
+    }
+
+    /// Patches the loop's forward continue statements to jump to the current position.
+    fn patch_continues(&'a self) {
+        let continue_jumps = match self.loops.borrow_mut().last_mut() {
+            Some(loop_context) => std::mem::take(&mut loop_context.continue_jumps),
+            None => vec![],
+        };
+
+        for jump in continue_jumps {
+            self.patch_jump(jump, OpCode::OpJump(None));
+        }
+    }
+
+    /// Patches the loop's break statements to jump to the current position.
+    fn end_loop(&'a self) {
+        let loop_context = self
+            .loops
+            .borrow_mut()
+            .pop()
+            .expect("Error ending a loop that was never started.");
+
+        for jump in loop_context.break_jumps {
+            self.patch_jump(jump, OpCode::OpJump(None));
+        }
+    }
+
+    fn expression_statement(&'a self, in_body: bool) {
         self.expression();
+        // a function body's trailing expression, without a semicolon, is returned
+        if in_body && self.check_token(TokenType::RightBrace) {
+            self.emit_byte(OpCode::OpReturn(0));
+            return;
+        }
         self.consume(
             TokenType::Semicolon,
             "Expected ';' after expression statement.",
         );
-        self.emit_byte(OpCode::OpPop);
+
+        let is_top_level =
+            *self.scope_depth.borrow() == 0 && *self.function_type.borrow() == FunctionType::Script;
+
+        if *self.echo_expressions.borrow() && *self.scope_depth.borrow() == 0 {
+            self.emit_byte(OpCode::OpEcho);
+        } else if is_top_level && self.check_token(TokenType::EOF) {
+            // the script's last statement leaves its value for OpReturn
+        } else {
+            self.emit_byte(OpCode::OpPop);
+        }
     }
 
     fn for_statement(&'a self) {
@@ -434,10 +890,10 @@ impl<'a> Compiler<'a> {
         } else if self.match_token(TokenType::Var) {
             self.var_declaration();
         } else {
-            self.expression_statement();
+            self.expression_statement(false);
         }
 
-        let mut loop_start = self.chunk.borrow().count();
+        let mut loop_start = self.current_chunk().borrow().count();
 
         // compile conditional statement
         let mut exit_jump = None;
@@ -449,13 +905,18 @@ impl<'a> Compiler<'a> {
             self.emit_byte(OpCode::OpPop);
         }
 
-        // compile increment statement
+        // compile increment expressions, which are separated by commas
         if !self.match_token(TokenType::RightParen) {
             let body_jump = self.emit_jump(OpCode::OpJump(None));
-            let incr_start = self.chunk.borrow().count();
+            let incr_start = self.current_chunk().borrow().count();
 
-            self.expression();
-            self.emit_byte(OpCode::OpPop);
+            loop {
+                self.expression();
+                self.emit_byte(OpCode::OpPop);
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
             self.consume(TokenType::RightParen, "Expect ')' after for clauses.");
 
             self.emit_loop(loop_start);
@@ -463,7 +924,8 @@ impl<'a> Compiler<'a> {
             self.patch_jump(body_jump, OpCode::OpJump(None));
         }
 
-        self.statement();
+        self.begin_loop(Some(loop_start));
+        self.statement(false);
         self.emit_loop(loop_start);
 
         // compile code to quit for loop early when condition is false
@@ -471,24 +933,53 @@ impl<'a> Compiler<'a> {
             self.patch_jump(exit_jump_offset, OpCode::OpJumpIfFalse(None));
             self.emit_byte(OpCode::OpPop);
         }
+        self.end_loop();
 
         self.end_scope();
     }
 
     fn while_statement(&'a self) {
-        let loop_start = self.chunk.borrow().count();
+        let loop_start = self.current_chunk().borrow().count();
 
         self.consume(TokenType::LeftParen, "Expect '(' after 'while'.");
         self.expression();
         self.consume(TokenType::RightParen, "Expect ')' after condition.");
 
         let exit_jump = self.emit_jump(OpCode::OpJumpIfFalse(None));
-        self.emit_jump(OpCode::OpPop);
-        self.statement();
+        self.emit_byte(OpCode::OpPop);
+        self.begin_loop(Some(loop_start));
+        self.statement(false);
         self.emit_loop(loop_start);
 
         self.patch_jump(exit_jump, OpCode::OpJumpIfFalse(None));
         self.emit_byte(OpCode::OpPop);
+        self.end_loop();
+    }
+
+    fn do_while_statement(&'a self) {
+        let loop_start = self.current_chunk().borrow().count();
+
+        // the body always runs once before the condition is checked
+        self.begin_loop(None);
+        self.consume(TokenType::LeftBrace, "Expect '{' after 'do'.");
+        self.begin_scope();
+        self.block();
+        self.end_scope();
+        self.patch_continues();
+
+        self.consume(TokenType::While, "Expect 'while' after do-while body.");
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.");
+        self.expression();
+        self.consume(TokenType::RightParen, "Expect ')' after condition.");
+        self.consume(TokenType::Semicolon, "Expect ';' after do-while condition.");
+
+        let exit_jump = self.emit_jump(OpCode::OpJumpIfFalse(None));
+        self.emit_byte(OpCode::OpPop);
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump, OpCode::OpJumpIfFalse(None));
+        self.emit_byte(OpCode::OpPop);
+        self.end_loop();
     }
 
     fn if_statement(&'a self) {
@@ -498,7 +989,7 @@ impl<'a> Compiler<'a> {
 
         let then_jump = self.emit_jump(OpCode::OpJumpIfFalse(None));
         self.emit_byte(OpCode::OpPop);
-        self.statement();
+        self.statement(false);
 
         let else_jump = self.emit_jump(OpCode::OpJump(None));
 
@@ -506,32 +997,164 @@ impl<'a> Compiler<'a> {
         self.emit_byte(OpCode::OpPop);
 
         if self.match_token(TokenType::Else) {
-            self.statement();
+            self.statement(false);
         }
         self.patch_jump(else_jump, OpCode::OpJump(None));
     }
 
+    fn try_statement(&'a self) {
+        let handler = self.emit_jump(OpCode::OpPushHandler(None, None));
+
+        self.consume(TokenType::LeftBrace, "Expect '{' after 'try'.");
+        let try_locals = self.locals.borrow().size();
+        self.tries.borrow_mut().push(try_locals);
+        self.begin_scope();
+        self.block();
+        self.end_scope();
+
+        self.emit_byte(OpCode::OpPopHandler);
+        let end_jump = self.emit_jump(OpCode::OpJump(None));
+
+        let has_catch = self.match_token(TokenType::Catch);
+        if has_catch {
+            self.catch_clause(handler);
+        }
+        self.patch_jump(end_jump, OpCode::OpJump(None));
+        self.tries.borrow_mut().pop();
+
+        if self.match_token(TokenType::Finally) {
+            self.finally_clause(handler);
+        } else if !has_catch {
+            self.error_at_current_token("Expect 'catch' or 'finally' after try block.");
+        }
+    }
+
+    fn catch_clause(&'a self, handler: usize) {
+        // the VM jumps here with the error value pushed on top of the stack,
+        // leaving the handler installed so a finally block still runs
+        self.patch_handler(handler, false);
+        self.consume(TokenType::LeftParen, "Expect '(' after 'catch'.");
+        self.consume(
+            TokenType::Identifier(Rc::new(RoxString::new(""))),
+            "Expect error variable name.",
+        );
+
+        // bind the error value as a local in the scope of the catch block
+        self.begin_scope();
+        self.add_local(
+            self.previous
+                .borrow()
+                .expect("Error borrowing previous token for catch variable."),
+            false,
+        );
+        self.consume(TokenType::RightParen, "Expect ')' after catch variable.");
+        self.consume(TokenType::LeftBrace, "Expect '{' before catch body.");
+        self.block();
+        self.end_scope();
+
+        self.emit_byte(OpCode::OpPopHandler);
+    }
+
+    fn finally_clause(&'a self, handler: usize) {
+        // a normal exit from the try construct has nothing left pending
+        self.emit_bytes(OpCode::OpNil, OpCode::OpNil);
+
+        // the VM jumps here with the value and kind of the pending
+        // throw or return on top of the stack
+        self.patch_handler(handler, true);
+        self.begin_scope();
+        let depth = *self.scope_depth.borrow();
+        self.locals.borrow_mut().add_unnamed_local(depth);
+        self.locals.borrow_mut().add_unnamed_local(depth);
+
+        self.consume(TokenType::LeftBrace, "Expect '{' after 'finally'.");
+        self.begin_scope();
+        self.block();
+        self.end_scope();
+
+        // the pending value and kind are popped by the VM, not by the scope
+        self.emit_byte(OpCode::OpEndFinally);
+        *self.scope_depth.borrow_mut() -= 1;
+        self.locals
+            .borrow_mut()
+            .remove_locals(*self.scope_depth.borrow());
+    }
+
+    fn throw_statement(&'a self) {
+        self.expression();
+        self.consume(TokenType::Semicolon, "Expect ';' after thrown value.");
+        self.emit_byte(OpCode::OpThrow);
+    }
+
     fn emit_jump(&'a self, instruction: OpCode) -> usize {
         self.emit_byte(instruction);
-        self.chunk.borrow().count() - 1
+        self.current_chunk().borrow().count() - 1
     }
 
+    ///
+    /// Patches the unpatched jump of the given kind at offset to jump to
+    /// the current position. Anything else at offset is a compiler bug.
+    ///
     fn patch_jump(&'a self, offset: usize, opcode: OpCode) {
-        let jump = self.chunk.borrow().count() - offset - 1;
+        let chunk = self.current_chunk();
+        let count = chunk.borrow().count();
+        let jump = count.checked_sub(offset + 1).unwrap_or_else(|| {
+            panic!(
+                "Internal compiler error: jump at offset {} is past the end of the chunk.",
+                offset
+            )
+        });
+        if jump > u16::MAX.into() {
+            self.error("Too much code to jump over.");
+        }
+        self.jump_target.replace(Some(count));
 
         // patch in the jump offset from the jump opcode to past the then clause
-        match opcode {
-            OpCode::OpJumpIfFalse(_) => {
-                self.chunk.borrow_mut().code[offset] = OpCode::OpJumpIfFalse(Some(jump))
+        let found = chunk.borrow().code[offset];
+        let patched = match (found, opcode) {
+            (OpCode::OpJumpIfFalse(None), OpCode::OpJumpIfFalse(_)) => {
+                OpCode::OpJumpIfFalse(Some(jump))
+            }
+            (OpCode::OpJump(None), OpCode::OpJump(_)) => OpCode::OpJump(Some(jump)),
+            _ => panic!(
+                "Internal compiler error: expected an unpatched {} at offset {} but found {}.",
+                opcode, offset, found
+            ),
+        };
+        chunk.borrow_mut().code[offset] = patched;
+    }
+
+    /// Patches the catch or finally jump of a handler to the current position.
+    fn patch_handler(&'a self, offset: usize, finally: bool) {
+        let jump = self.current_chunk().borrow().count() - offset - 1;
+        self.jump_target
+            .replace(Some(self.current_chunk().borrow().count()));
+
+        if let OpCode::OpPushHandler(catch_jump, finally_jump) =
+            &mut self.current_chunk().borrow_mut().code[offset]
+        {
+            match finally {
+                true => *finally_jump = Some(jump),
+                false => *catch_jump = Some(jump),
             }
-            OpCode::OpJump(_) => self.chunk.borrow_mut().code[offset] = OpCode::OpJump(Some(jump)),
-            _ => (),
         }
     }
 
+    ///
+    /// Compiles the statements of a function body like a block, except
+    /// that a trailing expression without a semicolon is returned.
+    ///
+    fn function_body(&'a self) {
+        while !self.check_token(TokenType::RightBrace) && !self.check_token(TokenType::EOF) {
+            self.declaration(true);
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after block.");
+    }
+
     fn block(&'a self) {
         while !self.check_token(TokenType::RightBrace) && !self.check_token(TokenType::EOF) {
-            self.declaration();
+            self.declaration(false);
         }
 
         self.consume(TokenType::RightBrace, "Expect '}' after block.");
@@ -545,11 +1168,55 @@ impl<'a> Compiler<'a> {
         *self.scope_depth.borrow_mut() -= 1;
         let scope_depth = *self.scope_depth.borrow();
 
+        let local_count = self.locals.borrow().size();
         let num_removed = self.locals.borrow_mut().remove_locals(scope_depth);
+        self.emit_local_pops(local_count, local_count - num_removed);
+    }
 
-        for _ in 0..num_removed {
-            self.emit_byte(OpCode::OpPop);
+    ///
+    /// Pops the locals in the slots from local_count down to new_count,
+    /// closing over the captured ones so closures keep their values.
+    ///
+    fn emit_local_pops(&self, local_count: usize, new_count: usize) {
+        let mut pops = 0;
+        for slot in (new_count..local_count).rev() {
+            if self.locals.borrow().is_captured(slot) {
+                self.emit_pops(pops);
+                pops = 0;
+                self.emit_byte(OpCode::OpCloseUpvalue);
+            } else {
+                pops += 1;
+            }
         }
+        self.emit_pops(pops);
+    }
+
+    /// Pops count values, with a single instruction when there are several.
+    fn emit_pops(&self, count: usize) {
+        match count {
+            0 => (),
+            1 => self.emit_byte(OpCode::OpPop),
+            _ => self.emit_byte(OpCode::OpPopN(count)),
+        }
+    }
+
+    fn conditional(&'a self, _can_assign: bool) {
+        // the branches are parsed at assignment precedence so they can assign
+        let then_jump = self.emit_jump(OpCode::OpJumpIfFalse(None));
+        self.emit_byte(OpCode::OpPop);
+        self.parse(&Precedence::PrecAssign);
+
+        let else_jump = self.emit_jump(OpCode::OpJump(None));
+        self.consume(
+            TokenType::Colon,
+            "Expect ':' after then branch of conditional expression.",
+        );
+
+        self.patch_jump(then_jump, OpCode::OpJumpIfFalse(None));
+        self.emit_byte(OpCode::OpPop);
+        // parsing at or below the same precedence makes conditionals right-associative
+        self.parse(&Precedence::PrecAssign);
+        self.patch_jump(else_jump, OpCode::OpJump(None));
     }
 
     fn and_(&'a self, _can_assign: bool) {
@@ -580,7 +1247,15 @@ impl<'a> Compiler<'a> {
     /// emit_byte since the Chunk already has a convenience
     /// function for such a task.
     fn emit_constant(&self, value: Value, line: usize) {
-        self.chunk.borrow_mut().add_constant(value, line);
+        self.current_chunk().borrow_mut().add_constant(value, line);
+        self.check_constant_count();
+    }
+
+    /// Reports an error once the current chunk holds more constants than allowed.
+    fn check_constant_count(&self) {
+        if self.current_chunk().borrow().constants.values.len() > *self.max_constants.borrow() {
+            self.error("Too many constants in one chunk.");
+        }
     }
 
     fn emit_identifier_constant(
@@ -590,50 +1265,400 @@ impl<'a> Compiler<'a> {
         variable_op: VariableOp,
     ) -> usize {
         // need to write string to constants array in chunk
-        self.chunk
-            .borrow_mut()
-            .add_identifier_constant(string_value, line, variable_op)
+        let index = self.current_chunk().borrow_mut().add_identifier_constant(
+            string_value,
+            line,
+            variable_op,
+        );
+        self.check_constant_count();
+        self.tracer.compiler(format_args!(
+            "Added id {} at index {} to values",
+            string_value, index
+        ));
+
+        index
+    }
+
+    fn call(&'a self, _can_assign: bool) {
+        let callee = self.direct_callee();
+        let arg_count = self.argument_list();
+
+        // indirect calls are left for the VM to check
+        if let Some(function) = callee {
+            if !function.accepts(arg_count) {
+                self.error(&function.arity_error(arg_count));
+            }
+        }
+        self.emit_byte(OpCode::OpCall(arg_count));
+    }
+
+    ///
+    /// Finds the function being called when it is a global function
+    /// read by name immediately before the call.
+    ///
+    fn direct_callee(&'a self) -> Option<Rc<RoxFunction>> {
+        let (offset, name) = self.global_callee.replace(None)?;
+        let chunk = self.current_chunk();
+        let chunk = chunk.borrow();
+        if offset + 1 != chunk.count() || !matches!(chunk.code[offset], OpCode::OpGetGlobal(_)) {
+            return None;
+        }
+
+        self.functions.borrow().get(&name).cloned()
+    }
+
+    fn dot(&'a self, _can_assign: bool) {
+        self.consume(
+            TokenType::Identifier(Rc::new(RoxString::new(""))),
+            "Expect property name after '.'.",
+        );
+
+        let previous = self
+            .previous
+            .borrow()
+            .expect("Error borrowing previous token for property name.");
+        if let TokenType::Identifier(name) = &previous.token_type {
+            let index = self.emit_identifier_constant(name, previous.line, VariableOp::Define);
+            self.emit_byte(OpCode::OpGetProperty(index));
+        }
+    }
+
+    fn array(&'a self, _can_assign: bool) {
+        let mut element_count = 0;
+        if !self.check_token(TokenType::RightBracket) {
+            loop {
+                self.expression();
+                element_count += 1;
+
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightBracket, "Expect ']' after array elements.");
+
+        self.emit_byte(OpCode::OpBuildArray(element_count));
+    }
+
+    fn index(&'a self, can_assign: bool) {
+        self.expression();
+        self.consume(TokenType::RightBracket, "Expect ']' after index.");
+
+        if can_assign && self.match_token(TokenType::Equal) {
+            self.expression();
+            self.emit_byte(OpCode::OpIndexSet);
+        } else {
+            self.emit_byte(OpCode::OpIndexGet);
+        }
+    }
+
+    fn argument_list(&'a self) -> usize {
+        let mut arg_count = 0;
+        if !self.check_token(TokenType::RightParen) {
+            loop {
+                self.expression();
+                if arg_count == 255 {
+                    self.error("Can't have more than 255 arguments.");
+                }
+                arg_count += 1;
+
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after arguments.");
+
+        arg_count
+    }
+
+    fn anonymous_function(&'a self, _can_assign: bool) {
+        self.function(RoxString::new(ANONYMOUS_NAME));
+
+        // the function value isn't a global that calls could check against
+        self.global_callee.replace(None);
     }
 
     fn grouping(&'a self, _can_assign: bool) {
         self.expression();
         self.consume(TokenType::RightParen, "Expect ')' after expression.");
+
+        // a parenthesized expression may end in a global read that isn't the callee
+        self.global_callee.replace(None);
     }
 
     fn string(&'a self, string: &Rc<RoxString>, line: usize, _can_assign: bool) {
-        let new_rox_object =
-            RoxObject::new(ObjectType::ObjString(RoxString::new(&Rc::clone(string))));
+        let interned = self.current_chunk().borrow().intern((**string).clone());
+        let new_rox_object = RoxObject::new(ObjectType::ObjString(interned));
         self.emit_constant(Value::Object(new_rox_object), line);
     }
 
     fn variable(&'a self, id: &Rc<RoxString>, line: usize, can_assign: bool) {
-        let (is_initialized, is_local_id) = self.locals.borrow().resolve_local(id);
+        // __line__ is a read-only builtin resolved by the VM at runtime
+        if &***id == LINE_BUILTIN {
+            if can_assign && self.match_token(TokenType::Equal) {
+                self.error("Cannot assign to __line__.");
+            }
+            self.emit_byte(OpCode::OpPushLine);
+            return;
+        }
+
+        let (is_initialized, is_local_id, is_const) = self.locals.borrow().resolve_local(id);
 
         if !is_initialized {
             self.error("Can't read local variable in its own initializer.");
         }
 
-        // locals live on the stack at runtime
-        if let Some(local_idx) = is_local_id {
+        if let Some(operator) = self.match_increment() {
+            self.emit_increment(id, line, operator, false);
+            return;
+        }
+
+        // locals live on the stack at runtime and captured variables in upvalues
+        let local_ops = match is_local_id {
+            Some(local_idx) => {
+                self.tracer
+                    .compiler(format_args!("Resolving local variable {}", id));
+                Some((
+                    OpCode::OpGetLocal(local_idx),
+                    OpCode::OpSetLocal(local_idx),
+                    is_const,
+                ))
+            }
+            None => self.resolve_upvalue(id).map(|(index, is_const)| {
+                (
+                    OpCode::OpGetUpvalue(index),
+                    OpCode::OpSetUpvalue(index),
+                    is_const,
+                )
+            }),
+        };
+
+        if let Some((get_op, set_op, is_const)) = local_ops {
             if can_assign && self.match_token(TokenType::Equal) {
+                self.check_assignable(id, Some(is_const));
                 self.expression();
-                self.emit_byte(OpCode::OpSetLocal(local_idx));
+                self.emit_byte(set_op);
+            } else if let Some(operator) = self.match_compound_assignment(can_assign) {
+                self.check_assignable(id, Some(is_const));
+                self.emit_byte(get_op);
+                self.expression();
+                self.emit_byte(operator);
+                self.emit_byte(set_op);
             } else {
-                self.emit_byte(OpCode::OpGetLocal(local_idx));
+                self.emit_byte(get_op);
             }
         } else {
             // globals live in globals list
             if can_assign && self.match_token(TokenType::Equal) {
+                self.check_assignable(id, None);
+                self.expression();
+                self.emit_identifier_constant(id, line, VariableOp::SetGlobal);
+                // the name may no longer hold the function declared with it
+                self.functions.borrow_mut().remove(RoxString::clone(id));
+            } else if let Some(operator) = self.match_compound_assignment(can_assign) {
+                self.check_assignable(id, None);
+                self.emit_identifier_constant(id, line, VariableOp::GetGlobal);
                 self.expression();
-                self.chunk
-                    .borrow_mut()
-                    .add_identifier_constant(id, line, VariableOp::SetGlobal);
+                self.emit_byte(operator);
+                self.emit_identifier_constant(id, line, VariableOp::SetGlobal);
+                self.functions.borrow_mut().remove(RoxString::clone(id));
             } else {
-                self.chunk
-                    .borrow_mut()
-                    .add_identifier_constant(id, line, VariableOp::GetGlobal);
+                self.emit_identifier_constant(id, line, VariableOp::GetGlobal);
+                let offset = self.current_chunk().borrow().count() - 1;
+                self.global_callee
+                    .replace(Some((offset, RoxString::clone(id))));
+            }
+        }
+    }
+
+    ///
+    /// Resolves id to a variable declared in an enclosing function,
+    /// adding an upvalue for it to each function in between. Returns
+    /// the upvalue's index and whether the variable is const.
+    ///
+    fn resolve_upvalue(&self, id: &RoxString) -> Option<(usize, bool)> {
+        let level = self.enclosing.borrow().len();
+        self.resolve_upvalue_at(level, id)
+    }
+
+    /// Resolves id as an upvalue of the function nested level deep, where the script is 0.
+    fn resolve_upvalue_at(&self, level: usize, id: &RoxString) -> Option<(usize, bool)> {
+        // the script has no enclosing function to capture from
+        if level == 0 {
+            return None;
+        }
+
+        let local = {
+            let mut enclosing = self.enclosing.borrow_mut();
+            let locals = &mut enclosing[level - 1].locals;
+            match locals.resolve_local(id) {
+                (_, Some(slot), is_const) => {
+                    locals.capture(slot);
+                    Some((slot, is_const))
+                }
+                _ => None,
+            }
+        };
+
+        let (index, is_local, is_const) = match local {
+            Some((slot, is_const)) => (slot, true, is_const),
+            None => {
+                let (index, is_const) = self.resolve_upvalue_at(level - 1, id)?;
+                (index, false, is_const)
+            }
+        };
+
+        Some((
+            self.add_upvalue(level, Upvalue { index, is_local }),
+            is_const,
+        ))
+    }
+
+    /// Adds an upvalue to the function nested level deep, reusing an identical one.
+    fn add_upvalue(&self, level: usize, upvalue: Upvalue) -> usize {
+        let mut enclosing = self.enclosing.borrow_mut();
+        let mut current = self.upvalues.borrow_mut();
+        let upvalues = if level == enclosing.len() {
+            &mut *current
+        } else {
+            &mut enclosing[level].upvalues
+        };
+
+        match upvalues.iter().position(|existing| *existing == upvalue) {
+            Some(index) => index,
+            None => {
+                upvalues.push(upvalue);
+                upvalues.len() - 1
+            }
+        }
+    }
+
+    fn prefix_increment(&'a self, _can_assign: bool) {
+        let operator = match self
+            .previous
+            .borrow()
+            .expect("Error borrowing previous token in prefix increment")
+            .token_type
+        {
+            TokenType::PlusPlus => OpCode::OpAdd,
+            _ => OpCode::OpSubtract,
+        };
+
+        // only variables can be incremented
+        if !self.match_token(TokenType::Identifier(Rc::new(RoxString::new("")))) {
+            self.error("Expect variable name after increment operator.");
+            return;
+        }
+
+        let previous = self
+            .previous
+            .borrow()
+            .expect("Error borrowing previous token for increment target.");
+        if let TokenType::Identifier(id) = &previous.token_type {
+            self.emit_increment(id, previous.line, operator, true);
+        }
+    }
+
+    /// Consumes a postfix `++` or `--` and returns the opcode it applies.
+    fn match_increment(&self) -> Option<OpCode> {
+        if self.match_token(TokenType::PlusPlus) {
+            Some(OpCode::OpAdd)
+        } else if self.match_token(TokenType::MinusMinus) {
+            Some(OpCode::OpSubtract)
+        } else {
+            None
+        }
+    }
+
+    ///
+    /// Adds or subtracts one from a variable and stores it back. A prefix
+    /// increment leaves the new value on the stack and a postfix one the old.
+    ///
+    fn emit_increment(&self, id: &Rc<RoxString>, line: usize, operator: OpCode, prefix: bool) {
+        if &***id == LINE_BUILTIN {
+            self.error("Cannot assign to __line__.");
+            return;
+        }
+
+        let (_, local_idx, is_const) = self.locals.borrow().resolve_local(id);
+        let upvalue = match local_idx {
+            Some(_) => None,
+            None => self.resolve_upvalue(id),
+        };
+
+        let (get_op, set_op) = match (local_idx, upvalue) {
+            (Some(local_idx), _) => {
+                self.check_assignable(id, Some(is_const));
+                (OpCode::OpGetLocal(local_idx), OpCode::OpSetLocal(local_idx))
+            }
+            (None, Some((index, is_const))) => {
+                self.check_assignable(id, Some(is_const));
+                (OpCode::OpGetUpvalue(index), OpCode::OpSetUpvalue(index))
             }
+            (None, None) => {
+                self.check_assignable(id, None);
+                let index = self.emit_identifier_constant(id, line, VariableOp::Define);
+                self.functions.borrow_mut().remove(RoxString::clone(id));
+                (OpCode::OpGetGlobal(index), OpCode::OpSetGlobal(index))
+            }
+        };
+
+        self.emit_byte(get_op);
+        if !prefix {
+            // keep the old value beneath the one being stored
+            self.emit_byte(get_op);
         }
+        self.emit_constant(Value::Number(RoxNumber(1.0)), line);
+        self.emit_byte(operator);
+        self.emit_byte(set_op);
+        if !prefix {
+            self.emit_byte(OpCode::OpPop);
+        }
+    }
+
+    ///
+    /// Reports an error if the variable being assigned was declared const,
+    /// using the constness of the local or upvalue it resolved to, or
+    /// else that of the global.
+    ///
+    fn check_assignable(&self, id: &RoxString, resolved_const: Option<bool>) {
+        let is_const = match resolved_const {
+            Some(is_const) => is_const,
+            None => self.const_globals.borrow().contains(RoxString::clone(id)),
+        };
+
+        if is_const {
+            self.error(&format!("Cannot assign to const variable '{}'", id));
+        }
+    }
+
+    ///
+    /// Consumes a compound assignment operator such as `+=` and returns
+    /// the arithmetic opcode it applies before assigning.
+    ///
+    fn match_compound_assignment(&self, can_assign: bool) -> Option<OpCode> {
+        if !can_assign {
+            return None;
+        }
+
+        let operator = match self
+            .current
+            .borrow()
+            .expect("Error borrowing current token in compound assignment")
+            .token_type
+        {
+            TokenType::PlusEqual => OpCode::OpAdd,
+            TokenType::MinusEqual => OpCode::OpSubtract,
+            TokenType::StarEqual => OpCode::OpMultiply,
+            TokenType::SlashEqual => OpCode::OpDivide,
+            TokenType::PercentEqual => OpCode::OpModulo,
+            TokenType::StarStarEqual => OpCode::OpPower,
+            _ => return None,
+        };
+        self.advance();
+
+        Some(operator)
     }
 
     fn literal(&'a self, _can_assign: bool) {
@@ -658,12 +1683,22 @@ impl<'a> Compiler<'a> {
             .expect("Error borrowing previous token in unary");
 
         // compile operand
+        let operand_start = self.current_chunk().borrow().count();
         self.parse(&Precedence::PrecUnary);
 
         // emit operator opcode
         match operator_type.token_type {
             TokenType::Minus => self.emit_byte(OpCode::OpNegate),
             TokenType::Bang => self.emit_byte(OpCode::OpNot),
+            TokenType::Plus => {
+                // a number literal needs no check
+                let chunk = self.current_chunk();
+                let is_number_literal = chunk.borrow().count() == operand_start + 1
+                    && chunk.borrow().number_constant_at(operand_start).is_some();
+                if !is_number_literal {
+                    self.emit_byte(OpCode::OpUnaryPlus);
+                }
+            }
             _ => panic!(
                 "Error parsing unary expression. Unexpected token type: {}",
                 operator_type
@@ -681,16 +1716,31 @@ impl<'a> Compiler<'a> {
         let rule = self.get_rule(operator_type);
 
         // parse rule with next highest precedence (term -> factor, factor -> unary)
-        self.parse(rule.precedence.get_next());
+        // except for right-associative operators, which parse at the same precedence
+        let lhs_end = self.current_chunk().borrow().count();
+        if rule.associativity == Associativity::Right {
+            self.parse(&rule.precedence);
+        } else {
+            self.parse(rule.precedence.get_next());
+        }
 
         // emit opcode for token type
         match operator_type.token_type {
-            TokenType::Plus => self.emit_byte(OpCode::OpAdd),
-            TokenType::Minus => self.emit_byte(OpCode::OpSubtract),
-            TokenType::Star => self.emit_byte(OpCode::OpMultiply),
-            TokenType::Slash => self.emit_byte(OpCode::OpDivide),
+            TokenType::Plus => self.emit_arithmetic(OpCode::OpAdd, lhs_end),
+            TokenType::Minus => self.emit_arithmetic(OpCode::OpSubtract, lhs_end),
+            TokenType::Star => self.emit_arithmetic(OpCode::OpMultiply, lhs_end),
+            TokenType::Slash => self.emit_arithmetic(OpCode::OpDivide, lhs_end),
+            TokenType::Percent => self.emit_arithmetic(OpCode::OpModulo, lhs_end),
+            TokenType::SlashSlash => self.emit_arithmetic(OpCode::OpFloorDiv, lhs_end),
+            TokenType::StarStar => self.emit_arithmetic(OpCode::OpPower, lhs_end),
+            TokenType::Ampersand => self.emit_byte(OpCode::OpBitAnd),
+            TokenType::Pipe => self.emit_byte(OpCode::OpBitOr),
+            TokenType::Caret => self.emit_byte(OpCode::OpBitXor),
+            TokenType::LessLess => self.emit_byte(OpCode::OpShl),
+            TokenType::GreaterGreater => self.emit_byte(OpCode::OpShr),
             TokenType::BangEqual => self.emit_bytes(OpCode::OpEqual, OpCode::OpNot),
             TokenType::EqualEqual => self.emit_byte(OpCode::OpEqual),
+            TokenType::EqualEqualEqual => self.emit_byte(OpCode::OpStrictEqual),
             TokenType::Greater => self.emit_byte(OpCode::OpGreater),
             TokenType::GreaterEqual => self.emit_bytes(OpCode::OpLess, OpCode::OpNot), // (a >= b) == !(a < b)
             TokenType::Less => self.emit_byte(OpCode::OpLess),
@@ -702,8 +1752,52 @@ impl<'a> Compiler<'a> {
         }
     }
 
+    ///
+    /// Emits an arithmetic operator, or folds it into a single constant
+    /// when both operands are number literals pushed just before it. A
+    /// jump landing between the operands means the left one might not be
+    /// the whole left-hand expression, as in `(a and 1) + 2`. Division by
+    /// zero is left to raise its error at runtime.
+    ///
+    fn emit_arithmetic(&self, operator: OpCode, lhs_end: usize) {
+        let chunk = self.current_chunk();
+        let folded = match (
+            lhs_end
+                .checked_sub(1)
+                .and_then(|lhs| chunk.borrow().number_constant_at(lhs)),
+            chunk.borrow().number_constant_at(lhs_end),
+        ) {
+            (Some(a), Some(b))
+                if chunk.borrow().count() == lhs_end + 1
+                    && *self.jump_target.borrow() != Some(lhs_end) =>
+            {
+                match operator {
+                    OpCode::OpAdd => Some(a + b),
+                    OpCode::OpSubtract => Some(a - b),
+                    OpCode::OpMultiply => Some(a * b),
+                    OpCode::OpDivide if b.0 != 0.0 => Some(a / b),
+                    OpCode::OpModulo if b.0 != 0.0 => Some(a % b),
+                    OpCode::OpFloorDiv if b.0 != 0.0 => Some(RoxNumber((a.0 / b.0).floor())),
+                    OpCode::OpPower => Some(RoxNumber(a.0.powf(b.0))),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+
+        match folded {
+            Some(result) => {
+                let line = chunk.borrow().line_at(lhs_end - 1);
+                chunk.borrow_mut().pop_constant();
+                chunk.borrow_mut().pop_constant();
+                self.emit_constant(Value::Number(result), line);
+            }
+            None => self.emit_byte(operator),
+        }
+    }
+
     fn emit_loop(&self, loop_start: usize) {
-        let offset = self.chunk.borrow().count() - loop_start + 1;
+        let offset = self.current_chunk().borrow().count() - loop_start + 1;
         if offset > u16::MAX.into() {
             self.error("Loop body too large");
         }
@@ -722,10 +1816,14 @@ impl<'a> Compiler<'a> {
             .borrow()
             .expect("Error borrowing previous token in emit byte")
             .line;
-        self.chunk.borrow_mut().write_chunk(byte, line);
+        self.current_chunk().borrow_mut().write_chunk(byte, line);
     }
 
     fn emit_return(&self) {
+        // functions without a return statement return nil
+        if *self.function_type.borrow() == FunctionType::Function {
+            self.emit_byte(OpCode::OpNil);
+        }
         self.emit_byte(OpCode::OpReturn(0));
     }
 
@@ -748,13 +1846,8 @@ impl<'a> Compiler<'a> {
         // call prefix parsing function if present
         if let Some(p_fn) = prefix_fn {
             p_fn(can_assign);
-        } else if self.previous.borrow().unwrap().token_type == TokenType::EOF {
-            return;
         } else {
-            self.error(&format!(
-                "No prefix function parsed for precedence {}.",
-                precedence
-            ));
+            self.error("Expect expression.");
             return;
         }
 
@@ -784,28 +1877,36 @@ impl<'a> Compiler<'a> {
         }
     }
 
-    fn parse_variable(&'a self, msg: &str) -> usize {
-        // TODO -- how to make parse variable work here without consuming blank ID?
-        self.consume(TokenType::Identifier(Rc::new(RoxString::new(""))), msg);
+    fn parse_variable(&'a self, msg: &str, is_const: bool) -> usize {
+        // tokens compare by type, so any identifier matches the blank one
+        if !self.match_token(TokenType::Identifier(Rc::new(RoxString::new("")))) {
+            self.error_at_current_token(msg);
+            // the index is never used, since the error fails the compile
+            return 0;
+        }
 
         let previous = self
             .previous
             .borrow()
             .expect("Error borrowing previous token when parsing variable.");
-        let previous_token_value = match &previous.token_type {
-            TokenType::Identifier(str) => str,
-            _ => panic!(
-                "Error did not find identifier when parsing previous token for variable {}",
-                previous
-            ),
+        let TokenType::Identifier(previous_token_value) = &previous.token_type else {
+            unreachable!("match_token only matches identifiers here");
         };
 
-        self.declare_variable();
+        self.declare_variable(is_const);
         // don't add a local and a global below
         if *self.scope_depth.borrow() > 0 {
             return 0;
         }
 
+        // a redeclared global is re-registered if it is a function or const
+        self.functions
+            .borrow_mut()
+            .remove(RoxString::clone(previous_token_value));
+        self.const_globals
+            .borrow_mut()
+            .remove(RoxString::clone(previous_token_value));
+
         self.emit_identifier_constant(previous_token_value, previous.line, VariableOp::Define)
     }
 
@@ -815,7 +1916,7 @@ impl<'a> Compiler<'a> {
 
         // parse sequence of declarations and statements
         while !self.match_token(TokenType::EOF) {
-            self.declaration();
+            self.declaration(false);
         }
 
         // emit final byte code
@@ -823,4 +1924,265 @@ impl<'a> Compiler<'a> {
 
         !*self.had_error.borrow()
     }
+
+    ///
+    /// Compiles source made of exactly one expression, whose value the
+    /// script returns. Any tokens after the expression are an error.
+    ///
+    pub fn compile_expression(&'a self) -> bool {
+        self.advance();
+        self.expression();
+        if !self.check_token(TokenType::EOF) {
+            self.error_at_current_token("Expect end of expression.");
+        }
+        self.end_compiler();
+
+        !*self.had_error.borrow()
+    }
+
+    ///
+    /// The errors reported while compiling, in the order they were
+    /// found. Only the first error after each synchronization point
+    /// is recorded.
+    ///
+    pub fn errors(&self) -> Vec<CompileError> {
+        self.errors.borrow().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ObjectList, Scanner};
+
+    #[test]
+    fn test_end_scope_pops_locals_at_once() {
+        let tokens = Scanner::new()
+            .scan_tokens("{ var a = 1; var b = 2; var c = 3; var d = 4; var e = 5; }");
+        let chunk = Rc::new(RefCell::new(Chunk::new(
+            Rc::new(RefCell::new(ObjectList::new())),
+            Rc::new(RefCell::new(Table::new())),
+            Rc::new(RefCell::new(Table::new())),
+        )));
+        let compiler = Compiler::new(
+            Rc::clone(&chunk),
+            RefCell::new(tokens.iter().peekable()),
+            Tracer::default(),
+        );
+
+        assert!(compiler.compile());
+        // five constants, one pop for all five locals and the return,
+        // where popping the locals one by one took eleven instructions
+        let code = &chunk.borrow().code;
+        assert_eq!(code.len(), 7);
+        assert!(matches!(code[5], OpCode::OpPopN(5)));
+    }
+
+    #[test]
+    fn test_parse_table_output() {
+        let tokens = Scanner::new().scan_tokens(
+            "var a; var b; var c; var d; var e; var f; var g; var h = [1]; \
+             a = -a + b * c ** 2 ** d < 3 and !e or f(1, 2) // 2 ? g.x : h[0] & 1 | 2 ^ 3 << 1; \
+             fun (x) { return x; }(++a);",
+        );
+        let chunk = Rc::new(RefCell::new(Chunk::new(
+            Rc::new(RefCell::new(ObjectList::new())),
+            Rc::new(RefCell::new(Table::new())),
+            Rc::new(RefCell::new(Table::new())),
+        )));
+        let compiler = Compiler::new(
+            Rc::clone(&chunk),
+            RefCell::new(tokens.iter().peekable()),
+            Tracer::default(),
+        );
+
+        assert!(compiler.compile());
+        // recorded from the match-based get_rule before it became a table
+        let ops = chunk
+            .borrow()
+            .code
+            .iter()
+            .map(|op| format!("{:?}", op))
+            .collect::<Vec<_>>();
+        let expected = [
+            "OpNil",
+            "OpDefineGlobal(0)",
+            "OpNil",
+            "OpDefineGlobal(1)",
+            "OpNil",
+            "OpDefineGlobal(2)",
+            "OpNil",
+            "OpDefineGlobal(3)",
+            "OpNil",
+            "OpDefineGlobal(4)",
+            "OpNil",
+            "OpDefineGlobal(5)",
+            "OpNil",
+            "OpDefineGlobal(6)",
+            "OpConstant(8)",
+            "OpBuildArray(1)",
+            "OpDefineGlobal(7)",
+            "OpGetGlobal(0)",
+            "OpNegate",
+            "OpGetGlobal(1)",
+            "OpGetGlobal(2)",
+            "OpConstant(9)",
+            "OpGetGlobal(3)",
+            "OpPower",
+            "OpPower",
+            "OpMultiply",
+            "OpAdd",
+            "OpConstant(10)",
+            "OpLess",
+            "OpJumpIfFalse(Some(3))",
+            "OpPop",
+            "OpGetGlobal(4)",
+            "OpNot",
+            "OpJumpIfFalse(Some(1))",
+            "OpJump(Some(7))",
+            "OpPop",
+            "OpGetGlobal(5)",
+            "OpConstant(11)",
+            "OpConstant(12)",
+            "OpCall(2)",
+            "OpConstant(13)",
+            "OpFloorDiv",
+            "OpJumpIfFalse(Some(4))",
+            "OpPop",
+            "OpGetGlobal(6)",
+            "OpGetProperty(14)",
+            "OpJump(Some(12))",
+            "OpPop",
+            "OpGetGlobal(7)",
+            "OpConstant(15)",
+            "OpIndexGet",
+            "OpConstant(16)",
+            "OpBitAnd",
+            "OpConstant(17)",
+            "OpConstant(18)",
+            "OpConstant(19)",
+            "OpShl",
+            "OpBitXor",
+            "OpBitOr",
+            "OpSetGlobal(0)",
+            "OpPop",
+            "OpConstant(20)",
+            "OpGetGlobal(0)",
+            "OpConstant(21)",
+            "OpAdd",
+            "OpSetGlobal(0)",
+            "OpCall(1)",
+            "OpReturn(0)",
+        ];
+        assert_eq!(ops, expected);
+    }
+
+    #[test]
+    fn test_too_many_constants() {
+        let tokens = Scanner::new().scan_tokens("print 1; print 2; print 3; var four = 4;");
+        let chunk = Rc::new(RefCell::new(Chunk::new(
+            Rc::new(RefCell::new(ObjectList::new())),
+            Rc::new(RefCell::new(Table::new())),
+            Rc::new(RefCell::new(Table::new())),
+        )));
+        let compiler = Compiler::new(
+            Rc::clone(&chunk),
+            RefCell::new(tokens.iter().peekable()),
+            Tracer::default(),
+        );
+        compiler.max_constants.replace(3);
+
+        assert!(!compiler.compile());
+        let errors = compiler.errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Too many constants in one chunk.");
+        assert_eq!(errors[0].line, 1);
+    }
+
+    #[test]
+    fn test_token_without_parse_rule_is_a_compile_error() {
+        // neither token has a parse rule, which used to panic
+        let tokens = Scanner::new().scan_tokens("var x = var;\nprint };");
+        let chunk = Rc::new(RefCell::new(Chunk::new(
+            Rc::new(RefCell::new(ObjectList::new())),
+            Rc::new(RefCell::new(Table::new())),
+            Rc::new(RefCell::new(Table::new())),
+        )));
+        let compiler = Compiler::new(
+            Rc::clone(&chunk),
+            RefCell::new(tokens.iter().peekable()),
+            Tracer::default(),
+        );
+
+        assert!(!compiler.compile());
+        let errors = compiler.errors();
+        assert_eq!(errors.len(), 2);
+        assert_eq!((errors[0].line, errors[1].line), (1, 2));
+        assert!(errors
+            .iter()
+            .all(|error| error.message == "Expect expression."));
+    }
+
+    #[test]
+    fn test_match_token_without_current_token() {
+        let tokens = Scanner::new().scan_tokens(";");
+        let chunk = Rc::new(RefCell::new(Chunk::new(
+            Rc::new(RefCell::new(ObjectList::new())),
+            Rc::new(RefCell::new(Table::new())),
+            Rc::new(RefCell::new(Table::new())),
+        )));
+        let compiler = Compiler::new(
+            Rc::clone(&chunk),
+            RefCell::new(tokens.iter().peekable()),
+            Tracer::default(),
+        );
+
+        // nothing has been advanced into current yet
+        assert!(!compiler.match_token(TokenType::Semicolon));
+        assert!(!compiler.check_token(TokenType::EOF));
+    }
+
+    #[test]
+    #[should_panic(expected = "expected an unpatched OP_JUMP at offset 0 but found OP_CONSTANT")]
+    fn test_patching_a_non_jump_panics() {
+        let tokens = Scanner::new().scan_tokens("1;");
+        let chunk = Rc::new(RefCell::new(Chunk::new(
+            Rc::new(RefCell::new(ObjectList::new())),
+            Rc::new(RefCell::new(Table::new())),
+            Rc::new(RefCell::new(Table::new())),
+        )));
+        let compiler = Compiler::new(
+            chunk,
+            RefCell::new(tokens.iter().peekable()),
+            Tracer::default(),
+        );
+
+        assert!(compiler.compile());
+        compiler.patch_jump(0, OpCode::OpJump(None));
+    }
+
+    #[test]
+    fn test_errors_are_collected() {
+        let tokens = Scanner::new().scan_tokens("print 1");
+        let chunk = Rc::new(RefCell::new(Chunk::new(
+            Rc::new(RefCell::new(ObjectList::new())),
+            Rc::new(RefCell::new(Table::new())),
+            Rc::new(RefCell::new(Table::new())),
+        )));
+        let compiler = Compiler::new(
+            chunk,
+            RefCell::new(tokens.iter().peekable()),
+            Tracer::default(),
+        );
+
+        assert!(!compiler.compile());
+        assert_eq!(
+            compiler.errors(),
+            vec![CompileError {
+                line: 2,
+                column: 1,
+                message: "Expected ';' after value.".to_string(),
+            }]
+        );
+    }
 }
@@ -1,17 +1,169 @@
+use crate::Value;
+
 pub type InterpretResult = std::result::Result<InterpretOk, InterpretError>;
 
-#[derive(PartialEq, Eq)]
-pub struct InterpretOk;
+///
+/// The value the top-level script returned, which is that of its
+/// trailing expression statement or nil when it doesn't end with one.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterpretOk(pub Value);
 
 #[derive(Debug, Clone)]
 pub enum InterpretError {
     CompileError(String),
-    RuntimeError(String),
+    RuntimeError(RuntimeError),
+    Thrown(Value),
+}
+
+///
+/// A diagnostic reported by the compiler at the position of the
+/// token where the problem was found.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+///
+/// An error that stops execution of compiled code. The VM sets the
+/// line to that of the faulting instruction when the error is raised
+/// without one, and records the calls that were active when it
+/// escaped the script, innermost first.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeError {
+    pub message: String,
+    pub line: Option<usize>,
+    pub trace: Vec<String>,
+}
+
+impl RuntimeError {
+    pub fn new(message: &str) -> RuntimeError {
+        RuntimeError {
+            message: message.to_string(),
+            line: None,
+            trace: vec![],
+        }
+    }
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "[line {}]: {}", line, self.message)?,
+            None => write!(f, "{}", self.message)?,
+        }
+        for frame in &self.trace {
+            write!(f, "\n{}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+///
+/// A reason a byte buffer couldn't be read back into a Chunk.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChunkError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    UnexpectedEnd,
+    TrailingBytes,
+    InvalidOpcode(u8),
+    InvalidConstant(u8),
+    InvalidString,
+    Io(std::io::ErrorKind),
+}
+
+impl std::fmt::Display for ChunkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChunkError::BadMagic => write!(f, "Buffer is not a serialized chunk"),
+            ChunkError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported chunk format version {}", version)
+            }
+            ChunkError::UnexpectedEnd => write!(f, "Serialized chunk ended unexpectedly"),
+            ChunkError::TrailingBytes => write!(f, "Unexpected bytes after serialized chunk"),
+            ChunkError::InvalidOpcode(tag) => write!(f, "Invalid opcode tag {}", tag),
+            ChunkError::InvalidConstant(tag) => write!(f, "Invalid constant tag {}", tag),
+            ChunkError::InvalidString => write!(f, "Serialized string is not valid UTF-8"),
+            ChunkError::Io(kind) => write!(f, "Error reading serialized chunk: {}", kind),
+        }
+    }
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Error at [{}, {}] with message: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+///
+/// The message for a call with the wrong number of arguments to a
+/// function whose last optional parameters may be left off.
+///
+pub fn arity_message(arity: usize, optional: usize, arg_count: usize) -> String {
+    match optional {
+        0 => format!("Expected {} arguments but got {}.", arity, arg_count),
+        optional => format!(
+            "Expected {} to {} arguments but got {}.",
+            arity - optional,
+            arity,
+            arg_count
+        ),
+    }
+}
+
+impl InterpretError {
+    ///
+    /// Sets the line of a runtime error that was raised without one.
+    /// Other errors are returned unchanged.
+    ///
+    pub fn at_line(self, line: usize) -> InterpretError {
+        match self {
+            InterpretError::RuntimeError(RuntimeError {
+                message,
+                line: None,
+                trace,
+            }) => InterpretError::RuntimeError(RuntimeError {
+                message,
+                line: Some(line),
+                trace,
+            }),
+            _ => self,
+        }
+    }
+
+    ///
+    /// Sets the call trace of a runtime error that doesn't have one yet.
+    /// Other errors are returned unchanged.
+    ///
+    pub fn with_trace(self, trace: Vec<String>) -> InterpretError {
+        match self {
+            InterpretError::RuntimeError(error) if error.trace.is_empty() => {
+                InterpretError::RuntimeError(RuntimeError { trace, ..error })
+            }
+            _ => self,
+        }
+    }
 }
 
 impl From<&str> for InterpretError {
     fn from(msg: &str) -> Self {
-        InterpretError::RuntimeError(msg.to_string())
+        InterpretError::RuntimeError(RuntimeError::new(msg))
+    }
+}
+
+impl From<RuntimeError> for InterpretError {
+    fn from(error: RuntimeError) -> Self {
+        InterpretError::RuntimeError(error)
     }
 }
 
@@ -19,7 +171,10 @@ impl std::fmt::Display for InterpretError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             InterpretError::CompileError(message) => write!(f, "{}", message),
-            InterpretError::RuntimeError(message) => write!(f, "{}", message),
+            InterpretError::RuntimeError(error) => write!(f, "{}", error),
+            InterpretError::Thrown(value) => {
+                write!(f, "Uncaught exception: {}", value.print_repr())
+            }
         }
     }
 }
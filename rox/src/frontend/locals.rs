@@ -1,4 +1,4 @@
-use crate::{RoxString, Token, TokenType, DEBUG_MODE};
+use crate::{RoxString, Token, TokenType};
 
 use super::LOCALS_COUNT;
 
@@ -6,13 +6,18 @@ use super::LOCALS_COUNT;
 pub struct Local {
     pub name: Option<Token>,
     pub depth: Option<usize>,
+    pub is_const: bool,
+    // captured locals are moved off the stack when their scope ends
+    pub is_captured: bool,
 }
 
 impl Local {
-    pub fn new(name: &Token, depth: usize) -> Local {
+    pub fn new(name: &Token, depth: usize, is_const: bool) -> Local {
         Local {
             name: Some(name.clone()),
             depth: Some(depth),
+            is_const,
+            is_captured: false,
         }
     }
 }
@@ -32,17 +37,49 @@ impl Locals {
         self.count
     }
 
+    ///
+    /// Claims the first stack slot of a function's frame, which
+    /// holds the function being called and can't be named.
+    ///
+    pub fn reserve_slot(&mut self) {
+        self.add_unnamed_local(0);
+    }
+
+    ///
+    /// Adds a local for a stack slot the VM uses internally, which
+    /// is removed with its scope like a named local but can't be
+    /// resolved by name.
+    ///
+    pub fn add_unnamed_local(&mut self, depth: usize) {
+        self.locals[self.count] = Local {
+            name: None,
+            depth: Some(depth),
+            is_const: false,
+            is_captured: false,
+        };
+        self.count += 1;
+    }
+
     pub fn initialize_variable(&mut self, scope_depth: usize) {
         self.locals[self.count - 1].depth = Some(scope_depth);
     }
 
-    pub fn add_local(&mut self, token: &Token, depth: usize) {
-        self.locals[self.count] = Local::new(token, depth);
+    pub fn add_local(&mut self, token: &Token, depth: usize, is_const: bool) {
+        self.locals[self.count] = Local::new(token, depth, is_const);
         self.count += 1;
+    }
 
-        if DEBUG_MODE {
-            println!("Added local variable at index {}", self.count - 1);
-        }
+    /// Marks the local in slot as captured by a closure.
+    pub fn capture(&mut self, slot: usize) {
+        self.locals[slot].is_captured = true;
+    }
+
+    ///
+    /// Whether the local in slot was captured by a closure. Slots stay
+    /// readable after their locals are removed, until they're reused.
+    ///
+    pub fn is_captured(&self, slot: usize) -> bool {
+        self.locals[slot].is_captured
     }
 
     pub fn remove_locals(&mut self, scope_depth: usize) -> usize {
@@ -81,10 +118,6 @@ impl Locals {
                     _ => panic!("Looking for string not an identifier!"),
                 };
 
-                if DEBUG_MODE {
-                    println!("Comparing {local_str} to {looking_for_str}.");
-                }
-
                 if *local_str == *looking_for_str {
                     return true;
                 }
@@ -94,24 +127,74 @@ impl Locals {
         false
     }
 
-    pub fn resolve_local(&self, local_id: &RoxString) -> (bool, Option<usize>) {
+    ///
+    /// Finds the innermost local named local_id, returning whether it
+    /// is initialized, its slot if one was found, and whether it was
+    /// declared const.
+    ///
+    pub fn resolve_local(&self, local_id: &RoxString) -> (bool, Option<usize>, bool) {
         for idx in (0..self.count).rev() {
             let local = &self.locals[idx];
             if let Some(token) = &local.name {
                 if let TokenType::Identifier(string) = &token.token_type {
                     if **string == *local_id {
-                        if DEBUG_MODE {
-                            println!("Resolving local variable {}", local_id);
-                        }
                         if local.depth.is_none() {
-                            return (false, None);
+                            return (false, None, local.is_const);
                         }
 
-                        return (true, Some(idx));
+                        return (true, Some(idx), local.is_const);
                     }
                 }
             }
         }
-        (true, None)
+        (true, None, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    fn identifier(name: &str) -> Token {
+        Token::new(TokenType::Identifier(Rc::new(RoxString::new(name))), 1, 1)
+    }
+
+    #[test]
+    fn test_resolve_local_reports_constness() {
+        let mut locals = Locals::new();
+        locals.add_local(&identifier("a"), 1, false);
+        locals.add_local(&identifier("b"), 1, true);
+
+        assert_eq!(
+            locals.resolve_local(&RoxString::new("a")),
+            (true, Some(0), false)
+        );
+        assert_eq!(
+            locals.resolve_local(&RoxString::new("b")),
+            (true, Some(1), true)
+        );
+        assert_eq!(
+            locals.resolve_local(&RoxString::new("c")),
+            (true, None, false)
+        );
+    }
+
+    #[test]
+    fn test_shadowing_local_has_its_own_constness() {
+        let mut locals = Locals::new();
+        locals.add_local(&identifier("a"), 1, true);
+        locals.add_local(&identifier("a"), 2, false);
+
+        assert_eq!(
+            locals.resolve_local(&RoxString::new("a")),
+            (true, Some(1), false)
+        );
+
+        locals.remove_locals(1);
+        assert_eq!(
+            locals.resolve_local(&RoxString::new("a")),
+            (true, Some(0), true)
+        );
     }
 }
@@ -1,6 +1,5 @@
 mod locals;
 
-pub use locals::Local;
 pub use locals::Locals;
 
 pub const LOCALS_COUNT: usize = 256;
@@ -0,0 +1,86 @@
+use crate::{RoxMap, RoxString, Table, Value};
+
+///
+/// The VM's global variables. Each name is given a slot the first time
+/// it's defined and keeps it for the life of the VM, since globals are
+/// never removed, so a slot looked up once can be reused without
+/// hashing the name again. Redefining a global overwrites its slot.
+///
+#[derive(Debug)]
+pub struct Globals {
+    slots: Table<RoxString, usize>,
+    values: Vec<Value>,
+}
+
+impl Globals {
+    pub fn new() -> Globals {
+        Globals {
+            slots: Table::new(),
+            values: vec![],
+        }
+    }
+
+    /// The slot of a defined global.
+    pub fn slot(&self, name: &RoxString) -> Option<usize> {
+        self.slots.get(name).copied()
+    }
+
+    pub fn get(&self, name: &RoxString) -> Option<&Value> {
+        self.slot(name).map(|slot| &self.values[slot])
+    }
+
+    pub fn get_slot(&self, slot: usize) -> &Value {
+        &self.values[slot]
+    }
+
+    pub fn contains(&self, name: &RoxString) -> bool {
+        self.slots.contains(name.clone())
+    }
+
+    /// Defines or redefines a global, returning its slot.
+    pub fn define(&mut self, name: &RoxString, value: Value) -> usize {
+        match self.slot(name) {
+            Some(slot) => {
+                self.values[slot] = value;
+                slot
+            }
+            None => {
+                let slot = self.values.len();
+                self.values.push(value);
+                self.slots.set(name, &slot);
+                slot
+            }
+        }
+    }
+
+    pub fn set_slot(&mut self, slot: usize, value: Value) {
+        self.values[slot] = value;
+    }
+}
+
+impl Default for Globals {
+    fn default() -> Self {
+        Globals::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RoxNumber;
+
+    #[test]
+    fn redefining_keeps_the_slot() {
+        let mut globals = Globals::new();
+        let a = RoxString::new("a");
+        let slot = globals.define(&a, Value::Nil);
+        globals.define(&RoxString::new("b"), Value::Nil);
+
+        assert_eq!(globals.define(&a, Value::Boolean(true)), slot);
+        assert_eq!(globals.get(&a), Some(&Value::Boolean(true)));
+
+        globals.set_slot(slot, Value::Number(RoxNumber(1.0)));
+        assert_eq!(globals.get_slot(slot), &Value::Number(RoxNumber(1.0)));
+        assert!(!globals.contains(&RoxString::new("c")));
+    }
+}
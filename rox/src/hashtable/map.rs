@@ -11,4 +11,7 @@ pub trait RoxMap<K = Rc<RoxString>, V = Value> {
     fn contains(&self, key: K) -> bool;
 
     fn remove(&mut self, key: K) -> Option<V>;
+
+    /// Removes the key from the map, returning whether it was present.
+    fn delete(&mut self, key: &K) -> bool;
 }
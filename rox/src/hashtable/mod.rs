@@ -3,6 +3,6 @@ mod map;
 //mod raw_table;
 mod table;
 
-pub use entry::Entry;
 pub use map::RoxMap;
+pub use table::Iter as TableIter;
 pub use table::StdTable as Table;
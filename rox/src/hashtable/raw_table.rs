@@ -11,7 +11,7 @@ use std::ops::Deref;
 use std::ptr::{self, NonNull};
 
 const INITIAL_TABLE_CAPACITY: usize = 8;
-static LOAD_FACTOR: f32 = 0.5;
+static LOAD_FACTOR: f32 = 0.75;
 
 pub trait RoxMap<K = RoxString, V = Value, E = Entry> {
     fn get(&self, key: &K) -> Option<E>;
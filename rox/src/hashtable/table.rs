@@ -1,9 +1,10 @@
 use crate::hashtable::entry::Entry;
 use crate::hashtable::map::RoxMap;
-use crate::DEBUG_MODE;
+use std::collections::hash_map::Values;
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::hash::Hash;
+use std::slice;
 
 impl<K, V> RoxMap<K, V> for StdTable<K, V>
 where
@@ -18,9 +19,14 @@ where
     }
 
     fn set(&mut self, key: &K, value: &V) -> bool {
-        self.inner_table
+        let existed = self
+            .inner_table
             .insert(key.clone(), Entry::new_full(key, value))
-            .is_some()
+            .is_some();
+        if let (false, Some(order)) = (existed, self.insertion_order.as_mut()) {
+            order.push(key.clone());
+        }
+        existed
     }
 
     /// Sets the value at key if it already exists
@@ -28,11 +34,7 @@ where
     /// and does not set a new key-value pair.
     fn get_and_set(&mut self, key: &K, value: &V) -> bool {
         match self.inner_table.get(key) {
-            Some(s) => {
-                if DEBUG_MODE {
-                    println!("Overwriting string {:?}", s);
-                }
-
+            Some(_) => {
                 self.set(key, value);
                 true
             }
@@ -42,7 +44,10 @@ where
 
     fn remove(&mut self, key: K) -> Option<V> {
         match self.inner_table.remove(&key) {
-            Some(entry) => Some(entry.value),
+            Some(entry) => {
+                self.forget_order(&key);
+                Some(entry.value)
+            }
             _ => None,
         }
     }
@@ -50,20 +55,205 @@ where
     fn contains(&self, key: K) -> bool {
         self.inner_table.contains_key(&key)
     }
+
+    /// The HashMap marks deleted buckets with tombstones itself, so
+    /// probing for the remaining keys is unaffected by a delete.
+    fn delete(&mut self, key: &K) -> bool {
+        let deleted = self.inner_table.remove(key).is_some();
+        if deleted {
+            self.forget_order(key);
+        }
+        deleted
+    }
 }
 
+///
+/// A table backed by the standard library's HashMap, which grows its
+/// backing storage and rehashes existing entries once it reaches its
+/// maximum load factor, so capacity never has to be managed here.
+///
 #[derive(Debug, Default)]
 pub struct StdTable<K, V> {
     inner_table: HashMap<K, Entry<K, V>>,
+    // the keys in the order they were first set, when iteration keeps it
+    insertion_order: Option<Vec<K>>,
 }
 
 impl<K, V> StdTable<K, V> {
     pub fn new() -> StdTable<K, V> {
         let inner_table: HashMap<K, Entry<K, V>> = HashMap::new();
-        StdTable { inner_table }
+        StdTable {
+            inner_table,
+            insertion_order: None,
+        }
+    }
+
+    ///
+    /// Creates a table that iterates over its entries in the order their
+    /// keys were first set, so output built from it is deterministic.
+    /// Setting an existing key again keeps its place.
+    ///
+    pub fn with_insertion_order() -> StdTable<K, V> {
+        StdTable {
+            inner_table: HashMap::new(),
+            insertion_order: Some(vec![]),
+        }
     }
 
     pub fn reset(&mut self) {
         self.inner_table.drain();
+        if let Some(order) = self.insertion_order.as_mut() {
+            order.clear();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner_table.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner_table.is_empty()
+    }
+
+    ///
+    /// Iterates over every entry in the table, in insertion order for a
+    /// table created with_insertion_order and arbitrary order otherwise.
+    ///
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let entries = match &self.insertion_order {
+            Some(order) => Entries::Inserted(order.iter(), &self.inner_table),
+            None => Entries::Arbitrary(self.inner_table.values()),
+        };
+        Iter { entries }
+    }
+}
+
+impl<K: Eq, V> StdTable<K, V> {
+    fn forget_order(&mut self, key: &K) {
+        if let Some(order) = self.insertion_order.as_mut() {
+            order.retain(|ordered| ordered != key);
+        }
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    entries: Entries<'a, K, V>,
+}
+
+enum Entries<'a, K, V> {
+    Arbitrary(Values<'a, K, Entry<K, V>>),
+    Inserted(slice::Iter<'a, K>, &'a HashMap<K, Entry<K, V>>),
+}
+
+impl<'a, K: Hash + Eq, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = match &mut self.entries {
+            Entries::Arbitrary(entries) => entries.next(),
+            Entries::Inserted(keys, table) => keys.next().and_then(|key| table.get(key)),
+        };
+        entry.map(|entry| (&entry.key, &entry.value))
+    }
+}
+
+impl<'a, K: Hash + Eq, V> IntoIterator for &'a StdTable<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RoxNumber, RoxString, Value};
+
+    #[test]
+    fn test_delete_key() {
+        let mut table: StdTable<RoxString, Value> = StdTable::new();
+        let keys = ["first", "second", "third"].map(RoxString::new);
+        for (idx, key) in keys.iter().enumerate() {
+            table.set(key, &Value::Number(RoxNumber(idx as f32)));
+        }
+
+        assert!(table.delete(&keys[1]));
+        assert!(!table.delete(&keys[1]));
+        assert_eq!(table.get(&keys[1]), None);
+        assert_eq!(table.get(&keys[0]), Some(&Value::Number(RoxNumber(0.0))));
+        assert_eq!(table.get(&keys[2]), Some(&Value::Number(RoxNumber(2.0))));
+
+        table.set(&keys[1], &Value::Boolean(true));
+        assert_eq!(table.get(&keys[1]), Some(&Value::Boolean(true)));
+        assert_eq!(table.len(), 3);
+    }
+
+    #[test]
+    fn test_iter_visits_every_entry() {
+        let mut table: StdTable<RoxString, usize> = StdTable::new();
+        for (idx, key) in ["a", "b", "c", "d"].into_iter().enumerate() {
+            table.set(&RoxString::new(key), &idx);
+        }
+        table.delete(&RoxString::new("c"));
+
+        let mut entries: Vec<(String, usize)> = table
+            .iter()
+            .map(|(key, value)| (key.to_string(), *value))
+            .collect();
+        entries.sort();
+
+        assert_eq!(
+            entries,
+            vec![
+                ("a".to_string(), 0),
+                ("b".to_string(), 1),
+                ("d".to_string(), 3)
+            ]
+        );
+        assert_eq!((&table).into_iter().count(), 3);
+    }
+
+    #[test]
+    fn test_insertion_order_iteration() {
+        let mut table: StdTable<RoxString, usize> = StdTable::with_insertion_order();
+        for (idx, key) in ["d", "a", "c", "b", "e"].into_iter().enumerate() {
+            table.set(&RoxString::new(key), &idx);
+        }
+        table.delete(&RoxString::new("c"));
+        table.set(&RoxString::new("a"), &10);
+        table.set(&RoxString::new("c"), &11);
+
+        let entries: Vec<(String, usize)> = table
+            .iter()
+            .map(|(key, value)| (key.to_string(), *value))
+            .collect();
+
+        assert_eq!(
+            entries,
+            vec![
+                ("d".to_string(), 0),
+                ("a".to_string(), 10),
+                ("b".to_string(), 3),
+                ("e".to_string(), 4),
+                ("c".to_string(), 11),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_many_keys_survive_resizing() {
+        let mut table: StdTable<RoxString, Value> = StdTable::new();
+        for idx in 0..10_000 {
+            let key = RoxString::new(&format!("key{}", idx));
+            assert!(!table.set(&key, &Value::Number(RoxNumber(idx as f32))));
+        }
+
+        assert_eq!(table.len(), 10_000);
+        for idx in 0..10_000 {
+            let key = RoxString::new(&format!("key{}", idx));
+            assert_eq!(table.get(&key), Some(&Value::Number(RoxNumber(idx as f32))));
+        }
     }
 }
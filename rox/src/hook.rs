@@ -0,0 +1,32 @@
+use crate::Value;
+
+///
+/// Events reported to an instrumentation hook registered on the VM.
+/// Events only carry copies of VM state so that a hook can never
+/// hold a borrow into the running VM.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookEvent {
+    LineChange { line: usize },
+    FunctionEnter { name: String },
+    FunctionExit { name: String },
+    RuntimeError { message: String, line: usize },
+}
+
+pub type Hook = Box<dyn FnMut(&HookEvent)>;
+
+/// A callback given each value a print statement prints, before it is formatted.
+pub type PrintCallback = Box<dyn FnMut(&Value)>;
+
+impl std::fmt::Display for HookEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HookEvent::LineChange { line } => write!(f, "<line change: {}>", line),
+            HookEvent::FunctionEnter { name } => write!(f, "<function enter: {}>", name),
+            HookEvent::FunctionExit { name } => write!(f, "<function exit: {}>", name),
+            HookEvent::RuntimeError { message, line } => {
+                write!(f, "<runtime error at line {}: {}>", line, message)
+            }
+        }
+    }
+}
@@ -2,7 +2,10 @@ mod chunk;
 mod compiler;
 mod error;
 mod frontend;
+mod globals;
 mod hashtable;
+mod hook;
+mod native;
 mod object;
 mod object_list;
 mod opcode;
@@ -10,7 +13,9 @@ mod precedence;
 mod raw_stack;
 mod run;
 mod scanner;
+mod serialize;
 mod token;
+mod trace;
 mod types;
 mod value;
 mod vm;
@@ -18,8 +23,11 @@ mod vm;
 pub use chunk::*;
 pub use compiler::*;
 pub use error::*;
+pub use globals::Globals;
 pub use hashtable::RoxMap;
 pub use hashtable::Table;
+pub use hashtable::TableIter;
+pub use hook::*;
 pub use object::*;
 pub use object_list::ObjectList;
 pub use opcode::OpCode;
@@ -27,10 +35,18 @@ pub use precedence::Precedence;
 pub use raw_stack::RawStack as Stack;
 pub use run::*;
 pub use scanner::Scanner;
+pub use serialize::CHUNK_FORMAT_VERSION;
 pub use token::*;
+pub use trace::*;
 pub use types::*;
 pub use value::*;
 pub use vm::*;
 
-pub static DEBUG_MODE: bool = true;
+/// Initial capacity of the VM stack, which grows past it as needed.
 pub const STACK_MAX: usize = 256;
+
+/// Most constants a chunk may hold unless the compiler is given another cap.
+pub const MAX_CONSTANTS: usize = 1 << 24;
+
+/// Deepest nesting of calls before the VM reports a stack overflow.
+pub const FRAMES_MAX: usize = 256;
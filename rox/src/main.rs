@@ -1,20 +1,30 @@
-use rox::Config;
+use rox::{Config, RunOutcome};
 use std::env::args;
+use std::process::ExitCode;
 
-fn main() {
-    let mut config = Config::new(&mut args()).unwrap_or_else(|err| {
-        eprintln!(
-            "\n<<<Error with command line arguments>>>\n\nExiting with message:\n{}",
-            err
-        );
-        std::process::exit(1);
-    });
+fn main() -> ExitCode {
+    let mut config = match Config::new(&mut args()) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!(
+                "\n<<<Error with command line arguments>>>\n\nExiting with message:\n{}",
+                err
+            );
+            return err.outcome().into();
+        }
+    };
 
-    if config.is_repl {
-        config.repl();
+    let outcome = if let Some(filename) = config.filename.clone() {
+        match config.run_file_with_filename(&filename) {
+            Ok(()) => RunOutcome::Ok,
+            Err(msg) => {
+                eprintln!("\n<<<Error in Rox interpreter>>>\n\nMessage: {}", msg);
+                msg.outcome()
+            }
+        }
     } else {
-        config.run_file().unwrap_or_else(|msg| {
-            eprintln!("\n<<<Error in Rox interpreter>>>\n\nMessage: {}", msg);
-        });
-    }
+        config.repl();
+        RunOutcome::Ok
+    };
+    outcome.into()
 }
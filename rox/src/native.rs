@@ -0,0 +1,210 @@
+use crate::{
+    ObjectType, RoxError, RoxNumber, RoxObject, RoxString, RuntimeError, Value, MAX_REPEAT_LEN, VM,
+};
+use std::rc::Rc;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+static START: OnceLock<Instant> = OnceLock::new();
+
+///
+/// Returns the number of seconds elapsed since the clock was first
+/// read, for timing code from within Rox.
+///
+pub fn clock(_vm: &VM, _args: &[Value]) -> Result<Value, RuntimeError> {
+    let start = START.get_or_init(Instant::now);
+    Ok(Value::Number(RoxNumber(start.elapsed().as_secs_f32())))
+}
+
+///
+/// Creates an error object carrying the given message, which can
+/// be thrown and read back with `.message` in a catch block.
+///
+pub fn error(_vm: &VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    let message = match &args[0] {
+        Value::Object(RoxObject {
+            object_type: ObjectType::ObjString(message),
+            ..
+        }) => (**message).clone(),
+        other => RoxString::new(&other.to_string()),
+    };
+
+    Ok(Value::Object(RoxObject::new(ObjectType::ObjError(
+        RoxError::new(message),
+    ))))
+}
+
+///
+/// Returns the number of characters in a string or the number of
+/// elements in an array.
+///
+pub fn len(_vm: &VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    let length = match &args[0] {
+        Value::Object(RoxObject {
+            object_type: ObjectType::ObjArray(array),
+            ..
+        }) => array.len(),
+        Value::Object(RoxObject {
+            object_type: ObjectType::ObjString(string),
+            ..
+        }) => string.chars().count(),
+        _ => return Err(RuntimeError::new("len expects a string or an array.")),
+    };
+    Ok(Value::Number(RoxNumber(length as f32)))
+}
+
+///
+/// Returns the character at a 0-based index into a string as a
+/// string of length one.
+///
+pub fn char_at(_vm: &VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    let string = string_arg(&args[0], "char_at")?;
+    let index = match &args[1] {
+        Value::Number(RoxNumber(index)) if index.fract() == 0.0 => *index,
+        _ => return Err(RuntimeError::new("char_at index must be an integer.")),
+    };
+
+    let length = string.chars().count();
+    match string.chars().nth(index as usize) {
+        Some(c) if index >= 0.0 => Ok(Value::Object(RoxObject::new(ObjectType::ObjString(
+            Rc::new(RoxString::new(&c.to_string())),
+        )))),
+        _ => Err(RuntimeError::new(&format!(
+            "Index {} out of range for string of length {}.",
+            index, length
+        ))),
+    }
+}
+
+///
+/// Returns whether a global with the given name is defined, so
+/// scripts can check for one without raising an error.
+///
+pub fn defined(vm: &VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    let name = string_arg(&args[0], "defined")?;
+    Ok(Value::Boolean(vm.is_global_defined(name)))
+}
+
+///
+/// Returns the name of a value's type as a string, such as
+/// "number" or "nil".
+///
+pub fn type_of(_vm: &VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::Object(RoxObject::new(ObjectType::ObjString(
+        Rc::new(RoxString::new(args[0].type_name())),
+    ))))
+}
+
+///
+/// Raises a runtime error when the condition is falsey. The error
+/// carries the optional message, shown as print would show it.
+///
+pub fn assert(_vm: &VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    if !args[0].is_falsey() {
+        return Ok(Value::Nil);
+    }
+    match args.get(1) {
+        Some(message) => Err(RuntimeError::new(&format!(
+            "Assertion failed: {}",
+            message.print_repr()
+        ))),
+        None => Err(RuntimeError::new("Assertion failed.")),
+    }
+}
+
+///
+/// Reads a line from the VM's input, returning nil once the input
+/// is exhausted.
+///
+pub fn readline(vm: &VM, _args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(match vm.read_line()? {
+        Some(line) => Value::Object(RoxObject::new(ObjectType::ObjString(Rc::new(
+            RoxString::new(&line),
+        )))),
+        None => Value::Nil,
+    })
+}
+
+///
+/// Converts a string holding a number, such as a line read by
+/// readline, to that number. Returns nil when the string doesn't hold
+/// a finite number, and returns numbers unchanged.
+///
+pub fn num(_vm: &VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    if let Value::Number(number) = &args[0] {
+        return Ok(Value::Number(*number));
+    }
+    let string = string_arg(&args[0], "num")?;
+    Ok(match string.trim().parse::<f32>() {
+        Ok(number) if number.is_finite() => Value::Number(RoxNumber(number)),
+        _ => Value::Nil,
+    })
+}
+
+///
+/// Returns whether a number lies within an inclusive range, as an
+/// unambiguous alternative to chaining `lo <= x <= hi`.
+///
+pub fn between(_vm: &VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    match args {
+        [Value::Number(x), Value::Number(lo), Value::Number(hi)] => {
+            Ok(Value::Boolean(lo <= x && x <= hi))
+        }
+        _ => Err(RuntimeError::new("between expects number arguments.")),
+    }
+}
+
+///
+/// Returns a value as print would show it, as a string.
+///
+pub fn str(_vm: &VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::Object(RoxObject::new(ObjectType::ObjString(
+        Rc::new(RoxString::new(&args[0].print_repr())),
+    ))))
+}
+
+///
+/// Returns a value as str() would show it, padded with spaces to at
+/// least width characters. Numbers are right-aligned so columns of
+/// them line up on their last digit, and everything else is left-aligned.
+///
+pub fn pad(_vm: &VM, args: &[Value]) -> Result<Value, RuntimeError> {
+    let width = match &args[1] {
+        Value::Number(RoxNumber(width)) if *width > MAX_REPEAT_LEN as f32 => {
+            return Err(RuntimeError::new(&format!(
+                "pad width can't be more than {}.",
+                MAX_REPEAT_LEN
+            )))
+        }
+        Value::Number(RoxNumber(width)) if *width >= 0.0 && width.fract() == 0.0 => *width as usize,
+        _ => {
+            return Err(RuntimeError::new(
+                "pad width must be a whole, non-negative number.",
+            ))
+        }
+    };
+
+    // padded by hand, since format! widths are capped far below the limit
+    let text = args[0].print_repr();
+    let spaces = " ".repeat(width.saturating_sub(text.chars().count()));
+    let padded = match &args[0] {
+        Value::Number(_) => spaces + &text,
+        _ => text + &spaces,
+    };
+    Ok(Value::Object(RoxObject::new(ObjectType::ObjString(
+        Rc::new(RoxString::new(&padded)),
+    ))))
+}
+
+fn string_arg<'a>(arg: &'a Value, native: &str) -> Result<&'a RoxString, RuntimeError> {
+    match arg {
+        Value::Object(RoxObject {
+            object_type: ObjectType::ObjString(string),
+            ..
+        }) => Ok(string),
+        _ => Err(RuntimeError::new(&format!(
+            "{} expects a string argument.",
+            native
+        ))),
+    }
+}
@@ -1,6 +1,17 @@
+mod roxarray;
+mod roxclosure;
+mod roxerror;
+mod roxfunction;
+mod roxnative;
 mod roxstring;
 
-pub use crate::object::roxstring::RoxString;
+pub use crate::object::roxarray::RoxArray;
+pub use crate::object::roxclosure::{RoxClosure, RoxUpvalue};
+pub use crate::object::roxerror::RoxError;
+pub use crate::object::roxfunction::{RoxFunction, Upvalue};
+pub use crate::object::roxnative::{NativeFn, RoxNative};
+pub use crate::object::roxstring::{RoxString, RoxStringBuilder};
+use std::rc::Rc;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RoxObject {
@@ -23,15 +34,31 @@ impl std::fmt::Display for RoxObject {
     }
 }
 
+///
+/// The kinds of heap object. Strings and errors compare by contents,
+/// while functions, closures, natives and arrays compare by identity:
+/// each is equal only to itself, so values holding them are equal
+/// exactly when they refer to the same object.
+///
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ObjectType {
-    ObjString(RoxString),
+    ObjString(Rc<RoxString>),
+    ObjFunction(Rc<RoxFunction>),
+    ObjClosure(Rc<RoxClosure>),
+    ObjNative(Rc<RoxNative>),
+    ObjError(RoxError),
+    ObjArray(Rc<RoxArray>),
 }
 
 impl std::fmt::Display for ObjectType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ObjectType::ObjString(string) => write!(f, "str = {}", string),
+            ObjectType::ObjFunction(function) => write!(f, "fn = {}", function),
+            ObjectType::ObjClosure(closure) => write!(f, "fn = {}", closure),
+            ObjectType::ObjNative(native) => write!(f, "fn = {}", native),
+            ObjectType::ObjError(error) => write!(f, "error = {}", error),
+            ObjectType::ObjArray(array) => write!(f, "array = {}", array),
         }
     }
 }
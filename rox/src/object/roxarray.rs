@@ -0,0 +1,64 @@
+use crate::Value;
+use std::cell::RefCell;
+
+///
+/// A list of values built with an array literal. Arrays are shared
+/// by reference, so a write through one variable is seen through
+/// every other variable holding the same array.
+///
+#[derive(Debug, Default)]
+pub struct RoxArray {
+    elements: RefCell<Vec<Value>>,
+}
+
+impl RoxArray {
+    pub fn new(elements: Vec<Value>) -> RoxArray {
+        RoxArray {
+            elements: RefCell::new(elements),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.borrow().is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<Value> {
+        self.elements.borrow().get(index).cloned()
+    }
+
+    /// Replaces the element at index, returning false if it's out of range.
+    pub fn set(&self, index: usize, value: Value) -> bool {
+        match self.elements.borrow_mut().get_mut(index) {
+            Some(element) => {
+                *element = value;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// By identity, since arrays are mutable and shared; see ObjectType.
+impl PartialEq for RoxArray {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Eq for RoxArray {}
+
+impl std::fmt::Display for RoxArray {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let elements = self
+            .elements
+            .borrow()
+            .iter()
+            .map(|element| element.repr())
+            .collect::<Vec<_>>();
+        write!(f, "[{}]", elements.join(", "))
+    }
+}
@@ -0,0 +1,45 @@
+use crate::{RcMut, RoxFunction, Value};
+use std::rc::Rc;
+
+///
+/// A variable captured by a closure. It refers to the variable's
+/// stack slot while the variable is in scope, and holds the value
+/// itself once the variable's scope has ended.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoxUpvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+///
+/// A function together with the variables it captured from the
+/// functions enclosing it. Closures sharing a variable share the
+/// same upvalue, so writes through one are seen by the others.
+///
+#[derive(Debug)]
+pub struct RoxClosure {
+    pub function: Rc<RoxFunction>,
+    pub upvalues: Vec<RcMut<RoxUpvalue>>,
+}
+
+impl RoxClosure {
+    pub fn new(function: Rc<RoxFunction>, upvalues: Vec<RcMut<RoxUpvalue>>) -> RoxClosure {
+        RoxClosure { function, upvalues }
+    }
+}
+
+/// By identity, since closures of one function can capture different variables; see ObjectType.
+impl PartialEq for RoxClosure {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Eq for RoxClosure {}
+
+impl std::fmt::Display for RoxClosure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.function)
+    }
+}
@@ -0,0 +1,29 @@
+use crate::RoxString;
+
+///
+/// An error object created by the Error builtin. The line is filled
+/// in when the error is first thrown and kept when it is rethrown.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoxError {
+    pub message: RoxString,
+    pub line: Option<usize>,
+}
+
+impl RoxError {
+    pub fn new(message: RoxString) -> RoxError {
+        RoxError {
+            message,
+            line: None,
+        }
+    }
+}
+
+impl std::fmt::Display for RoxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "[line {}] Error: {}", line, self.message),
+            None => write!(f, "Error: {}", self.message),
+        }
+    }
+}
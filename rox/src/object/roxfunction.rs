@@ -0,0 +1,89 @@
+use crate::{arity_message, Chunk, RcMut, RoxString, Value};
+
+///
+/// A compiled Rox function. The body is compiled into its own
+/// chunk, which the VM executes in a fresh call frame.
+///
+#[derive(Debug)]
+pub struct RoxFunction {
+    pub name: RoxString,
+    pub arity: usize,
+    pub chunk: RcMut<Chunk>,
+    pub upvalues: Vec<Upvalue>,
+    /// Values for the trailing parameters a call may leave off.
+    pub defaults: Vec<Value>,
+    /// Whether the last parameter collects any surplus arguments into an array.
+    pub variadic: bool,
+}
+
+///
+/// Where a closure finds a variable it captures when it is created:
+/// a local slot of the enclosing function, or when is_local is false,
+/// one of the enclosing function's own upvalues.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Upvalue {
+    pub index: usize,
+    pub is_local: bool,
+}
+
+impl RoxFunction {
+    pub fn new(name: RoxString, arity: usize, chunk: RcMut<Chunk>) -> RoxFunction {
+        RoxFunction::with_upvalues(name, arity, chunk, vec![])
+    }
+
+    /// Creates a function that captures the given variables when it's wrapped in a closure.
+    pub fn with_upvalues(
+        name: RoxString,
+        arity: usize,
+        chunk: RcMut<Chunk>,
+        upvalues: Vec<Upvalue>,
+    ) -> RoxFunction {
+        RoxFunction {
+            name,
+            arity,
+            chunk,
+            upvalues,
+            defaults: vec![],
+            variadic: false,
+        }
+    }
+
+    /// The fewest arguments a call may pass.
+    pub fn min_arity(&self) -> usize {
+        self.arity - self.defaults.len() - self.variadic as usize
+    }
+
+    /// Whether a call may pass this many arguments.
+    pub fn accepts(&self, arg_count: usize) -> bool {
+        arg_count >= self.min_arity() && (self.variadic || arg_count <= self.arity)
+    }
+
+    /// The error for a call passing a number of arguments this function doesn't accept.
+    pub fn arity_error(&self, arg_count: usize) -> String {
+        if self.variadic {
+            format!(
+                "Expected at least {} arguments but got {}.",
+                self.min_arity(),
+                arg_count
+            )
+        } else {
+            arity_message(self.arity, self.defaults.len(), arg_count)
+        }
+    }
+}
+
+/// By identity, since identical declarations still make distinct functions; see ObjectType.
+impl PartialEq for RoxFunction {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Eq for RoxFunction {}
+
+impl std::fmt::Display for RoxFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<fn {}>", self.name)
+    }
+}
@@ -0,0 +1,63 @@
+use crate::{RoxString, RuntimeError, Value, VM};
+
+/// The signature of a Rust function callable from Rox code, which is
+/// given the calling VM. An error stops execution like any other
+/// runtime error.
+pub type NativeFn = fn(&VM, &[Value]) -> Result<Value, RuntimeError>;
+
+///
+/// A built-in function implemented in Rust. Natives are called
+/// directly by the VM without pushing a call frame.
+///
+#[derive(Debug)]
+pub struct RoxNative {
+    pub name: RoxString,
+    pub arity: usize,
+    /// How many arguments may be left off the end of a call.
+    pub optional: usize,
+    pub function: NativeFn,
+}
+
+impl RoxNative {
+    pub fn new(name: RoxString, arity: usize, function: NativeFn) -> RoxNative {
+        RoxNative::with_optional(name, arity, 0, function)
+    }
+
+    ///
+    /// Creates a native whose last optional arguments may be omitted,
+    /// so it accepts between arity - optional and arity arguments.
+    ///
+    pub fn with_optional(
+        name: RoxString,
+        arity: usize,
+        optional: usize,
+        function: NativeFn,
+    ) -> RoxNative {
+        RoxNative {
+            name,
+            arity,
+            optional,
+            function,
+        }
+    }
+
+    /// Whether a call may pass this many arguments.
+    pub fn accepts(&self, arg_count: usize) -> bool {
+        (self.arity - self.optional..=self.arity).contains(&arg_count)
+    }
+}
+
+/// By identity, as Rust function pointers can't be compared reliably; see ObjectType.
+impl PartialEq for RoxNative {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Eq for RoxNative {}
+
+impl std::fmt::Display for RoxNative {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
@@ -2,7 +2,7 @@ use std::convert::From;
 use std::ops::Deref;
 use std::rc::Rc;
 
-#[derive(Clone, Debug, Eq, Hash)]
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct RoxString(String);
 
 impl RoxString {
@@ -19,17 +19,31 @@ impl RoxString {
     }
 
     pub fn as_bytes(&self) -> &[u8] {
-        &(*self.0.as_bytes())
+        self.0.as_bytes()
     }
 
     pub fn raw_parts(&mut self) -> (*const u8, usize, usize) {
         (self.0.as_ptr(), self.0.len(), self.0.capacity())
     }
-}
 
-impl PartialEq for RoxString {
-    fn eq(&self, other: &Self) -> bool {
-        self.0.eq(&other.0)
+    ///
+    /// The string quoted, with quotes, backslashes and whitespace
+    /// control characters escaped so it reads as a string literal.
+    ///
+    pub fn escaped(&self) -> String {
+        let mut escaped = String::from("\"");
+        for ch in self.0.chars() {
+            match ch {
+                '"' => escaped.push_str("\\\""),
+                '\\' => escaped.push_str("\\\\"),
+                '\n' => escaped.push_str("\\n"),
+                '\t' => escaped.push_str("\\t"),
+                '\r' => escaped.push_str("\\r"),
+                _ => escaped.push(ch),
+            }
+        }
+        escaped.push('"');
+        escaped
     }
 }
 
@@ -56,13 +70,46 @@ impl Deref for RoxString {
 impl std::ops::Add for RoxString {
     type Output = RoxString;
 
-    fn add(self, rhs: Self) -> Self::Output {
-        let str1 = self.0.as_str();
+    fn add(mut self, rhs: Self) -> Self::Output {
+        self.0.push_str(&rhs.0);
+        self
+    }
+}
+
+///
+/// Joins strings into a single RoxString. The buffer is sized up front
+/// when the total length is known, so the result is built with one
+/// allocation instead of one per joined piece.
+///
+#[derive(Debug, Default)]
+pub struct RoxStringBuilder(String);
+
+impl RoxStringBuilder {
+    pub fn new() -> RoxStringBuilder {
+        RoxStringBuilder::default()
+    }
 
-        let mut new_string = String::from(str1);
-        new_string.push_str(rhs.0.as_str());
+    pub fn with_capacity(capacity: usize) -> RoxStringBuilder {
+        RoxStringBuilder(String::with_capacity(capacity))
+    }
 
-        RoxString::new(&new_string)
+    /// Joins all of the parts in a single allocation.
+    pub fn concat(parts: &[&str]) -> RoxString {
+        let length = parts.iter().map(|part| part.len()).sum();
+        let mut builder = RoxStringBuilder::with_capacity(length);
+        for part in parts {
+            builder.push(part);
+        }
+        builder.build()
+    }
+
+    pub fn push(&mut self, part: &str) -> &mut RoxStringBuilder {
+        self.0.push_str(part);
+        self
+    }
+
+    pub fn build(self) -> RoxString {
+        RoxString(self.0)
     }
 }
 
@@ -71,3 +118,34 @@ impl std::fmt::Display for RoxString {
         write!(f, "{}", self.0)
     }
 }
+
+impl std::fmt::Debug for RoxString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.escaped())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escaped_differs_from_display() {
+        let string = RoxString::new("a\tb\nc");
+        assert_eq!(string.to_string(), "a\tb\nc");
+        assert_eq!(string.escaped(), "\"a\\tb\\nc\"");
+        assert_eq!(format!("{:?}", string), string.escaped());
+    }
+
+    #[test]
+    fn builder_concatenates_in_one_allocation() {
+        let string = RoxStringBuilder::concat(&["a", "bc", "", "def"]);
+        assert_eq!(&*string, "abcdef");
+        // sized exactly, so it was never reallocated while building
+        assert_eq!(string.capacity(), string.length());
+
+        let mut builder = RoxStringBuilder::new();
+        builder.push("x").push("y");
+        assert_eq!(&*builder.build(), "xy");
+    }
+}
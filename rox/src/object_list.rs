@@ -1,7 +1,6 @@
 use crate::RoxObject;
 
-
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct ObjectList(Option<*mut RoxObject>);
 
 impl ObjectList {
@@ -6,20 +6,32 @@
 pub enum OpCode {
     OpReturn(usize),
     OpConstant(usize), // the internal value is treated as index into constant values array
+    OpConstantLong(usize), // same as OpConstant for indices that don't fit in a byte
     OpNegate,
+    OpUnaryPlus, // checks that the operand is a number and leaves it unchanged
     OpAdd,
     OpSubtract,
     OpMultiply,
     OpDivide,
+    OpModulo,
+    OpFloorDiv, // divides and rounds the quotient down
+    OpPower,
+    OpBitAnd,
+    OpBitOr,
+    OpBitXor,
+    OpShl,
+    OpShr,
     OpNil,
     OpTrue,
     OpFalse,
     OpNot,
     OpEqual,
+    OpStrictEqual, // like OpEqual, but values of different types are never equal
     OpGreater,
     OpLess,
     OpPrint,
     OpPop,
+    OpPopN(usize),         // pops this many values at once
     OpDefineGlobal(usize), // stores the index of the string identifier in the constants array
     OpGetGlobal(usize),
     OpSetGlobal(usize),
@@ -28,39 +40,90 @@ pub enum OpCode {
     OpJumpIfFalse(Option<usize>),
     OpJump(Option<usize>),
     OpLoop(usize),
+    OpEcho,
+    OpPushLine, // pushes the line the executing function was called from
+    OpPushHandler(Option<usize>, Option<usize>), // stores the jump offsets to the catch and finally blocks
+    OpPopHandler,
+    OpLeaveTry,
+    OpEndFinally,
+    OpThrow,
+    OpCall(usize), // stores the number of arguments passed
+    OpGetProperty(usize),
+    OpBuildArray(usize), // pops this many elements into a new array
+    OpIndexGet,
+    OpIndexSet, // stores the index of the property name in constants
+    OpClosure,  // wraps the function on top of the stack in a closure
+    OpGetUpvalue(usize),
+    OpSetUpvalue(usize),
+    OpCloseUpvalue, // moves the captured local on top of the stack off it
 }
 
-impl std::fmt::Display for OpCode {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl OpCode {
+    /// The name of the instruction, without its operands.
+    pub fn name(&self) -> &'static str {
         match self {
-            OpCode::OpReturn(_) => write!(f, "OP_RETURN"),
-            OpCode::OpConstant(_) => write!(f, "OP_CONSTANT"),
-            OpCode::OpNegate => write!(f, "OP_NEGATE"),
-            OpCode::OpAdd => write!(f, "OP_ADD"),
-            OpCode::OpSubtract => write!(f, "OP_SUBTRACT"),
-            OpCode::OpMultiply => write!(f, "OP_MULTIPLY"),
-            OpCode::OpDivide => write!(f, "OP_DIVIDE"),
-            OpCode::OpNil => write!(f, "OP_NIL"),
-            OpCode::OpTrue => write!(f, "OP_TRUE"),
-            OpCode::OpFalse => write!(f, "OP_FALSE"),
-            OpCode::OpNot => write!(f, "OP_NOT"),
-            OpCode::OpEqual => write!(f, "OP_EQUAL"),
-            OpCode::OpGreater => write!(f, "OP_GREATER"),
-            OpCode::OpLess => write!(f, "OP_LESS"),
-            OpCode::OpPrint => write!(f, "OP_PRINT"),
-            OpCode::OpPop => write!(f, "OP_POP"),
-            OpCode::OpDefineGlobal(_) => write!(f, "OP_DEFINE_GLOBAL"),
-            OpCode::OpGetGlobal(_) => write!(f, "OP_GET_GLOBAL"),
-            OpCode::OpSetGlobal(_) => write!(f, "OP_SET_GLOBAL"),
-            OpCode::OpSetLocal(_) => write!(f, "OP_SET_LOCAL"),
-            OpCode::OpGetLocal(_) => write!(f, "OP_GET_LOCAL"),
-            OpCode::OpJumpIfFalse(_) => write!(f, "OP_JUMP_IF_FALSE"),
-            OpCode::OpJump(_) => write!(f, "OP_JUMP"),
-            OpCode::OpLoop(_) => write!(f, "OP_LOOP"),
+            OpCode::OpReturn(_) => "OP_RETURN",
+            OpCode::OpConstant(_) => "OP_CONSTANT",
+            OpCode::OpConstantLong(_) => "OP_CONSTANT_LONG",
+            OpCode::OpNegate => "OP_NEGATE",
+            OpCode::OpUnaryPlus => "OP_UNARY_PLUS",
+            OpCode::OpAdd => "OP_ADD",
+            OpCode::OpSubtract => "OP_SUBTRACT",
+            OpCode::OpMultiply => "OP_MULTIPLY",
+            OpCode::OpDivide => "OP_DIVIDE",
+            OpCode::OpModulo => "OP_MODULO",
+            OpCode::OpFloorDiv => "OP_FLOOR_DIV",
+            OpCode::OpPower => "OP_POWER",
+            OpCode::OpBitAnd => "OP_BIT_AND",
+            OpCode::OpBitOr => "OP_BIT_OR",
+            OpCode::OpBitXor => "OP_BIT_XOR",
+            OpCode::OpShl => "OP_SHL",
+            OpCode::OpShr => "OP_SHR",
+            OpCode::OpNil => "OP_NIL",
+            OpCode::OpTrue => "OP_TRUE",
+            OpCode::OpFalse => "OP_FALSE",
+            OpCode::OpNot => "OP_NOT",
+            OpCode::OpEqual => "OP_EQUAL",
+            OpCode::OpStrictEqual => "OP_STRICT_EQUAL",
+            OpCode::OpGreater => "OP_GREATER",
+            OpCode::OpLess => "OP_LESS",
+            OpCode::OpPrint => "OP_PRINT",
+            OpCode::OpPop => "OP_POP",
+            OpCode::OpPopN(_) => "OP_POP_N",
+            OpCode::OpDefineGlobal(_) => "OP_DEFINE_GLOBAL",
+            OpCode::OpGetGlobal(_) => "OP_GET_GLOBAL",
+            OpCode::OpSetGlobal(_) => "OP_SET_GLOBAL",
+            OpCode::OpSetLocal(_) => "OP_SET_LOCAL",
+            OpCode::OpGetLocal(_) => "OP_GET_LOCAL",
+            OpCode::OpJumpIfFalse(_) => "OP_JUMP_IF_FALSE",
+            OpCode::OpJump(_) => "OP_JUMP",
+            OpCode::OpLoop(_) => "OP_LOOP",
+            OpCode::OpEcho => "OP_ECHO",
+            OpCode::OpPushLine => "OP_PUSH_LINE",
+            OpCode::OpPushHandler(..) => "OP_PUSH_HANDLER",
+            OpCode::OpPopHandler => "OP_POP_HANDLER",
+            OpCode::OpLeaveTry => "OP_LEAVE_TRY",
+            OpCode::OpEndFinally => "OP_END_FINALLY",
+            OpCode::OpThrow => "OP_THROW",
+            OpCode::OpCall(_) => "OP_CALL",
+            OpCode::OpGetProperty(_) => "OP_GET_PROPERTY",
+            OpCode::OpBuildArray(_) => "OP_BUILD_ARRAY",
+            OpCode::OpIndexGet => "OP_INDEX_GET",
+            OpCode::OpIndexSet => "OP_INDEX_SET",
+            OpCode::OpClosure => "OP_CLOSURE",
+            OpCode::OpGetUpvalue(_) => "OP_GET_UPVALUE",
+            OpCode::OpSetUpvalue(_) => "OP_SET_UPVALUE",
+            OpCode::OpCloseUpvalue => "OP_CLOSE_UPVALUE",
         }
     }
 }
 
+impl std::fmt::Display for OpCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
 pub enum VariableOp {
     GetGlobal,
     SetGlobal,
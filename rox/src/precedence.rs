@@ -1,36 +1,49 @@
 extern crate precedence_macro;
 use precedence_macro::make_precedence;
 
+#[allow(dead_code, clippy::enum_variant_names)]
 #[derive(Debug, PartialEq, PartialOrd, Eq, Ord)]
 #[repr(u8)]
 pub enum NewPrecedence {
     PrecNone = 0,
     PrecAssign = 1,
-    PrecOr = 2,
-    PrecAnd = 3,
-    PrecEquality = 4,
-    PrecComparison = 5,
-    PrecTerm = 6,
-    PrecFactor = 7,
-    PrecUnary = 8,
-    PrecCall = 9,
-    PrecPrimary = 10,
+    PrecConditional = 2,
+    PrecOr = 3,
+    PrecAnd = 4,
+    PrecEquality = 5,
+    PrecComparison = 6,
+    PrecBitOr = 7,
+    PrecBitXor = 8,
+    PrecBitAnd = 9,
+    PrecShift = 10,
+    PrecTerm = 11,
+    PrecFactor = 12,
+    PrecUnary = 13,
+    PrecPower = 14,
+    PrecCall = 15,
+    PrecPrimary = 16,
 }
 
-impl Into<u8> for NewPrecedence {
-    fn into(self) -> u8 {
-        match self {
+impl From<NewPrecedence> for u8 {
+    fn from(precedence: NewPrecedence) -> u8 {
+        match precedence {
             NewPrecedence::PrecNone => 0,
             NewPrecedence::PrecAssign => 1,
-            NewPrecedence::PrecOr => 2,
-            NewPrecedence::PrecAnd => 3,
-            NewPrecedence::PrecEquality => 4,
-            NewPrecedence::PrecComparison => 5,
-            NewPrecedence::PrecTerm => 6,
-            NewPrecedence::PrecFactor => 7,
-            NewPrecedence::PrecUnary => 8,
-            NewPrecedence::PrecCall => 9,
-            NewPrecedence::PrecPrimary => 10,
+            NewPrecedence::PrecConditional => 2,
+            NewPrecedence::PrecOr => 3,
+            NewPrecedence::PrecAnd => 4,
+            NewPrecedence::PrecEquality => 5,
+            NewPrecedence::PrecComparison => 6,
+            NewPrecedence::PrecBitOr => 7,
+            NewPrecedence::PrecBitXor => 8,
+            NewPrecedence::PrecBitAnd => 9,
+            NewPrecedence::PrecShift => 10,
+            NewPrecedence::PrecTerm => 11,
+            NewPrecedence::PrecFactor => 12,
+            NewPrecedence::PrecUnary => 13,
+            NewPrecedence::PrecPower => 14,
+            NewPrecedence::PrecCall => 15,
+            NewPrecedence::PrecPrimary => 16,
         }
     }
 }
@@ -40,15 +53,21 @@ impl From<u8> for NewPrecedence {
         match n {
             0 => NewPrecedence::PrecNone,
             1 => NewPrecedence::PrecAssign,
-            2 => NewPrecedence::PrecOr,
-            3 => NewPrecedence::PrecAnd,
-            4 => NewPrecedence::PrecEquality,
-            5 => NewPrecedence::PrecComparison,
-            6 => NewPrecedence::PrecTerm,
-            7 => NewPrecedence::PrecFactor,
-            8 => NewPrecedence::PrecUnary,
-            9 => NewPrecedence::PrecCall,
-            10 => NewPrecedence::PrecPrimary,
+            2 => NewPrecedence::PrecConditional,
+            3 => NewPrecedence::PrecOr,
+            4 => NewPrecedence::PrecAnd,
+            5 => NewPrecedence::PrecEquality,
+            6 => NewPrecedence::PrecComparison,
+            7 => NewPrecedence::PrecBitOr,
+            8 => NewPrecedence::PrecBitXor,
+            9 => NewPrecedence::PrecBitAnd,
+            10 => NewPrecedence::PrecShift,
+            11 => NewPrecedence::PrecTerm,
+            12 => NewPrecedence::PrecFactor,
+            13 => NewPrecedence::PrecUnary,
+            14 => NewPrecedence::PrecPower,
+            15 => NewPrecedence::PrecCall,
+            16 => NewPrecedence::PrecPrimary,
             unknown => panic!("Cannot yield u8 from unknown Precedence Value {}", unknown),
         }
     }
@@ -69,13 +88,19 @@ impl std::fmt::Display for NewPrecedence {
         match self {
             NewPrecedence::PrecNone => write!(f, "PrecNone"),
             NewPrecedence::PrecAssign => write!(f, "PrecAssign"),
+            NewPrecedence::PrecConditional => write!(f, "PrecConditional"),
             NewPrecedence::PrecOr => write!(f, "PrecOr"),
             NewPrecedence::PrecAnd => write!(f, "PrecAnd"),
             NewPrecedence::PrecEquality => write!(f, "PrecEquality"),
             NewPrecedence::PrecComparison => write!(f, "PrecComparison"),
+            NewPrecedence::PrecBitOr => write!(f, "PrecBitOr"),
+            NewPrecedence::PrecBitXor => write!(f, "PrecBitXor"),
+            NewPrecedence::PrecBitAnd => write!(f, "PrecBitAnd"),
+            NewPrecedence::PrecShift => write!(f, "PrecShift"),
             NewPrecedence::PrecTerm => write!(f, "PrecTerm"),
             NewPrecedence::PrecFactor => write!(f, "PrecFactor"),
             NewPrecedence::PrecUnary => write!(f, "PrecUnary"),
+            NewPrecedence::PrecPower => write!(f, "PrecPower"),
             NewPrecedence::PrecCall => write!(f, "PrecCall"),
             NewPrecedence::PrecPrimary => write!(f, "PrecPrimary"),
         }
@@ -90,43 +115,67 @@ pub struct PrecNone;
 pub struct PrecAssign;
 #[derive(PartialOrd, PartialEq, Debug)]
 #[make_precedence(2)]
-pub struct PrecOr;
+pub struct PrecConditional;
 #[derive(PartialOrd, PartialEq, Debug)]
 #[make_precedence(3)]
-pub struct PrecAnd;
+pub struct PrecOr;
 #[derive(PartialOrd, PartialEq, Debug)]
 #[make_precedence(4)]
-pub struct PrecEquality;
+pub struct PrecAnd;
 #[derive(PartialOrd, PartialEq, Debug)]
 #[make_precedence(5)]
-pub struct PrecComparison;
+pub struct PrecEquality;
 #[derive(PartialOrd, PartialEq, Debug)]
 #[make_precedence(6)]
-pub struct PrecTerm;
+pub struct PrecComparison;
 #[derive(PartialOrd, PartialEq, Debug)]
 #[make_precedence(7)]
-pub struct PrecFactor;
+pub struct PrecBitOr;
 #[derive(PartialOrd, PartialEq, Debug)]
 #[make_precedence(8)]
-pub struct PrecUnary;
+pub struct PrecBitXor;
 #[derive(PartialOrd, PartialEq, Debug)]
 #[make_precedence(9)]
-pub struct PrecCall;
+pub struct PrecBitAnd;
 #[derive(PartialOrd, PartialEq, Debug)]
 #[make_precedence(10)]
+pub struct PrecShift;
+#[derive(PartialOrd, PartialEq, Debug)]
+#[make_precedence(11)]
+pub struct PrecTerm;
+#[derive(PartialOrd, PartialEq, Debug)]
+#[make_precedence(12)]
+pub struct PrecFactor;
+#[derive(PartialOrd, PartialEq, Debug)]
+#[make_precedence(13)]
+pub struct PrecUnary;
+#[derive(PartialOrd, PartialEq, Debug)]
+#[make_precedence(14)]
+pub struct PrecPower;
+#[derive(PartialOrd, PartialEq, Debug)]
+#[make_precedence(15)]
+pub struct PrecCall;
+#[derive(PartialOrd, PartialEq, Debug)]
+#[make_precedence(16)]
 pub struct PrecPrimary;
 
 #[derive(Debug, PartialOrd, PartialEq)]
 pub enum Precedence {
     PrecNone,
     PrecAssign,
+    PrecConditional,
     PrecOr,
     PrecAnd,
     PrecEquality,
     PrecComparison,
+    PrecBitOr,
+    PrecBitXor,
+    PrecBitAnd,
+    PrecShift,
     PrecTerm,
     PrecFactor,
     PrecUnary,
+    PrecPower,
     PrecCall,
     PrecPrimary,
 }
@@ -135,14 +184,20 @@ impl Precedence {
     pub fn get_next(&self) -> &Self {
         match self {
             Precedence::PrecNone => &Precedence::PrecAssign,
-            Precedence::PrecAssign => &Precedence::PrecOr,
+            Precedence::PrecAssign => &Precedence::PrecConditional,
+            Precedence::PrecConditional => &Precedence::PrecOr,
             Precedence::PrecOr => &Precedence::PrecAnd,
             Precedence::PrecAnd => &Precedence::PrecEquality,
             Precedence::PrecEquality => &Precedence::PrecComparison,
-            Precedence::PrecComparison => &Precedence::PrecTerm,
+            Precedence::PrecComparison => &Precedence::PrecBitOr,
+            Precedence::PrecBitOr => &Precedence::PrecBitXor,
+            Precedence::PrecBitXor => &Precedence::PrecBitAnd,
+            Precedence::PrecBitAnd => &Precedence::PrecShift,
+            Precedence::PrecShift => &Precedence::PrecTerm,
             Precedence::PrecTerm => &Precedence::PrecFactor,
             Precedence::PrecFactor => &Precedence::PrecUnary,
-            Precedence::PrecUnary => &Precedence::PrecCall,
+            Precedence::PrecUnary => &Precedence::PrecPower,
+            Precedence::PrecPower => &Precedence::PrecCall,
             Precedence::PrecCall => &Precedence::PrecPrimary,
             Precedence::PrecPrimary => panic!("Error, no precedence higher than PrePrimary"),
         }
@@ -154,17 +209,23 @@ impl std::ops::Deref for Precedence {
 
     fn deref(&self) -> &Self::Target {
         match self {
-            Precedence::PrecNone => &*PrecNone,
-            Precedence::PrecAssign => &*PrecAssign,
-            Precedence::PrecOr => &*PrecOr,
-            Precedence::PrecAnd => &*PrecAnd,
-            Precedence::PrecEquality => &*PrecEquality,
-            Precedence::PrecComparison => &*PrecComparison,
-            Precedence::PrecTerm => &*PrecTerm,
-            Precedence::PrecFactor => &*PrecFactor,
-            Precedence::PrecUnary => &*PrecUnary,
-            Precedence::PrecCall => &*PrecCall,
-            Precedence::PrecPrimary => &*PrecPrimary,
+            Precedence::PrecNone => &PrecNone,
+            Precedence::PrecAssign => &PrecAssign,
+            Precedence::PrecConditional => &PrecConditional,
+            Precedence::PrecOr => &PrecOr,
+            Precedence::PrecAnd => &PrecAnd,
+            Precedence::PrecEquality => &PrecEquality,
+            Precedence::PrecComparison => &PrecComparison,
+            Precedence::PrecBitOr => &PrecBitOr,
+            Precedence::PrecBitXor => &PrecBitXor,
+            Precedence::PrecBitAnd => &PrecBitAnd,
+            Precedence::PrecShift => &PrecShift,
+            Precedence::PrecTerm => &PrecTerm,
+            Precedence::PrecFactor => &PrecFactor,
+            Precedence::PrecUnary => &PrecUnary,
+            Precedence::PrecPower => &PrecPower,
+            Precedence::PrecCall => &PrecCall,
+            Precedence::PrecPrimary => &PrecPrimary,
         }
     }
 }
@@ -174,13 +235,19 @@ impl std::fmt::Display for Precedence {
         match self {
             Precedence::PrecNone => write!(f, "PrecNone"),
             Precedence::PrecAssign => write!(f, "PrecAssign"),
+            Precedence::PrecConditional => write!(f, "PrecConditional"),
             Precedence::PrecOr => write!(f, "PrecOr"),
             Precedence::PrecAnd => write!(f, "PrecAnd"),
             Precedence::PrecEquality => write!(f, "PrecEquality"),
             Precedence::PrecComparison => write!(f, "PrecComparison"),
+            Precedence::PrecBitOr => write!(f, "PrecBitOr"),
+            Precedence::PrecBitXor => write!(f, "PrecBitXor"),
+            Precedence::PrecBitAnd => write!(f, "PrecBitAnd"),
+            Precedence::PrecShift => write!(f, "PrecShift"),
             Precedence::PrecTerm => write!(f, "PrecTerm"),
             Precedence::PrecFactor => write!(f, "PrecFactor"),
             Precedence::PrecUnary => write!(f, "PrecUnary"),
+            Precedence::PrecPower => write!(f, "PrecPower"),
             Precedence::PrecCall => write!(f, "PrecCall"),
             Precedence::PrecPrimary => write!(f, "PrecPrimary"),
         }
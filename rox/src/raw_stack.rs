@@ -1,33 +1,31 @@
-use std::ops::Index;
-
 use crate::Value;
 use crate::STACK_MAX;
 
+///
+/// The VM value stack. The backing store starts out with room
+/// for STACK_MAX values and grows on demand when pushed past it.
+///
 #[derive(Debug)]
 pub struct RawStack {
-    pub values: [Option<Value>; STACK_MAX],
+    pub values: Vec<Option<Value>>,
     pub size: usize,
-    pub stack_ptr: *mut Option<Value>,
 }
 
 impl RawStack {
     pub fn new() -> RawStack {
-        let mut values = [(); STACK_MAX].map(|_| Option::<Value>::default());
-        let stack_ptr = values.as_mut_ptr();
         RawStack {
-            values,
+            values: Vec::with_capacity(STACK_MAX),
             size: 0,
-            stack_ptr,
         }
     }
 
     pub fn reset_stack(&mut self) {
         self.size = 0;
-        self.stack_ptr = self.values.as_mut_ptr();
+        self.values.clear();
     }
 
     pub fn peek(&self, distance: usize) -> Result<Value, &'static str> {
-        if let None = self.size.checked_sub(distance) {
+        if self.size.checked_sub(distance).is_none() {
             return Err("Cannot peek beyond bottom of stack!");
         }
 
@@ -41,11 +39,12 @@ impl RawStack {
     }
 
     pub fn push(&mut self, value: Value) {
-        if self.size == STACK_MAX {
-            panic!("Cannot push beyond maximum stack size of {}", STACK_MAX);
+        // reuse a previously popped slot before growing the backing store
+        if self.size < self.values.len() {
+            self.values[self.size] = Some(value);
+        } else {
+            self.values.push(Some(value));
         }
-
-        self.values[self.size] = Some(value.clone());
         self.size += 1;
     }
 
@@ -55,18 +54,48 @@ impl RawStack {
         }
 
         self.size -= 1;
-        let val = &self.values[self.size];
 
-        match val {
-            Some(val) => Ok(val.clone()),
+        match self.values[self.size].take() {
+            Some(val) => Ok(val),
             None => Err("Cannot pop from empty stack!"),
         }
     }
 
+    /// Discards the top count values in one step.
+    pub fn pop_n(&mut self, count: usize) -> Result<(), &'static str> {
+        match self.size.checked_sub(count) {
+            Some(size) => {
+                self.truncate(size);
+                Ok(())
+            }
+            None => Err("Cannot pop beyond bottom of stack!"),
+        }
+    }
+
+    /// Discards every value above the given stack size.
+    pub fn truncate(&mut self, size: usize) {
+        while self.size > size {
+            self.size -= 1;
+            self.values[self.size] = None;
+        }
+    }
+
+    /// The value in the slot at index.
+    pub fn get(&self, index: usize) -> Result<Value, &'static str> {
+        match self.values.get(index) {
+            Some(Some(value)) if index < self.size => Ok(value.clone()),
+            _ => Err("Error reading stack slot."),
+        }
+    }
+
+    /// The live values, from the bottom of the stack to the top.
+    pub fn as_slice(&self) -> &[Option<Value>] {
+        &self.values[..self.size]
+    }
+
     pub fn get_and_push_local(&mut self, index: usize) -> Result<(), &'static str> {
-        if let Some(value) = &self.values[index] {
-            self.values[self.size] = Some(value.clone());
-            self.size += 1;
+        if let Some(Some(value)) = self.values.get(index) {
+            self.push(value.clone());
             Ok(())
         } else {
             Err("Error pushing local variable.")
@@ -90,20 +119,21 @@ impl RawStack {
     }
 }
 
+impl Default for RawStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl std::fmt::Display for RawStack {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut s = String::from("[");
-        // TODO! Need to implement this to use stack ptr instead of array
-        for i in 0..self.size {
-            if let Some(val) = &self.values[i] {
-                s.push_str(&(val.to_string()));
-                if i < self.size - 1 {
-                    s.push_str(", ");
-                }
-            }
-        }
-        s.push_str("]");
-        write!(f, "{}", s)
+        let values = self
+            .as_slice()
+            .iter()
+            .flatten()
+            .map(|val| val.to_string())
+            .collect::<Vec<_>>();
+        write!(f, "[{}]", values.join(", "))
     }
 }
 
@@ -148,6 +178,25 @@ mod tests {
         assert_eq!(s.size, 3);
     }
 
+    #[test]
+    fn test_as_slice() -> Result<(), &'static str> {
+        let mut s = RawStack::new();
+        s.push(Value::Number(RoxNumber(6.0)));
+        s.push(Value::Number(RoxNumber(5.0)));
+        s.push(Value::Number(RoxNumber(4.0)));
+
+        assert_eq!(s.as_slice().len(), 3);
+        assert_eq!(s.as_slice()[0], Some(Value::Number(RoxNumber(6.0))));
+        assert_eq!(s.as_slice()[2], Some(Value::Number(RoxNumber(4.0))));
+
+        // popped slots are kept for reuse but aren't part of the live stack
+        s.pop()?;
+        assert_eq!(s.as_slice().len(), 2);
+        assert_eq!(s.to_string(), "[6, 5]");
+
+        Ok(())
+    }
+
     #[test]
     fn test_pop() -> Result<(), &'static str> {
         let mut s = RawStack::new();
@@ -163,25 +212,37 @@ mod tests {
         assert_eq!(val2, Value::Number(RoxNumber(5.0)));
         assert_eq!(val3, Value::Number(RoxNumber(6.0)));
 
-        if let Ok(_) = s.pop() {
-            assert!(false);
-        }
+        assert!(s.pop().is_err());
 
         Ok(())
     }
 
     #[test]
-    #[should_panic]
-    fn test_max_stack_panics() {
+    fn test_pop_empty() {
+        let mut s = RawStack::new();
+
+        assert_eq!(s.pop(), Err("Cannot pop from empty VM stack!"));
+        assert_eq!(s.size, 0);
+    }
+
+    #[test]
+    fn test_stack_grows_beyond_max() -> Result<(), &'static str> {
         let mut s = RawStack::new();
 
-        for i in 0..STACK_MAX + 1 {
+        for i in 0..1000 {
             s.push(Value::Number(RoxNumber(i as f32)));
         }
+        assert_eq!(s.size, 1000);
+
+        for i in (0..1000).rev() {
+            assert_eq!(s.pop()?, Value::Number(RoxNumber(i as f32)));
+        }
+        assert!(s.pop().is_err());
+
+        Ok(())
     }
 
     #[test]
-    #[ignore = "not working currently"]
     fn test_print_stack() {
         let mut s = RawStack::new();
         s.push(Value::Number(RoxNumber(6.0)));
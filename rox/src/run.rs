@@ -1,33 +1,77 @@
 use crate::vm::VM;
-use crate::InterpretError;
-use crate::DEBUG_MODE;
-use std::io::Write;
+use crate::Scanner;
+use crate::{InterpretError, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::Path;
 use std::{fs, io};
 
+///
+/// How running a file ended, so a host can choose its own exit behavior.
+/// This isn't a process exit status like std::process::ExitCode, but
+/// converts into one using the sysexits codes clox uses.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    Ok,
+    UsageError, // the command line arguments were invalid
+    CompileError,
+    RuntimeError,
+    IoError, // the file couldn't be read
+}
+
+impl From<&InterpretError> for RunOutcome {
+    fn from(error: &InterpretError) -> Self {
+        match error {
+            InterpretError::CompileError(_) => RunOutcome::CompileError,
+            InterpretError::RuntimeError(_) | InterpretError::Thrown(_) => RunOutcome::RuntimeError,
+        }
+    }
+}
+
+impl From<RunOutcome> for std::process::ExitCode {
+    fn from(outcome: RunOutcome) -> Self {
+        match outcome {
+            RunOutcome::Ok => std::process::ExitCode::SUCCESS,
+            RunOutcome::UsageError => std::process::ExitCode::from(64),
+            RunOutcome::CompileError => std::process::ExitCode::from(65),
+            RunOutcome::RuntimeError => std::process::ExitCode::from(70),
+            RunOutcome::IoError => std::process::ExitCode::from(74),
+        }
+    }
+}
+
 pub struct Config {
     vm: VM,
-    filename: Option<String>,
+    pub filename: Option<String>,
     pub is_repl: bool,
 }
 
 #[derive(Debug)]
 pub struct ConfigError {
     msg: String,
+    outcome: RunOutcome,
 }
 
 impl From<InterpretError> for ConfigError {
     fn from(error: InterpretError) -> Self {
+        let outcome = RunOutcome::from(&error);
         let msg = match error {
             InterpretError::CompileError(msg) => msg,
-            InterpretError::RuntimeError(msg) => msg,
+            InterpretError::RuntimeError(error) => error.to_string(),
+            InterpretError::Thrown(_) => error.to_string(),
         };
-        Self { msg }
+        Self { msg, outcome }
     }
 }
 
 impl ConfigError {
-    fn new(msg: String) -> ConfigError {
-        ConfigError { msg }
+    fn new(msg: String, outcome: RunOutcome) -> ConfigError {
+        ConfigError { msg, outcome }
+    }
+
+    pub fn outcome(&self) -> RunOutcome {
+        self.outcome
     }
 }
 
@@ -54,82 +98,279 @@ impl Config {
                 is_repl: false,
                 filename: args.next(),
             })
+        } else if num_args == 3 && args.next().as_deref() == Some("--time") {
+            // --time reports how often each opcode ran once the file finishes
+            let vm = VM::new();
+            vm.enable_profiling();
+            Ok(Config {
+                vm,
+                is_repl: false,
+                filename: args.next(),
+            })
         } else {
-            Err(ConfigError::new(String::from("Usage: rox [filename]")))
+            Err(ConfigError::new(
+                String::from("Usage: rox [--time] [filename]"),
+                RunOutcome::UsageError,
+            ))
         }
     }
 
-    pub fn run_file_with_filename(&self, pathname: &str) -> Result<(), ConfigError> {
+    pub fn run_file_with_filename(&mut self, pathname: &str) -> Result<(), ConfigError> {
         // read the file contents into string
         let file_contents = match fs::read_to_string(pathname) {
             Ok(content) => content,
             Err(msg) => {
-                return Err(ConfigError::new(format!(
-                    "Error reading from file {} with msg: {}",
-                    pathname, msg
-                )))
+                return Err(ConfigError::new(
+                    format!("Error reading from file {} with msg: {}", pathname, msg),
+                    RunOutcome::IoError,
+                ))
             }
         };
 
-        if DEBUG_MODE {
-            println!("Read contents of file:\n{file_contents}");
-        }
+        self.vm
+            .tracer()
+            .scanner(format_args!("Read contents of file:\n{file_contents}"));
 
         // interpret the file
-        self.vm.interpret(&file_contents)?;
+        self.vm.interpret_catching_panics(&file_contents)?;
+        if let Some(profile) = self.vm.profile() {
+            eprint!("{}", format_profile(&profile));
+        }
         Ok(())
     }
 
-    pub fn run_file(&self) -> Result<(), ConfigError> {
-        // grab filename path if it exists
-        let file = match &self.filename {
-            Some(val) => val,
-            None => return Err(ConfigError::new(String::from("Error retrieving filename."))),
+    pub fn repl(&mut self) {
+        run_repl(&mut self.vm, io::stdin().lock());
+    }
+}
+
+///
+/// Formats opcode execution counts one per line, most frequent first.
+///
+fn format_profile(profile: &HashMap<&'static str, u64>) -> String {
+    let mut counts = profile.iter().collect::<Vec<_>>();
+    counts.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+    counts
+        .iter()
+        .map(|(name, count)| format!("{:<20} {}\n", name, count))
+        .collect()
+}
+
+///
+/// Reads and interprets the file at path in a new VM, reporting any
+/// error on stderr and returning how the run ended.
+///
+pub fn run_file(path: &Path) -> RunOutcome {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(msg) => {
+            eprintln!("Error reading from file {}: {}", path.display(), msg);
+            return RunOutcome::IoError;
+        }
+    };
+
+    match VM::new().interpret_catching_panics(&source) {
+        Ok(_) => RunOutcome::Ok,
+        Err(error) => {
+            eprintln!("{}", error);
+            RunOutcome::from(&error)
+        }
+    }
+}
+
+///
+/// Interprets source in a new VM, returning the value of its trailing
+/// expression statement, or nil when it doesn't end with one.
+///
+pub fn run_source(source: &str) -> Result<Value, InterpretError> {
+    VM::new()
+        .interpret_catching_panics(source)
+        .map(|result| result.0)
+}
+
+///
+/// Evaluates a single expression in a new VM and returns its value.
+/// Source holding anything other than one expression, such as a
+/// statement or a trailing semicolon, is a compile error.
+///
+pub fn eval(expression: &str) -> Result<Value, InterpretError> {
+    VM::new()
+        .interpret_expression(expression)
+        .map(|result| result.0)
+}
+
+///
+/// Scans source without running it and formats each token with its
+/// type, line and column, for debugging the grammar.
+///
+pub fn dump_tokens(source: &str) -> Vec<String> {
+    Scanner::new()
+        .scan_tokens(source)
+        .iter()
+        .map(|token| token.to_string())
+        .collect()
+}
+
+///
+/// Runs an interactive session on stdin in a new VM.
+///
+pub fn repl() {
+    run_repl(&mut VM::new(), io::stdin().lock());
+}
+
+///
+/// Interprets each line read from input, echoing the value of bare
+/// expression statements, until an empty line or EOF. Globals persist
+/// between lines and errors are reported without ending the session.
+///
+fn run_repl(vm: &mut VM, mut input: impl BufRead) {
+    let mut buffer = String::new();
+
+    loop {
+        print!("rox> ");
+        if io::stdout().flush().is_err() {
+            return;
+        }
+
+        let result = match input.read_line(&mut buffer) {
+            Ok(size) => size,
+            Err(_) => return,
         };
 
-        // read the file contents into string
-        let file_contents = match fs::read_to_string(file) {
-            Ok(content) => content,
-            Err(_) => {
-                return Err(ConfigError::new(format!(
-                    "Error reading from file: {}",
-                    file,
-                )))
-            }
+        vm.tracer().scanner(format_args!(
+            "Repl read line of length {} -- {}",
+            result,
+            buffer.trim_end()
+        ));
+
+        // EOF or an empty line ends the session
+        if buffer.trim().is_empty() {
+            println!();
+            return;
+        }
+
+        if let Err(val) = vm.interpret_repl(&buffer) {
+            println!("\n<<<Error in Rox REPL>>>\n\nMessage: {}", val);
         };
 
-        if DEBUG_MODE {
-            println!("Read contents of file:\n{file_contents}");
+        vm.reset();
+
+        buffer.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RoxNumber;
+    use std::path::PathBuf;
+
+    fn source_file(name: &str, source: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("rox-{}-{}.rox", name, std::process::id()));
+        fs::write(&path, source).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_run_file_exit_codes() {
+        for (name, source, expected) in [
+            ("ok", "var a = 1;", RunOutcome::Ok),
+            ("syntax", "var a = ;", RunOutcome::CompileError),
+            ("runtime", "var a = -true;", RunOutcome::RuntimeError),
+        ] {
+            let path = source_file(name, source);
+            assert_eq!(run_file(&path), expected);
+            fs::remove_file(&path).unwrap();
         }
 
-        // interpret the file
-        self.vm.interpret(&file_contents)?;
-        Ok(())
+        let path = source_file("missing", "");
+        fs::remove_file(&path).unwrap();
+        assert_eq!(run_file(&path), RunOutcome::IoError);
     }
 
-    pub fn repl(&mut self) {
-        let mut buffer = String::new();
+    #[test]
+    fn test_config_error_outcomes() {
+        let mut config = Config {
+            vm: VM::new(),
+            filename: None,
+            is_repl: false,
+        };
+        let path = source_file("config-syntax", "var a = ;");
+        let outcome = config
+            .run_file_with_filename(path.to_str().unwrap())
+            .unwrap_err()
+            .outcome();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(outcome, RunOutcome::CompileError);
 
-        loop {
-            print!("rox> ");
-            io::stdout().flush().unwrap();
+        let outcome = config
+            .run_file_with_filename(path.to_str().unwrap())
+            .unwrap_err()
+            .outcome();
+        assert_eq!(outcome, RunOutcome::IoError);
+        assert_eq!(
+            std::process::ExitCode::from(outcome),
+            std::process::ExitCode::from(74)
+        );
+    }
+
+    #[test]
+    fn test_run_source_returns_trailing_expression() {
+        assert_eq!(run_source("1 + 2;").unwrap(), Value::Number(RoxNumber(3.0)));
+        assert_eq!(run_source("var a = 1;").unwrap(), Value::Nil);
+        assert_eq!(
+            run_source("var a = 2; a * 5;").unwrap(),
+            Value::Number(RoxNumber(10.0))
+        );
+    }
 
-            let result = match io::stdin().read_line(&mut buffer) {
-                Ok(size) => size,
-                Err(_) => continue,
-            };
+    #[test]
+    fn test_eval() {
+        assert_eq!(eval("2 * (3 + 4)").unwrap(), Value::Number(RoxNumber(14.0)));
+        assert!(matches!(eval("2 *"), Err(InterpretError::CompileError(_))));
+        assert!(matches!(eval("-nil"), Err(InterpretError::RuntimeError(_))));
 
-            if DEBUG_MODE {
-                print!("Repl read line of length {} -- {}", result, buffer);
+        // only a single expression is evaluated
+        for source in ["var a = 1; print a", "1 + 2;", "1 2", ""] {
+            assert!(matches!(eval(source), Err(InterpretError::CompileError(_))));
+        }
+        match eval("1 + 2; 3") {
+            Err(InterpretError::CompileError(message)) => {
+                assert!(message.ends_with("Expect end of expression."))
             }
+            other => panic!("Expected a compile error, got {:?}", other),
+        }
+    }
 
-            if let Err(val) = self.vm.interpret(&buffer) {
-                println!("\n<<<Error in Rox REPL>>>\n\nMessage: {}", val);
-            };
+    #[test]
+    fn test_dump_tokens() {
+        let tokens = dump_tokens("1 + 2;");
 
-            self.vm.reset();
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(
+            tokens[1],
+            "<Token lexeme: type: Plus, line: 1, col: 3>".to_string()
+        );
+        assert!(tokens.last().unwrap().contains("type: EOF"));
+    }
 
-            buffer.clear();
-        }
+    #[test]
+    fn test_repl_keeps_globals_after_errors() {
+        let mut vm = VM::new();
+        run_repl(
+            &mut vm,
+            "var x = 1;\nvar y = ;\nvar z = x + 1;\n".as_bytes(),
+        );
+
+        // a throw would surface as an error if z wasn't defined from x
+        assert!(vm.interpret("if (z != 2) throw z;").is_ok());
+    }
+
+    #[test]
+    fn test_repl_stops_at_empty_line() {
+        let mut vm = VM::new();
+        run_repl(&mut vm, "var x = 1;\n\nx = 2;\n".as_bytes());
+
+        assert!(vm.interpret("if (x != 1) throw x;").is_ok());
     }
 }
@@ -1,6 +1,6 @@
 use crate::{
     token::{Token, TokenType},
-    RoxNumber, RoxString, TokenStream, DEBUG_MODE,
+    RoxNumber, RoxString, TokenStream, Tracer,
 };
 use std::cell::RefCell;
 use std::rc::Rc;
@@ -8,15 +8,23 @@ use std::{iter::Peekable, str::CharIndices};
 
 type Peeker<'a> = Peekable<CharIndices<'a>>;
 
+const BYTE_ORDER_MARK: char = '\u{FEFF}';
+
 #[derive(Default)]
 pub struct Scanner {
     had_error: RefCell<bool>,
+    tracer: Tracer,
 }
 
 impl Scanner {
     pub fn new() -> Scanner {
+        Scanner::with_tracer(Tracer::default())
+    }
+
+    pub fn with_tracer(tracer: Tracer) -> Scanner {
         Scanner {
             had_error: RefCell::new(false),
+            tracer,
         }
     }
 
@@ -38,7 +46,44 @@ impl Scanner {
         _t_type
     }
 
+    ///
+    /// Scans a string literal after its opening quote, replacing the
+    /// escapes `\n`, `\t`, `\r`, `\0`, `\\` and `\"` with the characters they stand for.
+    ///
     fn string(peeker: &mut Peeker) -> TokenType {
+        let mut result = String::new();
+        let mut bad_escape = None;
+        while let Some((_, c)) = peeker.next() {
+            match c {
+                '"' => {
+                    return match bad_escape {
+                        Some(escape) => TokenType::Error(format!(
+                            "Invalid escape sequence '\\{}' in string literal",
+                            escape
+                        )),
+                        None => TokenType::StringLiteral(Rc::new(RoxString::new(&result))),
+                    };
+                }
+                '\\' => match peeker.next() {
+                    Some((_, 'n')) => result.push('\n'),
+                    Some((_, 't')) => result.push('\t'),
+                    Some((_, 'r')) => result.push('\r'),
+                    Some((_, '0')) => result.push('\0'),
+                    Some((_, '\\')) => result.push('\\'),
+                    Some((_, '"')) => result.push('"'),
+                    // keep scanning so the rest of the literal isn't read as code
+                    Some((_, other)) => bad_escape = bad_escape.or(Some(other)),
+                    None => break,
+                },
+                c => result.push(c),
+            }
+        }
+
+        TokenType::Error(String::from("Unterminated string literal"))
+    }
+
+    /// Scans a raw string literal after its opening quote, leaving backslashes as they are.
+    fn raw_string(peeker: &mut Peeker) -> TokenType {
         let mut found_closing_quotation = false;
         let result: String = peeker
             .take_while(|(_, c)| {
@@ -59,23 +104,82 @@ impl Scanner {
     }
 
     fn number(peeker: &mut Peeker, ch: &char) -> TokenType {
+        if *ch == '0' {
+            if let Some((_, prefix)) = peeker.next_if(|(_, c)| *c == 'x' || *c == 'b') {
+                let radix = if prefix == 'x' { 16 } else { 2 };
+                return Scanner::integer(peeker, prefix, radix);
+            }
+        }
+
         let mut string_of_num = ch.to_string();
-        while let Some((_, c)) = peeker.next_if(|(_, c)| c.is_numeric() || *c == '.') {
+        while let Some((_, c)) = peeker.next_if(|(_, c)| c.is_numeric() || *c == '.' || *c == '_') {
             string_of_num.push(c)
         }
 
-        match string_of_num.parse::<f32>() {
+        // underscores may only separate two digits, as in 1_000
+        let has_bad_separator = string_of_num.ends_with('_')
+            || ["__", "_.", "._"]
+                .iter()
+                .any(|pattern| string_of_num.contains(pattern));
+        if has_bad_separator {
+            return TokenType::Error(format!(
+                "Invalid digit separator in number {}",
+                string_of_num
+            ));
+        }
+
+        match string_of_num.replace('_', "").parse::<f32>() {
             Ok(val) => TokenType::Number(RoxNumber(val)),
             Err(_) => TokenType::Error(format!("Error parsing number {}", string_of_num)),
         }
     }
 
+    ///
+    /// Scans the digits of a hexadecimal or binary literal after its
+    /// 0x or 0b prefix. Numbers are floats in Rox, so the literal
+    /// becomes the number with the same integer value.
+    ///
+    fn integer(peeker: &mut Peeker, prefix: char, radix: u32) -> TokenType {
+        let mut digits = String::new();
+        while let Some((_, c)) = peeker.next_if(|(_, c)| c.is_ascii_alphanumeric()) {
+            digits.push(c)
+        }
+
+        match i64::from_str_radix(&digits, radix) {
+            Ok(val) => TokenType::Number(RoxNumber(val as f32)),
+            Err(_) => TokenType::Error(format!("Error parsing number 0{}{}", prefix, digits)),
+        }
+    }
+
+    /// Skips over the body of a block comment up to and including
+    /// the closing `*/`. Returns false if the line ran out first,
+    /// meaning the comment continues onto the next line.
+    fn skip_block_comment(peeker: &mut Peeker) -> bool {
+        while let Some((_, c)) = peeker.next() {
+            if c == '*' && peeker.next_if(|(_, c)| *c == '/').is_some() {
+                return true;
+            }
+        }
+        false
+    }
+
     fn identifier(peeker: &mut Peeker, first_letter: &char) -> TokenType {
         let mut string_accum = first_letter.to_string();
         while let Some((_, c)) = peeker.next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '_') {
             string_accum.push(c);
         }
 
+        // a number with a leading digit separator, like _5
+        if string_accum.starts_with('_')
+            && string_accum.chars().any(|c| c.is_ascii_digit())
+            && string_accum.chars().all(|c| c.is_ascii_digit() || c == '_')
+        {
+            return TokenType::Error(format!(
+                "Invalid digit separator in number {}",
+                string_accum
+            ));
+        }
+
         Scanner::find_identifier_type(&string_accum)
     }
 
@@ -85,17 +189,39 @@ impl Scanner {
             (.., 'a') => Scanner::check_keyword(&mut id_chars, 2, "nd", id, TokenType::And),
             (.., 'b') => Scanner::check_keyword(&mut id_chars, 4, "reak", id, TokenType::Break),
             (.., 'c') => match id_chars.next().unwrap_or((0, '!')) {
-                (.., 'a') => Scanner::check_keyword(&mut id_chars, 2, "se", id, TokenType::Case),
+                (.., 'a') => match id_chars.next().unwrap_or((0, '!')) {
+                    (.., 's') => Scanner::check_keyword(&mut id_chars, 1, "e", id, TokenType::Case),
+                    (.., 't') => {
+                        Scanner::check_keyword(&mut id_chars, 2, "ch", id, TokenType::Catch)
+                    }
+                    _ => TokenType::Identifier(Rc::new(RoxString::new(id))),
+                },
                 (.., 'l') => Scanner::check_keyword(&mut id_chars, 3, "ass", id, TokenType::Class),
-                (.., 'o') => {
-                    Scanner::check_keyword(&mut id_chars, 6, "ntinue", id, TokenType::Continue)
+                (.., 'o') => match id_chars.next().unwrap_or((0, '!')) {
+                    (.., 'n') => match id_chars.next().unwrap_or((0, '!')) {
+                        (.., 's') => {
+                            Scanner::check_keyword(&mut id_chars, 1, "t", id, TokenType::Const)
+                        }
+                        (.., 't') => Scanner::check_keyword(
+                            &mut id_chars,
+                            4,
+                            "inue",
+                            id,
+                            TokenType::Continue,
+                        ),
+                        _ => TokenType::Identifier(Rc::new(RoxString::new(id))),
+                    },
+                    _ => TokenType::Identifier(Rc::new(RoxString::new(id))),
+                },
+                _ => TokenType::Identifier(Rc::new(RoxString::new(id))),
+            },
+            (.., 'd') => match id_chars.next().unwrap_or((0, '!')) {
+                (.., 'e') => {
+                    Scanner::check_keyword(&mut id_chars, 5, "fault", id, TokenType::Default)
                 }
-                (.., '!') => TokenType::Error(
-                    "Error grabbing next char after 'c' in scanning identifier.".to_string(),
-                ),
+                (.., 'o') => Scanner::check_keyword(&mut id_chars, 0, "", id, TokenType::Do),
                 _ => TokenType::Identifier(Rc::new(RoxString::new(id))),
             },
-            (.., 'd') => Scanner::check_keyword(&mut id_chars, 6, "efault", id, TokenType::Default),
             (.., 'e') => Scanner::check_keyword(&mut id_chars, 3, "lse", id, TokenType::Else),
             (.., 'i') => Scanner::check_keyword(&mut id_chars, 1, "f", id, TokenType::If),
             (.., 'n') => Scanner::check_keyword(&mut id_chars, 2, "il", id, TokenType::Nil),
@@ -107,28 +233,32 @@ impl Scanner {
                 (.., 'w') => {
                     Scanner::check_keyword(&mut id_chars, 4, "itch", id, TokenType::Switch)
                 }
-                (.., '!') => TokenType::Error(
-                    "Error grabbing next char after 's' in scanning identifier.".to_string(),
-                ),
                 _ => TokenType::Identifier(Rc::new(RoxString::new(id))),
             },
             (.., 'v') => Scanner::check_keyword(&mut id_chars, 2, "ar", id, TokenType::Var),
             (.., 'w') => Scanner::check_keyword(&mut id_chars, 4, "hile", id, TokenType::While),
             (.., 'f') => match id_chars.next().unwrap_or((0, '!')) {
                 (.., 'a') => Scanner::check_keyword(&mut id_chars, 3, "lse", id, TokenType::False),
+                (.., 'i') => {
+                    Scanner::check_keyword(&mut id_chars, 5, "nally", id, TokenType::Finally)
+                }
                 (.., 'o') => Scanner::check_keyword(&mut id_chars, 1, "r", id, TokenType::For),
                 (.., 'u') => Scanner::check_keyword(&mut id_chars, 1, "n", id, TokenType::Fun),
-                (.., '!') => TokenType::Error(
-                    "Error grabbing next char after 'f' in scanning identifier.".to_string(),
-                ),
                 _ => TokenType::Identifier(Rc::new(RoxString::new(id))),
             },
             (.., 't') => match id_chars.next().unwrap_or((0, '!')) {
-                (.., 'h') => Scanner::check_keyword(&mut id_chars, 2, "is", id, TokenType::This),
-                (.., 'r') => Scanner::check_keyword(&mut id_chars, 2, "ue", id, TokenType::True),
-                (.., '!') => TokenType::Error(
-                    "Error grabbing next char after 't' in scanning identifier".to_string(),
-                ),
+                (.., 'h') => match id_chars.next().unwrap_or((0, '!')) {
+                    (.., 'i') => Scanner::check_keyword(&mut id_chars, 1, "s", id, TokenType::This),
+                    (.., 'r') => {
+                        Scanner::check_keyword(&mut id_chars, 2, "ow", id, TokenType::Throw)
+                    }
+                    _ => TokenType::Identifier(Rc::new(RoxString::new(id))),
+                },
+                (.., 'r') => match id_chars.next().unwrap_or((0, '!')) {
+                    (.., 'u') => Scanner::check_keyword(&mut id_chars, 1, "e", id, TokenType::True),
+                    (.., 'y') => Scanner::check_keyword(&mut id_chars, 0, "", id, TokenType::Try),
+                    _ => TokenType::Identifier(Rc::new(RoxString::new(id))),
+                },
                 _ => TokenType::Identifier(Rc::new(RoxString::new(id))),
             },
             (.., '!') => {
@@ -150,7 +280,7 @@ impl Scanner {
             .map(|(_, c)| c.to_string())
             .collect::<String>();
 
-        if result == suffix && char_peeker.next() == None {
+        if result == suffix && char_peeker.next().is_none() {
             t_type
         } else {
             TokenType::Identifier(Rc::new(RoxString::new(original_str)))
@@ -160,58 +290,118 @@ impl Scanner {
     pub fn scan_tokens(&self, source: &str) -> TokenStream {
         let mut tokens: Vec<Token> = Vec::new();
         let mut num_lines = 1;
+        // position of an opening '/*' that has not been closed yet
+        let mut open_block_comment: Option<(usize, usize)> = None;
+        // for each open paren, whether it starts a statement header or parameter list
+        let mut header_parens: Vec<bool> = vec![];
+        let mut closed_header = false;
 
+        // a byte-order mark some editors write at the start of a file isn't source
+        let source = source.strip_prefix(BYTE_ORDER_MARK).unwrap_or(source);
         for (line_num, line) in source.lines().enumerate() {
             let mut line_chars: Peeker = line.char_indices().peekable();
+            if open_block_comment.is_some() && Scanner::skip_block_comment(&mut line_chars) {
+                open_block_comment = None;
+            }
             while let Some((char_num, ch)) = line_chars.next() {
                 let token_type = match ch {
                     ':' => TokenType::Colon,
-                    '(' => TokenType::LeftParen,
-                    ')' => TokenType::RightParen,
+                    '?' => TokenType::Question,
+                    '(' => {
+                        header_parens.push(Scanner::opens_header(&tokens));
+                        TokenType::LeftParen
+                    }
+                    ')' => {
+                        closed_header = header_parens.pop().unwrap_or(false);
+                        TokenType::RightParen
+                    }
                     '{' => TokenType::LeftBrace,
                     '}' => TokenType::RightBrace,
+                    '[' => TokenType::LeftBracket,
+                    ']' => TokenType::RightBracket,
                     ',' => TokenType::Comma,
                     ';' => TokenType::Semicolon,
                     '.' => {
+                        let mut ahead = line_chars.clone();
                         if line_chars.peek().unwrap_or(&(0, ' ')).1.is_numeric() {
                             while line_chars.next_if(|(_, c)| c.is_numeric()).is_some() {}
                             TokenType::Error(String::from(
                                 "Cannot begin a number in Rox with a dot.",
                             ))
+                        } else if matches!(
+                            (ahead.next(), ahead.next()),
+                            (Some((_, '.')), Some((_, '.')))
+                        ) {
+                            line_chars.nth(1);
+                            TokenType::DotDotDot
                         } else {
                             TokenType::Dot
                         }
                     }
-                    '-' => TokenType::Minus,
-                    '+' => TokenType::Plus,
-                    '*' => TokenType::Star,
-                    '!' => Scanner::check_next(
-                        &mut line_chars,
-                        '=',
-                        TokenType::Bang,
-                        TokenType::BangEqual,
-                    ),
-                    '=' => Scanner::check_next(
-                        &mut line_chars,
-                        '=',
-                        TokenType::Equal,
-                        TokenType::EqualEqual,
-                    ),
-                    '>' => Scanner::check_next(
+                    '-' => match line_chars.next_if(|(_, c)| *c == '-' || *c == '=') {
+                        Some((_, '-')) => TokenType::MinusMinus,
+                        Some(_) => TokenType::MinusEqual,
+                        None => TokenType::Minus,
+                    },
+                    '+' => match line_chars.next_if(|(_, c)| *c == '+' || *c == '=') {
+                        Some((_, '+')) => TokenType::PlusPlus,
+                        Some(_) => TokenType::PlusEqual,
+                        None => TokenType::Plus,
+                    },
+                    '*' => match line_chars.next_if(|(_, c)| *c == '*' || *c == '=') {
+                        Some((_, '*')) => Scanner::check_next(
+                            &mut line_chars,
+                            '=',
+                            TokenType::StarStar,
+                            TokenType::StarStarEqual,
+                        ),
+                        Some(_) => TokenType::StarEqual,
+                        None => TokenType::Star,
+                    },
+                    '%' => Scanner::check_next(
                         &mut line_chars,
                         '=',
-                        TokenType::Greater,
-                        TokenType::GreaterEqual,
+                        TokenType::Percent,
+                        TokenType::PercentEqual,
                     ),
-                    '<' => Scanner::check_next(
+                    '!' => Scanner::check_next(
                         &mut line_chars,
                         '=',
-                        TokenType::Less,
-                        TokenType::LessEqual,
+                        TokenType::Bang,
+                        TokenType::BangEqual,
                     ),
-                    ' ' | '\n' | '\t' | '\r' => continue, // skip whitespace
+                    '=' => match line_chars.next_if(|(_, c)| *c == '=') {
+                        Some(_) => Scanner::check_next(
+                            &mut line_chars,
+                            '=',
+                            TokenType::EqualEqual,
+                            TokenType::EqualEqualEqual,
+                        ),
+                        None => TokenType::Equal,
+                    },
+                    '>' => match line_chars.next_if(|(_, c)| *c == '=' || *c == '>') {
+                        Some((_, '=')) => TokenType::GreaterEqual,
+                        Some(_) => TokenType::GreaterGreater,
+                        None => TokenType::Greater,
+                    },
+                    '<' => match line_chars.next_if(|(_, c)| *c == '=' || *c == '<') {
+                        Some((_, '=')) => TokenType::LessEqual,
+                        Some(_) => TokenType::LessLess,
+                        None => TokenType::Less,
+                    },
+                    '&' => TokenType::Ampersand,
+                    '|' => TokenType::Pipe,
+                    '^' => TokenType::Caret,
+                    // a byte-order mark past the start of the file, say from
+                    // concatenated files, is skipped like whitespace
+                    ' ' | '\n' | '\t' | '\r' | BYTE_ORDER_MARK => continue, // skip whitespace
                     '/' => {
-                        if line_chars.peek().unwrap_or(&(0, ' ')).1 == '/' {
+                        if line_chars.peek().unwrap_or(&(0, ' ')).1 == '/'
+                            && Scanner::ends_operand(tokens.last(), line_num + 1, closed_header)
+                        {
+                            line_chars.next();
+                            TokenType::SlashSlash
+                        } else if line_chars.peek().unwrap_or(&(0, ' ')).1 == '/' {
                             for (_, c) in line_chars.by_ref() {
                                 match c {
                                     '\n' => break,
@@ -219,13 +409,24 @@ impl Scanner {
                                 }
                             }
                             continue;
+                        } else if line_chars.next_if(|(_, c)| *c == '*').is_some() {
+                            if !Scanner::skip_block_comment(&mut line_chars) {
+                                open_block_comment = Some((line_num + 1, char_num + 1));
+                            }
+                            continue;
+                        } else if line_chars.next_if(|(_, c)| *c == '=').is_some() {
+                            TokenType::SlashEqual
                         } else {
                             TokenType::Slash
                         }
                     }
                     '"' => Scanner::string(&mut line_chars),
+                    // r"..." is a raw string, while any other r starts an identifier
+                    'r' if line_chars.next_if(|(_, c)| *c == '"').is_some() => {
+                        Scanner::raw_string(&mut line_chars)
+                    }
                     '0'..='9' => Scanner::number(&mut line_chars, &ch),
-                    'a'..='z' | 'A'..='Z' => Scanner::identifier(&mut line_chars, &ch),
+                    'a'..='z' | 'A'..='Z' | '_' => Scanner::identifier(&mut line_chars, &ch),
                     _ => TokenType::Error(String::from("Unexpected char read from source")),
                 };
 
@@ -233,34 +434,145 @@ impl Scanner {
                     *self.had_error.borrow_mut() = true
                 }
 
-                tokens.push(self.scan_token(token_type, line_num + 1, char_num + 1));
+                // the token ends where the next unscanned character starts
+                let end = line_chars.peek().map_or(line.len(), |(index, _)| *index);
+                let length = line[char_num..end].chars().count();
+                tokens.push(self.scan_token(token_type, line_num + 1, char_num + 1, length));
             }
             num_lines += 1;
         }
 
+        // source ran out before the comment was closed
+        if let Some((line, column)) = open_block_comment {
+            *self.had_error.borrow_mut() = true;
+            tokens.push(self.scan_token(
+                TokenType::Error(String::from("Unterminated block comment")),
+                line,
+                column,
+                2,
+            ));
+        }
+
         // add token EOF sentinel for signaling end of scanner token stream
-        tokens.push(Token::new(TokenType::EOF, num_lines, 1));
+        tokens.push(self.scan_token(TokenType::EOF, num_lines, 1, 0));
 
-        if DEBUG_MODE {
-            tokens
-                .iter()
-                .for_each(|token| println!("Scanned Token: {}", token));
-        }
+        tokens.iter().for_each(|token| {
+            self.tracer
+                .scanner(format_args!("Scanned Token: {}", token))
+        });
         TokenStream::new(tokens)
     }
 
+    ///
+    /// Whether a paren about to be scanned opens the header of an if,
+    /// while, for or catch, or a parameter list, whose closing paren
+    /// is followed by a statement rather than an operator.
+    ///
+    fn opens_header(tokens: &[Token]) -> bool {
+        match tokens {
+            [.., last]
+                if matches!(
+                    last.token_type,
+                    TokenType::If
+                        | TokenType::While
+                        | TokenType::For
+                        | TokenType::Catch
+                        | TokenType::Fun
+                ) =>
+            {
+                true
+            }
+            [.., before, last] => {
+                matches!(last.token_type, TokenType::Identifier(_))
+                    && before.token_type == TokenType::Fun
+            }
+            _ => false,
+        }
+    }
+
+    ///
+    /// Whether a `//` after the given token is floor division rather
+    /// than a comment: it is when it follows an operand on the same
+    /// line, as in `7 // 2`. A comment after an operand needs a
+    /// statement between them, like the `;` in `x = 7; // note`, and
+    /// the paren closing a statement header or parameter list doesn't
+    /// end an operand, so `if (x) // note` is still a comment.
+    ///
+    fn ends_operand(previous: Option<&Token>, line: usize, closed_header: bool) -> bool {
+        match previous {
+            Some(token) if token.line == line => match token.token_type {
+                TokenType::Number(_)
+                | TokenType::StringLiteral(_)
+                | TokenType::Identifier(_)
+                | TokenType::True
+                | TokenType::False
+                | TokenType::Nil
+                | TokenType::RightBracket => true,
+                TokenType::RightParen => !closed_header,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
     pub fn had_error(&self) -> bool {
         *self.had_error.borrow()
     }
 
-    fn scan_token(&self, token_type: TokenType, line: usize, column: usize) -> Token {
-        Token::new(token_type, line, column)
+    fn scan_token(
+        &self,
+        token_type: TokenType,
+        line: usize,
+        column: usize,
+        length: usize,
+    ) -> Token {
+        Token::with_length(token_type, line, column, length)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[test]
+    fn test_literal_payloads() {
+        let scanner = Scanner::new();
+        let tokens = scanner.scan_tokens("count = 12.5 + \"hi\";");
+
+        let literals = tokens
+            .iter()
+            .filter_map(|token| match &token.token_type {
+                TokenType::Identifier(name) => Some(format!("identifier {}", name)),
+                TokenType::Number(number) => Some(format!("number {}", number)),
+                TokenType::StringLiteral(string) => Some(format!("string {}", string)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            literals,
+            vec!["identifier count", "number 12.5", "string hi"]
+        );
+        assert_eq!(
+            (tokens[2].line, tokens[2].column, tokens[2].length),
+            (1, 9, 4)
+        );
+    }
+
+    #[test]
+    fn test_strict_equality() {
+        let scanner = Scanner::new();
+        let tokens = scanner.scan_tokens("= == ===");
+
+        assert_eq!(
+            *tokens,
+            vec![
+                Token::new(TokenType::Equal, 1, 1),
+                Token::new(TokenType::EqualEqual, 1, 3),
+                Token::new(TokenType::EqualEqualEqual, 1, 6),
+                Token::new(TokenType::EOF, 2, 1),
+            ]
+        );
+    }
+
     #[test]
     fn test_binary_ops() {
         let scanner = Scanner::new();
@@ -282,17 +594,396 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_unterminated_string_at_eof() {
+        let scanner = Scanner::new();
+        let tokens = scanner.scan_tokens("print \"abc");
+
+        assert!(scanner.had_error());
+        assert_eq!(
+            *tokens,
+            vec![
+                Token::new(TokenType::Print, 1, 1),
+                Token::new(TokenType::Error(String::new()), 1, 7),
+                Token::new(TokenType::EOF, 2, 1),
+            ]
+        );
+        // tokens compare by type only, so check positions directly
+        let positions = tokens
+            .iter()
+            .map(|token| (token.line, token.column))
+            .collect::<Vec<_>>();
+        assert_eq!(positions, vec![(1, 1), (1, 7), (2, 1)]);
+    }
+
+    #[test]
+    fn test_unterminated_block_comment_at_eof() {
+        let scanner = Scanner::new();
+        let tokens = scanner.scan_tokens("1;\n/* never\nclosed");
+
+        assert!(scanner.had_error());
+        assert_eq!(
+            *tokens,
+            vec![
+                Token::new(TokenType::Number(RoxNumber(1.0)), 1, 1),
+                Token::new(TokenType::Semicolon, 1, 2),
+                Token::new(TokenType::Error(String::new()), 2, 1),
+                Token::new(TokenType::EOF, 4, 1),
+            ]
+        );
+        let positions = tokens
+            .iter()
+            .map(|token| (token.line, token.column))
+            .collect::<Vec<_>>();
+        assert_eq!(positions, vec![(1, 1), (1, 2), (2, 1), (4, 1)]);
+    }
+
+    #[test]
+    fn test_tokens_always_end_with_eof() {
+        let scanner = Scanner::new();
+        for source in [
+            "",
+            "\n\n",
+            "// comment",
+            "var a = 1;",
+            "\"open",
+            "/* open",
+            "@",
+        ] {
+            let tokens = scanner.scan_tokens(source);
+            assert_eq!(
+                tokens.last().map(|token| &token.token_type),
+                Some(&TokenType::EOF),
+                "Expected EOF at the end of {:?}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_token_lengths() {
+        let scanner = Scanner::new();
+        let tokens = scanner.scan_tokens("var >= \"abc\" 12.5 name;");
+
+        let spans = tokens
+            .iter()
+            .map(|token| (token.column, token.length))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            spans,
+            vec![(1, 3), (5, 2), (8, 5), (14, 4), (19, 4), (23, 1), (1, 0)]
+        );
+    }
+
+    #[test]
+    fn test_block_comment() {
+        let scanner = Scanner::new();
+        let tokens = scanner.scan_tokens("1 /* a\ncomment */ ;");
+
+        assert!(!scanner.had_error());
+        assert_eq!(
+            *tokens,
+            vec![
+                Token::new(TokenType::Number(RoxNumber(1.0)), 1, 1),
+                Token::new(TokenType::Semicolon, 2, 12),
+                Token::new(TokenType::EOF, 3, 1),
+            ]
+        );
+        let positions = tokens
+            .iter()
+            .map(|token| (token.line, token.column))
+            .collect::<Vec<_>>();
+        assert_eq!(positions, vec![(1, 1), (2, 12), (3, 1)]);
+    }
+
     #[test]
     fn test_string_literal() {}
 
     #[test]
     fn test_number_literal() {}
 
+    #[test]
+    fn test_hex_and_binary_literals() {
+        let scanner = Scanner::new();
+        let tokens = scanner.scan_tokens("0xFF 0b101 0x1f 0");
+
+        let numbers = tokens
+            .iter()
+            .filter_map(|token| match token.token_type {
+                TokenType::Number(RoxNumber(number)) => Some(number),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(numbers, vec![255.0, 5.0, 31.0, 0.0]);
+        assert_eq!(tokens.len(), 5);
+
+        for source in ["0xG", "0b12", "0x", "0b"] {
+            let tokens = scanner.scan_tokens(source);
+            assert!(
+                matches!(tokens[0].token_type, TokenType::Error(_)),
+                "Expected an error scanning {}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_number_digit_separators() {
+        let scanner = Scanner::new();
+        let tokens = scanner.scan_tokens("1_000 2.718_5");
+
+        // tokens compare by type only, so check the values directly
+        let numbers = tokens
+            .iter()
+            .filter_map(|token| match token.token_type {
+                TokenType::Number(RoxNumber(number)) => Some(number),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(numbers, vec![1000.0, 2.7185]);
+        assert_eq!(tokens.len(), 3);
+
+        for source in ["1__0", "_5", "1_", "1_.5", "1._5", "1_000_"] {
+            let tokens = scanner.scan_tokens(source);
+            assert!(
+                matches!(tokens[0].token_type, TokenType::Error(_)),
+                "Expected an error scanning {}",
+                source
+            );
+        }
+    }
+
     #[test]
     fn test_identifier() {}
 
     #[test]
-    fn test_keywords() {}
+    fn test_keywords() {
+        let scanner = Scanner::new();
+        let tokens = scanner.scan_tokens("try catch throw this true case tr thro catcher finally");
+
+        assert_eq!(
+            *tokens,
+            vec![
+                Token::new(TokenType::Try, 1, 1),
+                Token::new(TokenType::Catch, 1, 5),
+                Token::new(TokenType::Throw, 1, 11),
+                Token::new(TokenType::This, 1, 17),
+                Token::new(TokenType::True, 1, 22),
+                Token::new(TokenType::Case, 1, 27),
+                Token::new(TokenType::Identifier(Rc::new(RoxString::new("tr"))), 1, 32),
+                Token::new(
+                    TokenType::Identifier(Rc::new(RoxString::new("thro"))),
+                    1,
+                    35
+                ),
+                Token::new(
+                    TokenType::Identifier(Rc::new(RoxString::new("catcher"))),
+                    1,
+                    40
+                ),
+                Token::new(TokenType::Finally, 1, 48),
+                Token::new(TokenType::EOF, 2, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_const_keyword() {
+        let scanner = Scanner::new();
+        let tokens = scanner.scan_tokens("const continue cons conte");
+
+        assert_eq!(
+            *tokens,
+            vec![
+                Token::new(TokenType::Const, 1, 1),
+                Token::new(TokenType::Continue, 1, 7),
+                Token::new(
+                    TokenType::Identifier(Rc::new(RoxString::new("cons"))),
+                    1,
+                    16
+                ),
+                Token::new(
+                    TokenType::Identifier(Rc::new(RoxString::new("conte"))),
+                    1,
+                    21
+                ),
+                Token::new(TokenType::EOF, 2, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bitwise_operator_tokens() {
+        let scanner = Scanner::new();
+        let tokens = scanner.scan_tokens("<< >> <= >= < > & | ^");
+
+        assert_eq!(
+            *tokens,
+            vec![
+                Token::new(TokenType::LessLess, 1, 1),
+                Token::new(TokenType::GreaterGreater, 1, 4),
+                Token::new(TokenType::LessEqual, 1, 7),
+                Token::new(TokenType::GreaterEqual, 1, 10),
+                Token::new(TokenType::Less, 1, 13),
+                Token::new(TokenType::Greater, 1, 15),
+                Token::new(TokenType::Ampersand, 1, 17),
+                Token::new(TokenType::Pipe, 1, 19),
+                Token::new(TokenType::Caret, 1, 21),
+                Token::new(TokenType::EOF, 2, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_power_operator_token() {
+        let scanner = Scanner::new();
+        let tokens = scanner.scan_tokens("2 ** 3 * 4");
+
+        assert_eq!(
+            *tokens,
+            vec![
+                Token::new(TokenType::Number(RoxNumber(2.0)), 1, 1),
+                Token::new(TokenType::StarStar, 1, 3),
+                Token::new(TokenType::Number(RoxNumber(3.0)), 1, 6),
+                Token::new(TokenType::Star, 1, 8),
+                Token::new(TokenType::Number(RoxNumber(4.0)), 1, 10),
+                Token::new(TokenType::EOF, 2, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compound_assignment_tokens() {
+        let scanner = Scanner::new();
+        let tokens = scanner.scan_tokens("+= -= *= /= ** / = ++ -- %= **= %");
+
+        assert_eq!(
+            *tokens,
+            vec![
+                Token::new(TokenType::PlusEqual, 1, 1),
+                Token::new(TokenType::MinusEqual, 1, 4),
+                Token::new(TokenType::StarEqual, 1, 7),
+                Token::new(TokenType::SlashEqual, 1, 10),
+                Token::new(TokenType::StarStar, 1, 13),
+                Token::new(TokenType::Slash, 1, 16),
+                Token::new(TokenType::Equal, 1, 18),
+                Token::new(TokenType::PlusPlus, 1, 20),
+                Token::new(TokenType::MinusMinus, 1, 23),
+                Token::new(TokenType::PercentEqual, 1, 26),
+                Token::new(TokenType::StarStarEqual, 1, 29),
+                Token::new(TokenType::Percent, 1, 33),
+                Token::new(TokenType::EOF, 2, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_floor_division_and_comment_tokens() {
+        let scanner = Scanner::new();
+        let tokens = scanner.scan_tokens("7 // 2\nx; // note\nif (x) // note\n// note");
+        let types = tokens
+            .iter()
+            .map(|token| token.token_type.clone())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            types,
+            vec![
+                TokenType::Number(RoxNumber(7.0)),
+                TokenType::SlashSlash,
+                TokenType::Number(RoxNumber(2.0)),
+                TokenType::Identifier(Rc::new(RoxString::new("x"))),
+                TokenType::Semicolon,
+                TokenType::If,
+                TokenType::LeftParen,
+                TokenType::Identifier(Rc::new(RoxString::new("x"))),
+                TokenType::RightParen,
+                TokenType::EOF,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_raw_and_escaped_strings() {
+        let scanner = Scanner::new();
+        let tokens = scanner.scan_tokens(r#"r"a\nb" "a\nb" "q\"\\" r rx"#);
+        let literals = tokens
+            .iter()
+            .filter_map(|token| match &token.token_type {
+                TokenType::Identifier(name) => Some(format!("identifier {}", name)),
+                TokenType::StringLiteral(string) => Some(format!("string {}", string)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            literals,
+            vec![
+                "string a\\nb",
+                "string a\nb",
+                "string q\"\\",
+                "identifier r",
+                "identifier rx",
+            ]
+        );
+        assert_eq!(tokens.len(), 6);
+        assert!(matches!(
+            scanner.scan_tokens(r#""a\qb""#)[0].token_type,
+            TokenType::Error(_)
+        ));
+    }
+
+    #[test]
+    fn test_byte_order_mark_is_skipped() {
+        let scanner = Scanner::new();
+        let source = "var x = 1;\nprint x;";
+        let with_bom = scanner.scan_tokens(&format!("\u{FEFF}{}", source));
+        let without_bom = scanner.scan_tokens(source);
+
+        let positions = |tokens: &TokenStream| {
+            tokens
+                .iter()
+                .map(|token| (token.line, token.column))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(with_bom, without_bom);
+        assert_eq!(positions(&with_bom), positions(&without_bom));
+        assert!(!scanner.had_error());
+
+        let mid_file = scanner.scan_tokens("var x\u{FEFF}= 1;");
+        assert_eq!(mid_file, scanner.scan_tokens("var x = 1;"));
+    }
+
+    #[test]
+    fn test_rest_parameter_tokens() {
+        let scanner = Scanner::new();
+        let tokens = scanner.scan_tokens("...rest .. .");
+
+        assert_eq!(
+            *tokens,
+            vec![
+                Token::new(TokenType::DotDotDot, 1, 1),
+                Token::new(TokenType::Identifier(Rc::new(RoxString::new("rest"))), 1, 4),
+                Token::new(TokenType::Dot, 1, 9),
+                Token::new(TokenType::Dot, 1, 10),
+                Token::new(TokenType::Dot, 1, 12),
+                Token::new(TokenType::EOF, 2, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_single_letter_identifiers() {
+        let scanner = Scanner::new();
+        let tokens = scanner.scan_tokens("c f s t");
+
+        for (token, name) in tokens.iter().zip(["c", "f", "s", "t"]) {
+            match &token.token_type {
+                TokenType::Identifier(id) => assert_eq!(&***id, name),
+                other => panic!("Expected identifier {}, got {:?}", name, other),
+            }
+        }
+    }
 
     #[test]
     fn test_error_tokens() {}
@@ -0,0 +1,573 @@
+use crate::{
+    Chunk, ChunkError, ObjectList, ObjectType, OpCode, RoxFunction, RoxNumber, RoxObject,
+    RoxString, Table, Upvalue, Value,
+};
+use std::cell::RefCell;
+use std::io::{self, Read};
+use std::rc::Rc;
+
+/// Marks the start of every serialized chunk.
+const MAGIC: &[u8; 4] = b"ROXC";
+
+/// Bumped whenever the binary format changes.
+pub const CHUNK_FORMAT_VERSION: u8 = 4;
+
+const CONSTANT_NIL: u8 = 0;
+const CONSTANT_BOOLEAN: u8 = 1;
+const CONSTANT_NUMBER: u8 = 2;
+const CONSTANT_STRING: u8 = 3;
+const CONSTANT_FUNCTION: u8 = 4;
+
+impl Chunk {
+    ///
+    /// Writes the chunk's instructions, constants and line table to a
+    /// byte buffer that deserialize reads back. Functions are written
+    /// along with their own chunks.
+    ///
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(CHUNK_FORMAT_VERSION);
+        self.write_body(&mut bytes);
+        bytes
+    }
+
+    ///
+    /// Reads a chunk written by serialize into a new chunk with its own
+    /// object list and string pool. Buffers from another format version
+    /// are rejected.
+    ///
+    pub fn deserialize(bytes: &[u8]) -> Result<Chunk, ChunkError> {
+        let mut remaining = bytes;
+        let chunk = Chunk::read_from(&mut remaining)?;
+
+        if !remaining.is_empty() {
+            return Err(ChunkError::TrailingBytes);
+        }
+        Ok(chunk)
+    }
+
+    ///
+    /// Reads a chunk written by serialize from a stream, such as a file,
+    /// without loading the whole buffer first. Reading stops at the end
+    /// of the chunk, so the stream may carry data after it.
+    ///
+    pub fn read_from<R: Read>(source: R) -> Result<Chunk, ChunkError> {
+        let mut reader = Reader { source };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(ChunkError::BadMagic);
+        }
+
+        let version = reader.u8()?;
+        if version != CHUNK_FORMAT_VERSION {
+            return Err(ChunkError::UnsupportedVersion(version));
+        }
+
+        let mut chunk = Chunk::new(
+            Rc::new(RefCell::new(ObjectList::new())),
+            Rc::new(RefCell::new(Table::new())),
+            Rc::new(RefCell::new(Table::new())),
+        );
+        chunk.read_body(&mut reader)?;
+        Ok(chunk)
+    }
+
+    fn write_body(&self, bytes: &mut Vec<u8>) {
+        write_usize(bytes, self.code.len());
+        for (opcode, line) in self.code.iter().zip(&self.lines) {
+            write_opcode(bytes, opcode);
+            write_usize(bytes, *line);
+        }
+
+        write_usize(bytes, self.constants.values.len());
+        for constant in &self.constants.values {
+            write_constant(bytes, constant);
+        }
+    }
+
+    fn read_body<R: Read>(&mut self, reader: &mut Reader<R>) -> Result<(), ChunkError> {
+        for _ in 0..reader.usize()? {
+            let opcode = reader.opcode()?;
+            let line = reader.usize()?;
+            self.write_chunk(opcode, line);
+        }
+
+        for _ in 0..reader.usize()? {
+            let constant = self.read_constant(reader)?;
+            let (_, value_ref) = self.constants.write_value(constant, None);
+            if let Value::Object(obj) = value_ref {
+                self.objects.borrow_mut().add_object(obj);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_constant<R: Read>(&self, reader: &mut Reader<R>) -> Result<Value, ChunkError> {
+        let constant = match reader.u8()? {
+            CONSTANT_NIL => Value::Nil,
+            CONSTANT_BOOLEAN => Value::Boolean(reader.u8()? != 0),
+            CONSTANT_NUMBER => {
+                let bits = u32::from_le_bytes(reader.array()?);
+                Value::Number(RoxNumber(f32::from_bits(bits)))
+            }
+            CONSTANT_STRING => {
+                let string = self.intern(reader.string()?);
+                Value::Object(RoxObject::new(ObjectType::ObjString(string)))
+            }
+            CONSTANT_FUNCTION => {
+                let name = reader.string()?;
+                let arity = reader.usize()?;
+                let upvalues = (0..reader.usize()?)
+                    .map(|_| {
+                        Ok(Upvalue {
+                            is_local: reader.u8()? != 0,
+                            index: reader.usize()?,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, ChunkError>>()?;
+                let defaults = (0..reader.usize()?)
+                    .map(|_| self.read_constant(reader))
+                    .collect::<Result<Vec<_>, ChunkError>>()?;
+                let variadic = reader.u8()? != 0;
+                let mut chunk = self.new_function_chunk();
+                chunk.read_body(reader)?;
+
+                let mut function =
+                    RoxFunction::with_upvalues(name, arity, Rc::new(RefCell::new(chunk)), upvalues);
+                function.defaults = defaults;
+                function.variadic = variadic;
+                Value::Object(RoxObject::new(ObjectType::ObjFunction(Rc::new(function))))
+            }
+            tag => return Err(ChunkError::InvalidConstant(tag)),
+        };
+
+        Ok(constant)
+    }
+}
+
+fn write_usize(bytes: &mut Vec<u8>, value: usize) {
+    bytes.extend_from_slice(&(value as u64).to_le_bytes());
+}
+
+fn write_offset(bytes: &mut Vec<u8>, offset: Option<usize>) {
+    match offset {
+        Some(offset) => {
+            bytes.push(1);
+            write_usize(bytes, offset);
+        }
+        None => bytes.push(0),
+    }
+}
+
+fn write_string(bytes: &mut Vec<u8>, string: &RoxString) {
+    write_usize(bytes, string.length());
+    bytes.extend_from_slice(string.as_bytes());
+}
+
+fn write_constant(bytes: &mut Vec<u8>, constant: &Value) {
+    match constant {
+        Value::Nil => bytes.push(CONSTANT_NIL),
+        Value::Boolean(boolean) => {
+            bytes.push(CONSTANT_BOOLEAN);
+            bytes.push(*boolean as u8);
+        }
+        Value::Number(RoxNumber(number)) => {
+            bytes.push(CONSTANT_NUMBER);
+            bytes.extend_from_slice(&number.to_bits().to_le_bytes());
+        }
+        Value::Object(RoxObject {
+            object_type: ObjectType::ObjString(string),
+            ..
+        }) => {
+            bytes.push(CONSTANT_STRING);
+            write_string(bytes, string);
+        }
+        Value::Object(RoxObject {
+            object_type: ObjectType::ObjFunction(function),
+            ..
+        }) => {
+            bytes.push(CONSTANT_FUNCTION);
+            write_string(bytes, &function.name);
+            write_usize(bytes, function.arity);
+            write_usize(bytes, function.upvalues.len());
+            for upvalue in &function.upvalues {
+                bytes.push(upvalue.is_local as u8);
+                write_usize(bytes, upvalue.index);
+            }
+            write_usize(bytes, function.defaults.len());
+            for default in &function.defaults {
+                write_constant(bytes, default);
+            }
+            bytes.push(function.variadic as u8);
+            function.chunk.borrow().write_body(bytes);
+        }
+        // the compiler only ever emits the constants above
+        _ => panic!("Cannot serialize constant {}", constant),
+    }
+}
+
+///
+/// Writes an opcode as a one byte tag followed by its operands. New
+/// opcodes take the next free tag, and existing tags must not change
+/// without bumping CHUNK_FORMAT_VERSION.
+///
+fn write_opcode(bytes: &mut Vec<u8>, opcode: &OpCode) {
+    let (tag, operand) = match opcode {
+        OpCode::OpReturn(count) => (0, Some(*count)),
+        OpCode::OpConstant(index) => (1, Some(*index)),
+        OpCode::OpConstantLong(index) => (2, Some(*index)),
+        OpCode::OpNegate => (3, None),
+        OpCode::OpAdd => (4, None),
+        OpCode::OpSubtract => (5, None),
+        OpCode::OpMultiply => (6, None),
+        OpCode::OpDivide => (7, None),
+        OpCode::OpModulo => (8, None),
+        OpCode::OpPower => (9, None),
+        OpCode::OpBitAnd => (10, None),
+        OpCode::OpBitOr => (11, None),
+        OpCode::OpBitXor => (12, None),
+        OpCode::OpShl => (13, None),
+        OpCode::OpShr => (14, None),
+        OpCode::OpNil => (15, None),
+        OpCode::OpTrue => (16, None),
+        OpCode::OpFalse => (17, None),
+        OpCode::OpNot => (18, None),
+        OpCode::OpEqual => (19, None),
+        OpCode::OpGreater => (20, None),
+        OpCode::OpLess => (21, None),
+        OpCode::OpPrint => (22, None),
+        OpCode::OpPop => (23, None),
+        OpCode::OpDefineGlobal(index) => (24, Some(*index)),
+        OpCode::OpGetGlobal(index) => (25, Some(*index)),
+        OpCode::OpSetGlobal(index) => (26, Some(*index)),
+        OpCode::OpGetLocal(index) => (27, Some(*index)),
+        OpCode::OpSetLocal(index) => (28, Some(*index)),
+        OpCode::OpJumpIfFalse(offset) => {
+            bytes.push(29);
+            write_offset(bytes, *offset);
+            return;
+        }
+        OpCode::OpJump(offset) => {
+            bytes.push(30);
+            write_offset(bytes, *offset);
+            return;
+        }
+        OpCode::OpLoop(offset) => (31, Some(*offset)),
+        OpCode::OpEcho => (32, None),
+        OpCode::OpPushLine => (33, None),
+        OpCode::OpPushHandler(catch_offset, finally_offset) => {
+            bytes.push(34);
+            write_offset(bytes, *catch_offset);
+            write_offset(bytes, *finally_offset);
+            return;
+        }
+        OpCode::OpPopHandler => (35, None),
+        OpCode::OpLeaveTry => (36, None),
+        OpCode::OpEndFinally => (37, None),
+        OpCode::OpThrow => (38, None),
+        OpCode::OpCall(arg_count) => (39, Some(*arg_count)),
+        OpCode::OpGetProperty(index) => (40, Some(*index)),
+        OpCode::OpBuildArray(count) => (41, Some(*count)),
+        OpCode::OpIndexGet => (42, None),
+        OpCode::OpIndexSet => (43, None),
+        OpCode::OpPopN(count) => (44, Some(*count)),
+        OpCode::OpClosure => (45, None),
+        OpCode::OpGetUpvalue(index) => (46, Some(*index)),
+        OpCode::OpSetUpvalue(index) => (47, Some(*index)),
+        OpCode::OpCloseUpvalue => (48, None),
+        OpCode::OpUnaryPlus => (49, None),
+        OpCode::OpFloorDiv => (50, None),
+        OpCode::OpStrictEqual => (51, None),
+    };
+
+    bytes.push(tag);
+    if let Some(operand) = operand {
+        write_usize(bytes, operand);
+    }
+}
+
+/// Reads the parts of a serialized chunk from a stream.
+struct Reader<R> {
+    source: R,
+}
+
+impl<R: Read> Reader<R> {
+    fn take(&mut self, length: usize) -> Result<Vec<u8>, ChunkError> {
+        // read through take so a corrupt length can't allocate a huge buffer up front
+        let mut taken = vec![];
+        (&mut self.source)
+            .take(length as u64)
+            .read_to_end(&mut taken)
+            .map_err(|error| ChunkError::Io(error.kind()))?;
+        if taken.len() != length {
+            return Err(ChunkError::UnexpectedEnd);
+        }
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], ChunkError> {
+        let mut array = [0; N];
+        self.source
+            .read_exact(&mut array)
+            .map_err(|error| match error.kind() {
+                io::ErrorKind::UnexpectedEof => ChunkError::UnexpectedEnd,
+                kind => ChunkError::Io(kind),
+            })?;
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, ChunkError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn usize(&mut self) -> Result<usize, ChunkError> {
+        usize::try_from(u64::from_le_bytes(self.array()?)).map_err(|_| ChunkError::UnexpectedEnd)
+    }
+
+    fn offset(&mut self) -> Result<Option<usize>, ChunkError> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => Ok(Some(self.usize()?)),
+        }
+    }
+
+    fn string(&mut self) -> Result<RoxString, ChunkError> {
+        let length = self.usize()?;
+        let string =
+            String::from_utf8(self.take(length)?).map_err(|_| ChunkError::InvalidString)?;
+        Ok(RoxString::new(&string))
+    }
+
+    fn opcode(&mut self) -> Result<OpCode, ChunkError> {
+        let opcode = match self.u8()? {
+            0 => OpCode::OpReturn(self.usize()?),
+            1 => OpCode::OpConstant(self.usize()?),
+            2 => OpCode::OpConstantLong(self.usize()?),
+            3 => OpCode::OpNegate,
+            4 => OpCode::OpAdd,
+            5 => OpCode::OpSubtract,
+            6 => OpCode::OpMultiply,
+            7 => OpCode::OpDivide,
+            8 => OpCode::OpModulo,
+            9 => OpCode::OpPower,
+            10 => OpCode::OpBitAnd,
+            11 => OpCode::OpBitOr,
+            12 => OpCode::OpBitXor,
+            13 => OpCode::OpShl,
+            14 => OpCode::OpShr,
+            15 => OpCode::OpNil,
+            16 => OpCode::OpTrue,
+            17 => OpCode::OpFalse,
+            18 => OpCode::OpNot,
+            19 => OpCode::OpEqual,
+            20 => OpCode::OpGreater,
+            21 => OpCode::OpLess,
+            22 => OpCode::OpPrint,
+            23 => OpCode::OpPop,
+            24 => OpCode::OpDefineGlobal(self.usize()?),
+            25 => OpCode::OpGetGlobal(self.usize()?),
+            26 => OpCode::OpSetGlobal(self.usize()?),
+            27 => OpCode::OpGetLocal(self.usize()?),
+            28 => OpCode::OpSetLocal(self.usize()?),
+            29 => OpCode::OpJumpIfFalse(self.offset()?),
+            30 => OpCode::OpJump(self.offset()?),
+            31 => OpCode::OpLoop(self.usize()?),
+            32 => OpCode::OpEcho,
+            33 => OpCode::OpPushLine,
+            34 => OpCode::OpPushHandler(self.offset()?, self.offset()?),
+            35 => OpCode::OpPopHandler,
+            36 => OpCode::OpLeaveTry,
+            37 => OpCode::OpEndFinally,
+            38 => OpCode::OpThrow,
+            39 => OpCode::OpCall(self.usize()?),
+            40 => OpCode::OpGetProperty(self.usize()?),
+            41 => OpCode::OpBuildArray(self.usize()?),
+            42 => OpCode::OpIndexGet,
+            43 => OpCode::OpIndexSet,
+            44 => OpCode::OpPopN(self.usize()?),
+            45 => OpCode::OpClosure,
+            46 => OpCode::OpGetUpvalue(self.usize()?),
+            47 => OpCode::OpSetUpvalue(self.usize()?),
+            48 => OpCode::OpCloseUpvalue,
+            49 => OpCode::OpUnaryPlus,
+            50 => OpCode::OpFloorDiv,
+            51 => OpCode::OpStrictEqual,
+            tag => return Err(ChunkError::InvalidOpcode(tag)),
+        };
+
+        Ok(opcode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Compiler, Scanner, Tracer};
+
+    fn compile(source: &str) -> Chunk {
+        let chunk = Rc::new(RefCell::new(Chunk::new(
+            Rc::new(RefCell::new(ObjectList::new())),
+            Rc::new(RefCell::new(Table::new())),
+            Rc::new(RefCell::new(Table::new())),
+        )));
+        {
+            let tokens = Scanner::new().scan_tokens(source);
+            let compiler = Compiler::new(
+                Rc::clone(&chunk),
+                RefCell::new(tokens.iter().peekable()),
+                Tracer::default(),
+            );
+            assert!(compiler.compile());
+        }
+
+        Rc::try_unwrap(chunk).unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_round_trip_keeps_disassembly() {
+        let chunk = compile("1 + 2 * 3;");
+        let restored = Chunk::deserialize(&chunk.serialize()).unwrap();
+
+        assert_eq!(restored.disassemble("test"), chunk.disassemble("test"));
+    }
+
+    #[test]
+    fn test_round_trip_with_strings_jumps_and_functions() {
+        let chunk = compile(
+            "var s = \"hi\"; fun f(a) { if (a) return nil; return true; } while (false) {} f(s);",
+        );
+        let restored = Chunk::deserialize(&chunk.serialize()).unwrap();
+
+        assert_eq!(restored.disassemble("test"), chunk.disassemble("test"));
+        assert_eq!(
+            function_disassembly(&restored),
+            function_disassembly(&chunk)
+        );
+        assert!(!function_disassembly(&chunk).is_empty());
+    }
+
+    #[test]
+    fn test_round_trip_keeps_captured_variables() {
+        let chunk = compile("{ var a = 1; var b = 2; fun f() { return b + a; } }");
+        let restored = Chunk::deserialize(&chunk.serialize()).unwrap();
+
+        let upvalues = function_upvalues(&chunk);
+        assert_eq!(
+            upvalues,
+            vec![vec![
+                Upvalue {
+                    index: 1,
+                    is_local: true
+                },
+                Upvalue {
+                    index: 0,
+                    is_local: true
+                },
+            ]]
+        );
+        assert_eq!(function_upvalues(&restored), upvalues);
+    }
+
+    #[test]
+    fn test_round_trip_keeps_parameter_defaults() {
+        let chunk = compile("fun f(a, b = 2, c = \"c\") { return a; }");
+        let restored = Chunk::deserialize(&chunk.serialize()).unwrap();
+
+        let defaults = |chunk: &Chunk| {
+            chunk
+                .constants
+                .values
+                .iter()
+                .filter_map(|constant| match constant {
+                    Value::Object(RoxObject {
+                        object_type: ObjectType::ObjFunction(function),
+                        ..
+                    }) => Some(
+                        function
+                            .defaults
+                            .iter()
+                            .map(Value::repr)
+                            .collect::<Vec<_>>(),
+                    ),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(defaults(&chunk), vec![vec!["2", "\"c\""]]);
+        assert_eq!(defaults(&restored), defaults(&chunk));
+    }
+
+    fn function_upvalues(chunk: &Chunk) -> Vec<Vec<Upvalue>> {
+        chunk
+            .constants
+            .values
+            .iter()
+            .filter_map(|constant| match constant {
+                Value::Object(RoxObject {
+                    object_type: ObjectType::ObjFunction(function),
+                    ..
+                }) => Some(function.upvalues.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn function_disassembly(chunk: &Chunk) -> Vec<String> {
+        chunk
+            .constants
+            .values
+            .iter()
+            .filter_map(|constant| match constant {
+                Value::Object(RoxObject {
+                    object_type: ObjectType::ObjFunction(function),
+                    ..
+                }) => Some(function.chunk.borrow().disassemble(&function.name)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_reading_from_a_stream_matches_deserialize() {
+        let chunk = compile("var s = \"hi\"; fun f(a) { return a + 1; } f(2);");
+        let mut bytes = chunk.serialize();
+        let from_bytes = Chunk::deserialize(&bytes).unwrap();
+
+        // data after the chunk is left in the stream
+        bytes.extend_from_slice(b"rest");
+        let mut cursor = io::Cursor::new(bytes);
+        let from_stream = Chunk::read_from(&mut cursor).unwrap();
+
+        assert_eq!(
+            from_stream.disassemble("test"),
+            from_bytes.disassemble("test")
+        );
+        assert_eq!(
+            function_disassembly(&from_stream),
+            function_disassembly(&from_bytes)
+        );
+        let mut rest = vec![];
+        cursor.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"rest");
+    }
+
+    #[test]
+    fn test_rejects_bad_buffers() {
+        let mut bytes = compile("1;").serialize();
+
+        assert_eq!(
+            Chunk::deserialize(b"nope").unwrap_err(),
+            ChunkError::BadMagic
+        );
+        assert_eq!(
+            Chunk::deserialize(&bytes[..bytes.len() - 1]).unwrap_err(),
+            ChunkError::UnexpectedEnd
+        );
+
+        bytes[MAGIC.len()] = CHUNK_FORMAT_VERSION + 1;
+        assert_eq!(
+            Chunk::deserialize(&bytes).unwrap_err(),
+            ChunkError::UnsupportedVersion(CHUNK_FORMAT_VERSION + 1)
+        );
+    }
+}
@@ -34,16 +34,16 @@ impl FromIterator<Token> for TokenStream {
     }
 }
 
-impl<'a> TokenStream {
+impl TokenStream {
     pub fn new(tokens: Vec<Token>) -> TokenStream {
         TokenStream(tokens)
     }
 
-    pub fn iter(&self) -> std::slice::Iter<Token> {
+    pub fn iter(&self) -> std::slice::Iter<'_, Token> {
         self.0.iter()
     }
 
-    pub fn iter_mut(&mut self) -> std::slice::IterMut<Token> {
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, Token> {
         self.0.iter_mut()
     }
 }
@@ -53,6 +53,8 @@ pub struct Token {
     pub token_type: TokenType,
     pub line: usize,
     pub column: usize,
+    // the number of source characters the token spans
+    pub length: usize,
 }
 
 impl std::fmt::Display for Token {
@@ -67,20 +69,33 @@ impl std::fmt::Display for Token {
     }
 }
 
+/// Tokens compare as same_type does, ignoring positions and literal
+/// values. Compare the fields to check those.
 impl std::cmp::PartialEq for Token {
     fn eq(&self, other: &Self) -> bool {
-        self.token_type == other.token_type
+        self.same_type(other)
     }
 }
 
 impl Token {
+    /// A token spanning a single character.
     pub fn new(token_type: TokenType, line: usize, column: usize) -> Token {
+        Token::with_length(token_type, line, column, 1)
+    }
+
+    pub fn with_length(token_type: TokenType, line: usize, column: usize, length: usize) -> Token {
         Token {
             token_type,
             line,
             column,
+            length,
         }
     }
+
+    /// Whether both tokens are of the same kind, whatever their positions or literal values.
+    pub fn same_type(&self, other: &Token) -> bool {
+        std::mem::discriminant(&self.token_type) == std::mem::discriminant(&other.token_type)
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -122,6 +137,11 @@ impl std::ops::Mul<RoxNumber> for RoxNumber {
     }
 }
 
+///
+/// Divides with IEEE semantics, so a zero divisor gives an infinity or
+/// NaN. The VM never lets that happen: dividing by zero in a program
+/// is a runtime error, checked before this is called.
+///
 impl std::ops::Div<RoxNumber> for RoxNumber {
     type Output = Self;
 
@@ -130,6 +150,14 @@ impl std::ops::Div<RoxNumber> for RoxNumber {
     }
 }
 
+impl std::ops::Rem<RoxNumber> for RoxNumber {
+    type Output = Self;
+
+    fn rem(self, rhs: RoxNumber) -> Self::Output {
+        RoxNumber(self.0 % rhs.0)
+    }
+}
+
 impl std::cmp::Eq for RoxNumber {}
 
 impl std::fmt::Display for RoxNumber {
@@ -152,6 +180,8 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
     Minus,
@@ -159,15 +189,34 @@ pub enum TokenType {
     Semicolon,
     Slash,
     Star,
+    StarStar,
+    Percent,
+    Question,
+    Ampersand,
+    Pipe,
+    Caret,
     // One or two character tokens.
     Bang,
     BangEqual,
     Equal,
     EqualEqual,
+    EqualEqualEqual,
     Greater,
     GreaterEqual,
     Less,
     LessEqual,
+    LessLess,
+    GreaterGreater,
+    PlusPlus,
+    MinusMinus,
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
+    PercentEqual,
+    StarStarEqual,
+    DotDotDot,
+    SlashSlash,
     // Literals.
     Identifier(Rc<RoxString>),
     StringLiteral(Rc<RoxString>),
@@ -175,6 +224,7 @@ pub enum TokenType {
     // Keywords.
     And,
     Class,
+    Const,
     Else,
     False,
     For,
@@ -192,6 +242,13 @@ pub enum TokenType {
 
     Break,
     Continue,
+    Do,
+
+    // exceptions
+    Try,
+    Catch,
+    Finally,
+    Throw,
 
     // switch
     Colon,
@@ -208,3 +265,30 @@ impl PartialEq for TokenType {
         std::mem::discriminant(self) == std::mem::discriminant(other)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_type_ignores_position_and_value() {
+        let identifier = |name| TokenType::Identifier(Rc::new(RoxString::new(name)));
+        let first = Token::with_length(identifier("x"), 1, 1, 1);
+        let second = Token::with_length(identifier("x"), 4, 9, 1);
+
+        assert!(first.same_type(&second));
+        assert!(first.same_type(&Token::new(identifier("y"), 1, 1)));
+        assert!(!first.same_type(&Token::new(TokenType::Dot, 1, 1)));
+    }
+
+    #[test]
+    fn equality_ignores_position() {
+        let x = || TokenType::Identifier(Rc::new(RoxString::new("x")));
+        let first = Token::with_length(x(), 1, 1, 1);
+        let second = Token::with_length(x(), 4, 9, 1);
+
+        assert_eq!(first, second);
+        assert_ne!((first.line, first.column), (second.line, second.column));
+        assert_ne!(first, Token::new(TokenType::Dot, 1, 1));
+    }
+}
@@ -0,0 +1,81 @@
+use crate::RcMut;
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+///
+/// Selects which stages of the interpreter write trace output.
+/// The default leaves every stage off.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TraceConfig {
+    pub trace_scanner: bool,
+    pub trace_compiler: bool,
+    pub trace_execution: bool,
+}
+
+impl TraceConfig {
+    /// Traces every stage of the interpreter.
+    pub fn all() -> TraceConfig {
+        TraceConfig {
+            trace_scanner: true,
+            trace_compiler: true,
+            trace_execution: true,
+        }
+    }
+}
+
+///
+/// Writes the trace output enabled by a TraceConfig to a sink that
+/// is shared by the scanner, compiler and VM. Messages are passed as
+/// format arguments so nothing is formatted for a disabled stage.
+///
+#[derive(Clone)]
+pub struct Tracer {
+    config: TraceConfig,
+    sink: RcMut<dyn Write>,
+}
+
+impl Tracer {
+    pub fn new(config: TraceConfig, sink: RcMut<dyn Write>) -> Tracer {
+        Tracer { config, sink }
+    }
+
+    pub fn config(&self) -> TraceConfig {
+        self.config
+    }
+
+    pub fn scanner(&self, message: fmt::Arguments) {
+        self.write(self.config.trace_scanner, message);
+    }
+
+    pub fn compiler(&self, message: fmt::Arguments) {
+        self.write(self.config.trace_compiler, message);
+    }
+
+    pub fn execution(&self, message: fmt::Arguments) {
+        self.write(self.config.trace_execution, message);
+    }
+
+    fn write(&self, enabled: bool, message: fmt::Arguments) {
+        if enabled {
+            // tracing is best effort and must never fail the program
+            let _ = writeln!(self.sink.borrow_mut(), "{}", message);
+        }
+    }
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Tracer::new(TraceConfig::default(), Rc::new(RefCell::new(io::stdout())))
+    }
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tracer")
+            .field("config", &self.config)
+            .finish()
+    }
+}
@@ -1,5 +1,9 @@
-use crate::{ObjectType, RoxMap, RoxNumber, RoxObject, RoxString, Table, DEBUG_MODE};
+use crate::{ObjectType, RoxMap, RoxNumber, RoxObject, RoxString, Table};
 use std::ops;
+use std::rc::Rc;
+
+/// Longest string, in bytes, that repeating or padding a string may build.
+pub const MAX_REPEAT_LEN: usize = 1 << 24;
 
 #[derive(Debug, Default)]
 pub struct Values {
@@ -35,35 +39,27 @@ impl Values {
     ) -> (usize, &mut Value) {
         // keep a globals map so as not to duplicate globals in values array
         if let Some(global_indices) = global_indices {
-            if let Value::Object(obj) = &value {
-                match &obj.object_type {
-                    ObjectType::ObjString(rox_string) => match global_indices.get(rox_string) {
-                        Some(idx) => {
-                            if DEBUG_MODE {
-                                println!("Global indices: {:?}", global_indices);
-                                println!("Values array: {:?}", self.values);
-                            }
-                            let found_global = self.values.get_mut(*idx).unwrap_or_else(|| {
-                                panic!("Error finding global '{}' at index {}", rox_string, idx,)
-                            });
-                            return (*idx, found_global);
-                        }
-                        None => {
-                            self.values.push(value.clone());
-                            self.count += 1;
-                            let index = self.count - 1;
-                            if DEBUG_MODE {
-                                println!("Setting global {} to index {}", rox_string, index);
-                                println!("Values array: {:?}", self.values);
-                            }
-
-                            let value_ref = self.values.get_mut(index).unwrap();
-
-                            global_indices.set(rox_string, &index);
-                            return (index, value_ref);
-                        }
-                    },
-                    _ => (),
+            if let Value::Object(RoxObject {
+                object_type: ObjectType::ObjString(rox_string),
+                ..
+            }) = &value
+            {
+                match global_indices.get(rox_string) {
+                    Some(idx) => {
+                        let found_global = self.values.get_mut(*idx).unwrap_or_else(|| {
+                            panic!("Error finding global '{}' at index {}", rox_string, idx,)
+                        });
+                        return (*idx, found_global);
+                    }
+                    None => {
+                        self.values.push(value.clone());
+                        self.count += 1;
+                        let index = self.count - 1;
+                        let value_ref = self.values.get_mut(index).unwrap();
+
+                        global_indices.set(rox_string, &index);
+                        return (index, value_ref);
+                    }
                 }
             };
         }
@@ -83,6 +79,17 @@ impl PartialOrd for Value {
                 Value::Number(other_num) => self_num.partial_cmp(other_num),
                 _ => None,
             },
+            // strings are ordered lexicographically
+            Value::Object(RoxObject {
+                object_type: ObjectType::ObjString(self_str),
+                ..
+            }) => match other {
+                Value::Object(RoxObject {
+                    object_type: ObjectType::ObjString(other_str),
+                    ..
+                }) => self_str.partial_cmp(other_str),
+                _ => None,
+            },
             _ => None,
         }
     }
@@ -100,13 +107,24 @@ impl PartialEq for Value {
                 _ => false,
             },
             Value::Nil => matches!(other, Value::Nil),
-            Value::Object(obj) => match &obj.object_type {
-                ObjectType::ObjString(string_one) => match other {
-                    Value::Object(obj_two) => match &obj_two.object_type {
-                        ObjectType::ObjString(string_two) => string_one == string_two,
-                    },
-                    _ => false,
-                },
+            Value::Object(obj) => match other {
+                Value::Object(obj_two) => {
+                    // objects of different types are never equal
+                    if std::mem::discriminant(&obj.object_type)
+                        != std::mem::discriminant(&obj_two.object_type)
+                    {
+                        return false;
+                    }
+
+                    match (&obj.object_type, &obj_two.object_type) {
+                        // interned strings share an allocation
+                        (ObjectType::ObjString(string_one), ObjectType::ObjString(string_two)) => {
+                            Rc::ptr_eq(string_one, string_two) || string_one == string_two
+                        }
+                        (type_one, type_two) => type_one == type_two,
+                    }
+                }
+                _ => false,
             },
             _ => false,
         }
@@ -158,10 +176,47 @@ impl ops::Sub<Value> for Value {
     }
 }
 
+///
+/// Number multiplication, or repetition of a string by a count on
+/// either side. A count that isn't a non-negative integer is an error,
+/// as is one that would build a string longer than MAX_REPEAT_LEN.
+///
 impl ops::Mul<Value> for Value {
     type Output = Value;
 
     fn mul(self, rhs: Value) -> Self::Output {
+        match (self, rhs) {
+            (Value::Number(lhs), Value::Number(rhs)) => Value::Number(lhs * rhs),
+            (
+                Value::Object(RoxObject {
+                    object_type: ObjectType::ObjString(string),
+                    ..
+                }),
+                Value::Number(RoxNumber(count)),
+            )
+            | (
+                Value::Number(RoxNumber(count)),
+                Value::Object(RoxObject {
+                    object_type: ObjectType::ObjString(string),
+                    ..
+                }),
+            ) if count >= 0.0
+                && count.fract() == 0.0
+                && string.len() as f64 * count as f64 <= MAX_REPEAT_LEN as f64 =>
+            {
+                let repeated = RoxString::new(&string.repeat(count as usize));
+                Value::Object(RoxObject::new(ObjectType::ObjString(Rc::new(repeated))))
+            }
+            _ => Value::Error,
+        }
+    }
+}
+
+/// Number division, which the VM guards against zero divisors; see RoxNumber.
+impl ops::Div<Value> for Value {
+    type Output = Value;
+
+    fn div(self, rhs: Value) -> Self::Output {
         let lhs = match self {
             Value::Number(num) => num,
             _ => return Value::Error,
@@ -171,14 +226,14 @@ impl ops::Mul<Value> for Value {
             _ => return Value::Error,
         };
 
-        Value::Number(lhs * rhs)
+        Value::Number(lhs / rhs)
     }
 }
 
-impl ops::Div<Value> for Value {
+impl ops::Rem<Value> for Value {
     type Output = Value;
 
-    fn div(self, rhs: Value) -> Self::Output {
+    fn rem(self, rhs: Value) -> Self::Output {
         let lhs = match self {
             Value::Number(num) => num,
             _ => return Value::Error,
@@ -188,18 +243,204 @@ impl ops::Div<Value> for Value {
             _ => return Value::Error,
         };
 
-        Value::Number(lhs / rhs)
+        Value::Number(lhs % rhs)
+    }
+}
+
+impl Value {
+    /// The name of the value's type as Rox programs see it.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::Boolean(_) => "bool",
+            Value::Nil => "nil",
+            Value::Object(obj) => match obj.object_type {
+                ObjectType::ObjString(_) => "string",
+                ObjectType::ObjFunction(_)
+                | ObjectType::ObjClosure(_)
+                | ObjectType::ObjNative(_) => "function",
+                ObjectType::ObjError(_) => "error",
+                ObjectType::ObjArray(_) => "array",
+            },
+            Value::Error => "error",
+        }
+    }
+
+    ///
+    /// Only nil and false are falsey; every other value, including
+    /// zero and the empty string, is truthy.
+    ///
+    pub fn is_falsey(&self) -> bool {
+        matches!(self, Value::Boolean(false) | Value::Nil)
+    }
+
+    ///
+    /// A representation for debugging and REPL echo. Unlike Display,
+    /// strings are quoted and escaped so they can't be mistaken for
+    /// other values.
+    ///
+    pub fn repr(&self) -> String {
+        match self {
+            Value::Object(obj) => match &obj.object_type {
+                ObjectType::ObjString(string) => string.escaped(),
+                ObjectType::ObjFunction(function) => function.to_string(),
+                ObjectType::ObjClosure(closure) => closure.to_string(),
+                ObjectType::ObjNative(native) => native.to_string(),
+                ObjectType::ObjError(error) => error.to_string(),
+                ObjectType::ObjArray(array) => array.to_string(),
+            },
+            _ => self.to_string(),
+        }
+    }
+
+    ///
+    /// The text written by a print statement. Strings are written as
+    /// they are; every other value is written as its repr.
+    ///
+    pub fn print_repr(&self) -> String {
+        match self {
+            Value::Object(obj) => match &obj.object_type {
+                ObjectType::ObjString(string) => string.to_string(),
+                _ => self.repr(),
+            },
+            _ => self.repr(),
+        }
     }
 }
 
 impl std::fmt::Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Value::Number(num) => write!(f, "{}", num.to_string()),
-            Value::Boolean(b) => write!(f, "{}", b.to_string()),
+            Value::Number(num) => write!(f, "{}", num),
+            Value::Boolean(b) => write!(f, "{}", b),
             Value::Nil => write!(f, "nil"),
             Value::Object(obj) => write!(f, "Object<{}>", obj),
             Value::Error => write!(f, "Value<Error>"),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RoxArray;
+    use std::cmp::Ordering;
+
+    fn string(s: &str) -> Value {
+        Value::Object(RoxObject::new(ObjectType::ObjString(Rc::new(
+            RoxString::new(s),
+        ))))
+    }
+
+    #[test]
+    fn test_string_ordering() {
+        assert!(string("abc") < string("abd"));
+        assert!(string("abd") > string("abc"));
+        assert!(string("ab") < string("abc"));
+        assert!(string("B") < string("a"));
+        assert_eq!(
+            string("abc").partial_cmp(&string("abc")),
+            Some(Ordering::Equal)
+        );
+    }
+
+    #[test]
+    fn test_number_string_ordering_is_none() {
+        let num = Value::Number(RoxNumber(1.0));
+
+        assert_eq!(num.partial_cmp(&string("1")), None);
+        assert_eq!(string("1").partial_cmp(&num), None);
+    }
+
+    #[test]
+    fn test_repr_quotes_and_escapes_strings() {
+        let value = string("say \"hi\"\n\\");
+
+        assert_eq!(value.repr(), "\"say \\\"hi\\\"\\n\\\\\"");
+        assert_eq!(value.to_string(), "Object<str = say \"hi\"\n\\>");
+    }
+
+    #[test]
+    fn test_repr_of_literals() {
+        assert_eq!(Value::Nil.repr(), "nil");
+        assert_eq!(Value::Boolean(true).repr(), "true");
+        assert_eq!(Value::Boolean(false).repr(), "false");
+        assert_eq!(
+            Value::Number(RoxNumber(1.5)).repr(),
+            Value::Number(RoxNumber(1.5)).to_string()
+        );
+    }
+
+    #[test]
+    fn test_object_equality() {
+        let array = Value::Object(RoxObject::new(ObjectType::ObjArray(Rc::new(
+            RoxArray::new(vec![]),
+        ))));
+
+        assert_eq!(string("abc"), string("abc"));
+        assert_ne!(string("abc"), string("abd"));
+        assert_ne!(string("1"), Value::Number(RoxNumber(1.0)));
+        assert_ne!(string(""), Value::Nil);
+        assert_ne!(Value::Nil, string(""));
+        assert_ne!(array, Value::Nil);
+        assert_ne!(array, string("[]"));
+        assert_eq!(array, array.clone());
+    }
+
+    #[test]
+    fn test_nil_equality_has_no_coercion() {
+        assert_eq!(Value::Nil, Value::Nil);
+        for other in [
+            Value::Number(RoxNumber(0.0)),
+            Value::Boolean(false),
+            string(""),
+            Value::Error,
+        ] {
+            assert_ne!(Value::Nil, other);
+            assert_ne!(other, Value::Nil);
+        }
+    }
+
+    #[test]
+    fn test_error_is_never_equal() {
+        assert_ne!(Value::Error, Value::Error);
+        for other in [
+            Value::Nil,
+            Value::Number(RoxNumber(0.0)),
+            Value::Boolean(false),
+            string(""),
+        ] {
+            assert_ne!(Value::Error, other);
+            assert_ne!(other, Value::Error);
+        }
+    }
+
+    #[test]
+    fn test_string_repetition() {
+        let three = Value::Number(RoxNumber(3.0));
+        assert_eq!(string("ab") * three.clone(), string("ababab"));
+        assert_eq!(three * string("ab"), string("ababab"));
+        assert!(matches!(
+            string("x") * Value::Number(RoxNumber(-1.0)),
+            Value::Error
+        ));
+        assert!(matches!(
+            string("x") * Value::Number(RoxNumber(1.5)),
+            Value::Error
+        ));
+        assert!(matches!(
+            string("ab") * Value::Number(RoxNumber((MAX_REPEAT_LEN / 2 + 1) as f32)),
+            Value::Error
+        ));
+        assert!(matches!(string("x") * string("y"), Value::Error));
+    }
+
+    #[test]
+    fn test_is_falsey() {
+        assert!(Value::Nil.is_falsey());
+        assert!(Value::Boolean(false).is_falsey());
+        assert!(!Value::Boolean(true).is_falsey());
+        assert!(!Value::Number(RoxNumber(0.0)).is_falsey());
+        assert!(!string("").is_falsey());
+    }
+}
@@ -1,55 +1,432 @@
 use crate::Chunk;
 use crate::Compiler;
+use crate::Globals;
+use crate::Hook;
+use crate::HookEvent;
+use crate::NativeFn;
 use crate::ObjectList;
 use crate::ObjectType;
 use crate::OpCode;
+use crate::PrintCallback;
 use crate::RcMut;
-use crate::RoxMap;
+use crate::RoxArray;
+use crate::RoxClosure;
+use crate::RoxError;
+use crate::RoxFunction;
+use crate::RoxNative;
+use crate::RoxNumber;
 use crate::RoxObject;
 use crate::RoxString;
+use crate::RoxStringBuilder;
+use crate::RoxUpvalue;
+use crate::RuntimeError;
 use crate::Scanner;
 use crate::Stack;
 use crate::Table;
+use crate::TraceConfig;
+use crate::Tracer;
 use crate::Value;
-use crate::DEBUG_MODE;
-use crate::{InterpretError, InterpretOk, InterpretResult};
+use crate::FRAMES_MAX;
+use crate::SCRIPT_NAME;
+use crate::{arity_message, native, InterpretError, InterpretOk, InterpretResult, MAX_REPEAT_LEN};
 use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::panic::{self, AssertUnwindSafe};
 use std::rc::Rc;
 
+/// Frames kept at each end of a call trace, which elides those between.
+const TRACE_EDGE_FRAMES: usize = 8;
+
+/// What source passed to the VM holds, which decides how it's compiled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SourceKind {
+    Program,
+    Repl, // a program whose top-level expression statements are echoed
+    Expression,
+}
+
+/// An installed handler for a try block. The catch ip is cleared once
+/// the catch block is entered, so only its finally block remains.
+#[derive(Debug, Clone, Copy)]
+struct Handler {
+    catch_ip: Option<usize>,
+    finally_ip: Option<usize>,
+    stack_size: usize,
+    frame_count: usize,
+}
+
+/// Why a finally block was entered other than by falling into it. The
+/// kind is pushed above the pending value and resumed by OpEndFinally.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Completion {
+    Throw = 1,
+    Error = 2,
+    Return = 3,
+    Jump = 4,
+}
+
+impl Completion {
+    fn to_value(self) -> Value {
+        Value::Number(RoxNumber(self as u8 as f32))
+    }
+
+    /// Decodes a pending kind, where nil means the try exited normally.
+    fn from_value(value: &Value) -> Option<Completion> {
+        match value {
+            Value::Number(RoxNumber(kind)) if *kind == 1.0 => Some(Completion::Throw),
+            Value::Number(RoxNumber(kind)) if *kind == 2.0 => Some(Completion::Error),
+            Value::Number(RoxNumber(kind)) if *kind == 3.0 => Some(Completion::Return),
+            Value::Number(RoxNumber(kind)) if *kind == 4.0 => Some(Completion::Jump),
+            _ => None,
+        }
+    }
+}
+
+/// The state of a single function invocation. The ip is only
+/// kept up to date for frames that are suspended by a call.
+#[derive(Debug)]
+struct CallFrame {
+    name: RoxString,
+    chunk: RcMut<Chunk>,
+    ip: usize,
+    slot_base: usize,
+    // the variables captured by the closure being called
+    upvalues: Vec<RcMut<RoxUpvalue>>,
+}
+
 pub struct VM {
     pub chunk: RcMut<Chunk>,
     ip: RefCell<usize>,
     stack: RefCell<Stack>,
+    frames: RefCell<Vec<CallFrame>>,
     scanner: Scanner,
     objects: Rc<RefCell<ObjectList>>,
-    globals: RcMut<Table<RoxString, Value>>,
-    global_indices: RcMut<Table<RoxString, usize>>,
+    globals: RefCell<Globals>,
+    handlers: RefCell<Vec<Handler>>,
+    hook: RefCell<Option<Hook>>,
+    print_callback: RefCell<Option<PrintCallback>>,
+    last_line: RefCell<Option<usize>>,
+    // upvalues still pointing at stack slots, ordered by slot
+    open_upvalues: RefCell<Vec<RcMut<RoxUpvalue>>>,
+    tracer: Tracer,
+    output: RcMut<dyn Write>,
+    // read by readline, which uses stdin when no input is set
+    input: Option<RcMut<dyn BufRead>>,
+    // how many times each opcode has executed, when profiling is enabled
+    profile: RefCell<Option<HashMap<&'static str, u64>>>,
 }
 
 impl VM {
+    ///
+    /// Creates a VM with every kind of tracing turned off.
+    ///
     pub fn new() -> VM {
+        VM::with_trace(TraceConfig::default(), Rc::new(RefCell::new(io::stdout())))
+    }
+
+    ///
+    /// Creates a VM that writes the trace output selected by the
+    /// config to the given sink.
+    ///
+    pub fn with_trace(config: TraceConfig, sink: RcMut<dyn Write>) -> VM {
+        let tracer = Tracer::new(config, sink);
         let objects = Rc::new(RefCell::new(ObjectList::new()));
         let global_indices = Rc::new(RefCell::new(Table::new()));
+        let strings = Rc::new(RefCell::new(Table::new()));
         let chunk = Rc::new(RefCell::new(Chunk::new(
             Rc::clone(&objects),
-            Rc::clone(&global_indices),
+            global_indices,
+            strings,
         )));
-        VM {
+        let vm = VM {
             chunk: Rc::clone(&chunk),
             ip: RefCell::new(0),
             stack: RefCell::new(Stack::new()),
-            scanner: Scanner::new(),
+            frames: RefCell::new(vec![]),
+            scanner: Scanner::with_tracer(tracer.clone()),
             objects: Rc::clone(&objects),
-            globals: Rc::new(RefCell::new(Table::new())),
-            global_indices: Rc::clone(&global_indices),
-        }
+            globals: RefCell::new(Globals::new()),
+            handlers: RefCell::new(vec![]),
+            hook: RefCell::new(None),
+            print_callback: RefCell::new(None),
+            last_line: RefCell::new(None),
+            open_upvalues: RefCell::new(vec![]),
+            tracer,
+            output: Rc::new(RefCell::new(io::stdout())),
+            input: None,
+            profile: RefCell::new(None),
+        };
+
+        vm.define_native("clock", 0, native::clock);
+        vm.define_native("Error", 1, native::error);
+        vm.define_native("len", 1, native::len);
+        vm.define_native("char_at", 2, native::char_at);
+        vm.define_native("defined", 1, native::defined);
+        vm.define_native("typeof", 1, native::type_of);
+        vm.define_native_with_optional("assert", 2, 1, native::assert);
+        vm.define_native("readline", 0, native::readline);
+        vm.define_native("num", 1, native::num);
+        vm.define_native("between", 3, native::between);
+        vm.define_native("str", 1, native::str);
+        vm.define_native("pad", 2, native::pad);
+        vm
+    }
+
+    ///
+    /// Registers a Rust function as a global callable from Rox code.
+    ///
+    pub fn define_native(&self, name: &str, arity: usize, function: NativeFn) {
+        let name = RoxString::new(name);
+        let native = RoxNative::new(name.clone(), arity, function);
+        let value = Value::Object(RoxObject::new(ObjectType::ObjNative(Rc::new(native))));
+        self.globals.borrow_mut().define(&name, value);
+    }
+
+    ///
+    /// Registers a Rust function whose last optional arguments may be
+    /// left off. The function is given only the arguments passed.
+    ///
+    pub fn define_native_with_optional(
+        &self,
+        name: &str,
+        arity: usize,
+        optional: usize,
+        function: NativeFn,
+    ) {
+        let name = RoxString::new(name);
+        let native = RoxNative::with_optional(name.clone(), arity, optional, function);
+        let value = Value::Object(RoxObject::new(ObjectType::ObjNative(Rc::new(native))));
+        self.globals.borrow_mut().define(&name, value);
+    }
+
+    /// Whether a global variable with the given name has been defined.
+    pub fn is_global_defined(&self, name: &RoxString) -> bool {
+        self.globals.borrow().contains(name)
     }
 
+    ///
+    /// Clears the code and execution state so the VM can run new source.
+    /// Globals and allocated objects are kept so later code can use them.
+    ///
     pub fn reset(&mut self) {
         *(self.ip.borrow_mut()) = 0;
+        *(self.last_line.borrow_mut()) = None;
         self.chunk.borrow_mut().reset();
-        self.objects.borrow_mut().reset();
         self.stack.borrow_mut().reset_stack();
+        self.frames.borrow_mut().clear();
+        self.handlers.borrow_mut().clear();
+        self.open_upvalues.borrow_mut().clear();
+    }
+
+    ///
+    /// Sends the output of print statements and REPL echo to the given
+    /// sink instead of stdout.
+    ///
+    pub fn set_output(&mut self, output: RcMut<dyn Write>) {
+        self.output = output;
+    }
+
+    ///
+    /// Reads the input for readline from the given source instead
+    /// of stdin.
+    ///
+    pub fn set_input(&mut self, input: RcMut<dyn BufRead>) {
+        self.input = Some(input);
+    }
+
+    ///
+    /// Reads one line from the input without its line ending,
+    /// returning None at the end of the input.
+    ///
+    pub fn read_line(&self) -> Result<Option<String>, RuntimeError> {
+        let mut line = String::new();
+        let read = match &self.input {
+            Some(input) => input.borrow_mut().read_line(&mut line),
+            None => io::stdin().read_line(&mut line),
+        }
+        .map_err(|err| RuntimeError::new(&format!("Failed to read input: {}", err)))?;
+
+        if read == 0 {
+            return Ok(None);
+        }
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(Some(line))
+    }
+
+    ///
+    /// Starts counting how many times each opcode executes, discarding
+    /// any earlier counts.
+    ///
+    pub fn enable_profiling(&self) {
+        self.profile.replace(Some(HashMap::new()));
+    }
+
+    /// The number of times each opcode has executed, or None if profiling isn't enabled.
+    pub fn profile(&self) -> Option<HashMap<&'static str, u64>> {
+        self.profile.borrow().clone()
+    }
+
+    /// The tracer this VM writes its trace output through.
+    pub fn tracer(&self) -> &Tracer {
+        &self.tracer
+    }
+
+    ///
+    /// Registers an instrumentation hook that is called for every
+    /// HookEvent raised while the VM runs, replacing any previous hook.
+    ///
+    pub fn set_hook(&self, hook: Hook) {
+        *self.hook.borrow_mut() = Some(hook);
+    }
+
+    ///
+    /// Registers a callback that is given each printed value as well as
+    /// writing it to the output, replacing any previous callback.
+    ///
+    pub fn on_print(&self, callback: PrintCallback) {
+        *self.print_callback.borrow_mut() = Some(callback);
+    }
+
+    fn call_hook(&self, event: HookEvent) {
+        if let Some(hook) = self.hook.borrow_mut().as_mut() {
+            hook(&event);
+        }
+    }
+
+    fn track_line(&self, ip: usize) {
+        if self.hook.borrow().is_none() {
+            return;
+        }
+
+        let line = self.current_chunk().borrow().line_at(ip);
+        let last_line = self.last_line.borrow_mut().replace(line);
+        if last_line != Some(line) {
+            self.call_hook(HookEvent::LineChange { line });
+        }
+    }
+
+    /// The chunk of the function executing in the topmost call frame.
+    fn current_chunk(&self) -> RcMut<Chunk> {
+        match self.frames.borrow().last() {
+            Some(frame) => Rc::clone(&frame.chunk),
+            None => Rc::clone(&self.chunk),
+        }
+    }
+
+    /// The stack index of the first slot of the topmost call frame.
+    fn slot_base(&self) -> usize {
+        self.frames
+            .borrow()
+            .last()
+            .map_or(0, |frame| frame.slot_base)
+    }
+
+    fn call_value(&self, arg_count: usize) -> Result<(), InterpretError> {
+        let callee = self.stack.borrow().peek(arg_count)?;
+        match callee {
+            Value::Object(RoxObject {
+                object_type: ObjectType::ObjFunction(function),
+                ..
+            }) => self.call(function, arg_count, vec![]),
+            Value::Object(RoxObject {
+                object_type: ObjectType::ObjClosure(closure),
+                ..
+            }) => self.call(
+                Rc::clone(&closure.function),
+                arg_count,
+                closure.upvalues.clone(),
+            ),
+            Value::Object(RoxObject {
+                object_type: ObjectType::ObjNative(native),
+                ..
+            }) => self.call_native(&native, arg_count),
+            _ => Err(RuntimeError::new("Can only call functions.").into()),
+        }
+    }
+
+    fn call_native(&self, native: &RoxNative, arg_count: usize) -> Result<(), InterpretError> {
+        if !native.accepts(arg_count) {
+            return Err(RuntimeError::new(&arity_message(
+                native.arity,
+                native.optional,
+                arg_count,
+            ))
+            .into());
+        }
+
+        let mut args = (0..arg_count)
+            .map(|_| self.stack.borrow_mut().pop())
+            .collect::<Result<Vec<Value>, _>>()?;
+        args.reverse();
+
+        // the native may use the VM, so the stack isn't borrowed while it runs
+        let result = (native.function)(self, &args)?;
+
+        // replace the callee with the result
+        let mut stack = self.stack.borrow_mut();
+        stack.pop()?;
+        stack.push(result);
+
+        Ok(())
+    }
+
+    fn call(
+        &self,
+        function: Rc<RoxFunction>,
+        arg_count: usize,
+        upvalues: Vec<RcMut<RoxUpvalue>>,
+    ) -> Result<(), InterpretError> {
+        if !function.accepts(arg_count) {
+            return Err(RuntimeError::new(&function.arity_error(arg_count)).into());
+        }
+        // overflow before touching the stack, so the error sees the call as made
+        if self.frames.borrow().len() >= FRAMES_MAX {
+            return Err(RuntimeError::new("Stack overflow.").into());
+        }
+        if function.variadic {
+            // bundle the surplus arguments into the rest parameter's array
+            let mut stack = self.stack.borrow_mut();
+            let mut rest = (function.min_arity()..arg_count)
+                .map(|_| stack.pop())
+                .collect::<Result<Vec<Value>, _>>()?;
+            rest.reverse();
+
+            let mut array = RoxObject::new(ObjectType::ObjArray(Rc::new(RoxArray::new(rest))));
+            self.objects.borrow_mut().add_object(&mut array);
+            stack.push(Value::Object(array));
+        } else {
+            // fill in the parameters left off with their defaults
+            for default in &function.defaults[arg_count - function.min_arity()..] {
+                self.stack.borrow_mut().push(default.clone());
+            }
+        }
+
+        // save where the caller resumes once the call returns
+        let mut frames = self.frames.borrow_mut();
+        if let Some(caller) = frames.last_mut() {
+            caller.ip = self.ip.replace(0);
+        }
+        frames.push(CallFrame {
+            name: function.name.clone(),
+            chunk: Rc::clone(&function.chunk),
+            ip: 0,
+            slot_base: self.stack.borrow().size - function.arity - 1,
+            upvalues,
+        });
+        drop(frames);
+
+        self.call_hook(HookEvent::FunctionEnter {
+            name: function.name.to_string(),
+        });
+
+        Ok(())
     }
 
     fn read_byte(code: &[OpCode], ip: usize) -> Option<OpCode> {
@@ -60,19 +437,41 @@ impl VM {
         values.get(index).cloned()
     }
 
-    fn read_string(values: &[Value], str_id_index: usize) -> RoxString {
+    fn global_name(&self, name_index: usize) -> Rc<RoxString> {
+        VM::read_string(&self.current_chunk().borrow().constants.values, name_index)
+    }
+
+    ///
+    /// Finds the slot of the global named by a constant in the current
+    /// chunk. The slot is cached on the chunk the first time, so later
+    /// accesses through the same constant skip hashing the name.
+    ///
+    fn global_slot(&self, name_index: usize) -> Result<usize, InterpretError> {
+        let chunk = self.current_chunk();
+        if let Some(slot) = chunk.borrow().cached_global_slot(name_index) {
+            return Ok(slot);
+        }
+
+        let name = self.global_name(name_index);
+        let slot = self
+            .globals
+            .borrow()
+            .slot(&name)
+            .ok_or_else(|| RuntimeError::new(&format!("Undefined variable '{}'.", name)))?;
+        chunk.borrow_mut().cache_global_slot(name_index, slot);
+        Ok(slot)
+    }
+
+    fn read_string(values: &[Value], str_id_index: usize) -> Rc<RoxString> {
         let string_id = VM::read_constant(values, str_id_index).unwrap_or_else(|| {
             panic!("String id constant at index {str_id_index} did not return expected value!")
         });
 
         match string_id {
-            Value::Object(obj) => match obj.object_type {
-                ObjectType::ObjString(string) => string,
-                _ => panic!(
-                    "Error Object String type was not located at index {}",
-                    str_id_index
-                ),
-            },
+            Value::Object(RoxObject {
+                object_type: ObjectType::ObjString(string),
+                ..
+            }) => string,
             _ => panic!(
                 "Error Value object was not located at index {}",
                 str_id_index
@@ -87,295 +486,1045 @@ impl VM {
         current_ip
     }
 
+    ///
+    /// Describes each active call frame, innermost first, with the line
+    /// it was executing. Callers are reported at the line of their call.
+    /// Deep traces keep the innermost and outermost frames and note how
+    /// many were left out between them.
+    ///
+    fn call_trace(&self, current_ip: usize) -> Vec<String> {
+        let frames = self.frames.borrow();
+        let mut trace = frames
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(depth, frame)| {
+                let ip = if depth == 0 {
+                    current_ip
+                } else {
+                    frame.ip.saturating_sub(1)
+                };
+                let line = frame.chunk.borrow().line_at(ip);
+                if frames.len() - depth == 1 {
+                    format!("[line {}] in {}", line, frame.name)
+                } else {
+                    format!("[line {}] in {}()", line, frame.name)
+                }
+            })
+            .collect::<Vec<_>>();
+
+        if trace.len() > 2 * TRACE_EDGE_FRAMES {
+            let omitted = trace.len() - 2 * TRACE_EDGE_FRAMES;
+            trace.splice(
+                TRACE_EDGE_FRAMES..TRACE_EDGE_FRAMES + omitted,
+                [format!("... {} frames omitted", omitted)],
+            );
+        }
+        trace
+    }
+
+    ///
+    /// The line of the call that made the executing function, so helpers
+    /// reading __line__ report where they were called. Top-level code
+    /// has no caller and gets the line of the instruction itself.
+    ///
+    fn call_site_line(&self, current_ip: usize) -> usize {
+        let frames = self.frames.borrow();
+        match frames.len().checked_sub(2).map(|index| &frames[index]) {
+            Some(caller) => caller.chunk.borrow().line_at(caller.ip.saturating_sub(1)),
+            None => self.current_chunk().borrow().line_at(current_ip),
+        }
+    }
+
+    /// Pops the value left by the script, or nil if it left nothing.
+    fn script_result(&self) -> InterpretOk {
+        InterpretOk(self.stack.borrow_mut().pop().unwrap_or(Value::Nil))
+    }
+
     fn run(&self) -> InterpretResult {
         loop {
+            // the script's own frame has returned
+            if self.frames.borrow().is_empty() {
+                return Ok(self.script_result());
+            }
+
             let current_ip = self.incr_ip();
 
             // read next instruction
-            let instruction = match VM::read_byte(&self.chunk.borrow().code, current_ip) {
+            let chunk = self.current_chunk();
+            let instruction = match VM::read_byte(&chunk.borrow().code, current_ip) {
                 Some(instr) => instr,
                 None => {
-                    if DEBUG_MODE {
-                        println!("Finished executing opcodes, finishing...");
-                    }
-                    return Ok(InterpretOk);
+                    self.tracer
+                        .execution(format_args!("Finished executing opcodes, finishing..."));
+                    return Ok(self.script_result());
                 }
             };
 
-            if DEBUG_MODE {
-                Chunk::disassemble_instruction(&instruction, current_ip, &self.chunk.borrow());
-                println!(" {}", *self.stack.borrow());
+            if self.tracer.config().trace_execution {
+                let (disassembly, _) = self
+                    .current_chunk()
+                    .borrow()
+                    .disassemble_instruction(current_ip);
+                self.tracer
+                    .execution(format_args!("{} {}", disassembly, *self.stack.borrow()));
             }
 
-            match instruction {
-                OpCode::OpReturn(_) => {
-                    // Nothing for now
-                    //let val = self.stack.borrow_mut().pop()?;
-                    //if DEBUG_MODE {
-                    //    println!("Popped: {}", val);
-                    //} else {
-                    //    println!("{}", val);
-                    //}
-                }
-                OpCode::OpPop => {
-                    self.stack.borrow_mut().pop()?;
-                }
-                OpCode::OpConstant(constants_index) => {
-                    let constant =
-                        VM::read_constant(&self.chunk.borrow().constants.values, constants_index)
-                            .unwrap_or_else(|| {
-                                panic!(
-                                    "Constant at IP {} did not return expected value!",
-                                    current_ip
-                                )
-                            });
-                    self.stack.borrow_mut().push(constant);
-                }
-                OpCode::OpDefineGlobal(str_id_index) => {
-                    let string_id =
-                        VM::read_string(&self.chunk.borrow().constants.values, str_id_index);
+            self.track_line(current_ip);
 
-                    if DEBUG_MODE {
-                        println!("Added id {string_id} to globals table");
-                    }
+            if let Some(profile) = self.profile.borrow_mut().as_mut() {
+                *profile.entry(instruction.name()).or_default() += 1;
+            }
 
-                    let global_rhs = self.stack.borrow().peek(0)?;
-                    self.globals.borrow_mut().set(&string_id, &global_rhs);
-                    self.stack.borrow_mut().pop()?;
-                }
-                OpCode::OpSetGlobal(str_id_index) => {
-                    let string_id =
-                        VM::read_string(&self.chunk.borrow().constants.values, str_id_index);
-
-                    let rhs = self.stack.borrow().peek(0)?;
-                    if !self.globals.borrow_mut().get_and_set(&string_id, &rhs) {
-                        return Err(InterpretError::RuntimeError(format!(
-                            "Undefined variable {}",
-                            string_id
-                        )));
-                    }
-                    if DEBUG_MODE {
-                        println!("Set global id {string_id} to {rhs}.");
-                    }
-                }
-                OpCode::OpGetGlobal(str_id_index) => {
-                    let string_id =
-                        VM::read_string(&self.chunk.borrow().constants.values, str_id_index);
-
-                    if let Some(value) = self.globals.borrow_mut().get(&string_id) {
-                        self.stack.borrow_mut().push(value.clone());
-                    } else {
-                        return Err(InterpretError::RuntimeError(format!(
-                            "Undefined variable '{}'.",
-                            string_id
-                        )));
-                    }
+            let name = instruction.name();
+            let result = self
+                .execute(instruction, current_ip)
+                .and_then(|()| self.check_for_error_value(name));
+            if let Err(error) = result {
+                // errors are reported at the line of the faulting instruction
+                let line = chunk.borrow().line_at(current_ip);
+                self.unwind(error.at_line(line))
+                    .map_err(|error| error.with_trace(self.call_trace(current_ip)))?;
+            }
+        }
+    }
 
-                    if DEBUG_MODE {
-                        println!("Read global id {string_id} from globals table");
-                    }
-                }
-                OpCode::OpGetLocal(index) => {
-                    if let Err(msg) = self.stack.borrow_mut().get_and_push_local(index) {
-                        return Err(InterpretError::RuntimeError(msg.to_string()));
-                    }
+    ///
+    /// Raises an error when the instruction just run left Value::Error
+    /// on the stack, so it's reported where it was made instead of
+    /// flowing on through later operations.
+    ///
+    fn check_for_error_value(&self, name: &str) -> Result<(), InterpretError> {
+        match self.stack.borrow().as_slice().last() {
+            Some(Some(Value::Error)) => {
+                Err(RuntimeError::new(&format!("{} produced an invalid value.", name)).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn execute(&self, instruction: OpCode, current_ip: usize) -> Result<(), InterpretError> {
+        match instruction {
+            OpCode::OpReturn(_) => self.return_from_frame()?,
+            OpCode::OpCall(arg_count) => self.call_value(arg_count)?,
+            OpCode::OpPop => {
+                self.stack.borrow_mut().pop()?;
+            }
+            OpCode::OpPopN(count) => self.stack.borrow_mut().pop_n(count)?,
+            OpCode::OpConstant(constants_index) | OpCode::OpConstantLong(constants_index) => {
+                let constant = VM::read_constant(
+                    &self.current_chunk().borrow().constants.values,
+                    constants_index,
+                )
+                .unwrap_or_else(|| {
+                    panic!(
+                        "Constant at IP {} did not return expected value!",
+                        current_ip
+                    )
+                });
+                self.stack.borrow_mut().push(constant);
+            }
+            OpCode::OpDefineGlobal(str_id_index) => {
+                let string_id = VM::read_string(
+                    &self.current_chunk().borrow().constants.values,
+                    str_id_index,
+                );
+
+                self.tracer
+                    .execution(format_args!("Added id {string_id} to globals table"));
+
+                let global_rhs = self.stack.borrow().peek(0)?;
+                let slot = self.globals.borrow_mut().define(&string_id, global_rhs);
+                self.current_chunk()
+                    .borrow_mut()
+                    .cache_global_slot(str_id_index, slot);
+                self.stack.borrow_mut().pop()?;
+            }
+            OpCode::OpSetGlobal(str_id_index) => {
+                let rhs = self.stack.borrow().peek(0)?;
+                // assignment never declares a global, unlike OpDefineGlobal
+                let slot = self.global_slot(str_id_index)?;
+                self.globals.borrow_mut().set_slot(slot, rhs.clone());
+
+                if self.tracer.config().trace_execution {
+                    let string_id = self.global_name(str_id_index);
+                    self.tracer
+                        .execution(format_args!("Set global id {string_id} to {rhs}."));
                 }
-                OpCode::OpSetLocal(index) => {
-                    if let Err(msg) = self.stack.borrow_mut().set_local(index) {
-                        return Err(InterpretError::RuntimeError(msg.to_string()));
-                    }
+            }
+            OpCode::OpGetGlobal(str_id_index) => {
+                let slot = self.global_slot(str_id_index)?;
+                let value = self.globals.borrow().get_slot(slot).clone();
+                self.stack.borrow_mut().push(value);
+
+                if self.tracer.config().trace_execution {
+                    let string_id = self.global_name(str_id_index);
+                    self.tracer.execution(format_args!(
+                        "Read global id {string_id} from globals table"
+                    ));
                 }
-                OpCode::OpTrue => self.stack.borrow_mut().push(Value::Boolean(true)),
-                OpCode::OpFalse => self.stack.borrow_mut().push(Value::Boolean(false)),
-                OpCode::OpNil => self.stack.borrow_mut().push(Value::Nil),
-                OpCode::OpNot => {
-                    let val = self.stack.borrow_mut().pop()?;
-                    self.stack
-                        .borrow_mut()
-                        .push(Value::Boolean(self.is_falsey(val)));
+            }
+            OpCode::OpGetLocal(index) => {
+                let slot = self.slot_base() + index;
+                if let Err(msg) = self.stack.borrow_mut().get_and_push_local(slot) {
+                    return Err(RuntimeError::new(msg).into());
                 }
-                OpCode::OpNegate => {
-                    let val = self.stack.borrow_mut().pop()?;
-
-                    // check for non number types
-                    let val = match val {
-                        Value::Number(num) => Value::Number(num),
-                        _ => {
-                            return Err(InterpretError::RuntimeError(
-                                "Cannot negate non-number type.".to_string(),
-                            ))
-                        }
-                    };
-                    self.stack.borrow_mut().push(-val);
+            }
+            OpCode::OpSetLocal(index) => {
+                let slot = self.slot_base() + index;
+                if let Err(msg) = self.stack.borrow_mut().set_local(slot) {
+                    return Err(RuntimeError::new(msg).into());
                 }
-                OpCode::OpAdd => {
-                    let b = self.stack.borrow_mut().pop()?; // rhs operand
-                    let a = self.stack.borrow_mut().pop()?; // lhs operand
+            }
+            OpCode::OpTrue => self.stack.borrow_mut().push(Value::Boolean(true)),
+            OpCode::OpFalse => self.stack.borrow_mut().push(Value::Boolean(false)),
+            OpCode::OpNil => self.stack.borrow_mut().push(Value::Nil),
+            OpCode::OpNot => {
+                let val = self.stack.borrow_mut().pop()?;
+                self.stack
+                    .borrow_mut()
+                    .push(Value::Boolean(val.is_falsey()));
+            }
+            OpCode::OpNegate => {
+                let val = self.stack.borrow_mut().pop()?;
 
-                    // check for string concatenation
-                    if let (true, Some(str_1), Some(str_2)) = self.check_for_strings(&a, &b) {
-                        self.concatenate(str_1, str_2);
-                    } else {
-                        // otherwise only numbers are addable
-                        let (a, b) = self.check_for_non_number_types(a, b)?;
-                        self.stack.borrow_mut().push(a + b); // push result
-                    }
+                // check for non number types
+                let val = match val {
+                    Value::Number(num) => Value::Number(num),
+                    _ => return Err(RuntimeError::new("Operand must be a number.").into()),
+                };
+                self.stack.borrow_mut().push(-val);
+            }
+            OpCode::OpUnaryPlus => {
+                if !self.peek_is_number(0)? {
+                    return Err(RuntimeError::new("Operand must be a number.").into());
                 }
-                OpCode::OpSubtract => {
-                    let b = self.stack.borrow_mut().pop()?; // rhs operand
-                    let a = self.stack.borrow_mut().pop()?; // lhs operand
-                    let (a, b) = self.check_for_non_number_types(a, b)?;
-                    self.stack.borrow_mut().push(a - b); // push result
+            }
+            OpCode::OpAdd => {
+                let b = self.stack.borrow().peek(0)?; // rhs operand
+                let a = self.stack.borrow().peek(1)?; // lhs operand
+
+                // check for string concatenation
+                if let (true, Some(str_1), Some(str_2)) = self.check_for_strings(&a, &b) {
+                    self.stack.borrow_mut().pop_n(2)?;
+                    self.concatenate(str_1, str_2);
+                } else {
+                    // otherwise only numbers are addable
+                    self.check_number_operands()?;
+                    self.stack.borrow_mut().pop_n(2)?;
+                    self.stack.borrow_mut().push(a + b); // push result
                 }
-                OpCode::OpMultiply => {
+            }
+            OpCode::OpSubtract => {
+                self.check_number_operands()?;
+                let b = self.stack.borrow_mut().pop()?; // rhs operand
+                let a = self.stack.borrow_mut().pop()?; // lhs operand
+                self.stack.borrow_mut().push(a - b); // push result
+            }
+            OpCode::OpMultiply => {
+                // a string next to a number is repeated
+                if self.peek_is_number(0)? != self.peek_is_number(1)? {
+                    self.repeat_string()?;
+                } else {
+                    self.check_number_operands()?;
                     let b = self.stack.borrow_mut().pop()?; // rhs operand
                     let a = self.stack.borrow_mut().pop()?; // lhs operand
-                    let (a, b) = self.check_for_non_number_types(a, b)?;
                     self.stack.borrow_mut().push(a * b); // push result
                 }
-                OpCode::OpDivide => {
-                    let b = self.stack.borrow_mut().pop()?; // rhs operand
-                    let a = self.stack.borrow_mut().pop()?; // lhs operand
-                    let (a, b) = self.check_for_non_number_types(a, b)?;
-                    self.stack.borrow_mut().push(a / b); // push result
-                }
-                OpCode::OpEqual => {
-                    let b = self.stack.borrow_mut().pop()?; // rhs
-                    let a = self.stack.borrow_mut().pop()?; // lhs
-                    self.stack.borrow_mut().push(Value::Boolean(a == b));
-                }
-                OpCode::OpGreater => {
-                    let b = self.stack.borrow_mut().pop()?; // rhs operand
-                    let a = self.stack.borrow_mut().pop()?; // lhs operand
-                    let (a, b) = self.check_for_non_number_types(a, b)?;
-                    self.stack.borrow_mut().push(Value::Boolean(a > b)); // push result
+            }
+            OpCode::OpDivide => {
+                self.check_number_operands()?;
+                let b = self.stack.borrow_mut().pop()?; // rhs operand
+                let a = self.stack.borrow_mut().pop()?; // lhs operand
+                self.check_for_zero_divisor(&b)?;
+                self.stack.borrow_mut().push(a / b); // push result
+            }
+            OpCode::OpModulo => {
+                self.check_number_operands()?;
+                let b = self.stack.borrow_mut().pop()?; // rhs operand
+                let a = self.stack.borrow_mut().pop()?; // lhs operand
+                self.check_for_zero_divisor(&b)?;
+                self.stack.borrow_mut().push(a % b); // push result
+            }
+            OpCode::OpFloorDiv => {
+                self.check_number_operands()?;
+                let b = self.stack.borrow_mut().pop()?; // rhs operand
+                let a = self.stack.borrow_mut().pop()?; // lhs operand
+                self.check_for_zero_divisor(&b)?;
+                if let (Value::Number(RoxNumber(a)), Value::Number(RoxNumber(b))) = (a, b) {
+                    self.stack
+                        .borrow_mut()
+                        .push(Value::Number(RoxNumber((a / b).floor())));
                 }
-                OpCode::OpLess => {
-                    let b = self.stack.borrow_mut().pop()?; // rhs operand
-                    let a = self.stack.borrow_mut().pop()?; // lhs operand
-                    let (a, b) = self.check_for_non_number_types(a, b)?;
-                    self.stack.borrow_mut().push(Value::Boolean(a < b)); // push result
+            }
+            OpCode::OpPower => {
+                self.check_number_operands()?;
+                let b = self.stack.borrow_mut().pop()?; // rhs operand
+                let a = self.stack.borrow_mut().pop()?; // lhs operand
+                if let (Value::Number(RoxNumber(a)), Value::Number(RoxNumber(b))) = (a, b) {
+                    self.stack
+                        .borrow_mut()
+                        .push(Value::Number(RoxNumber(a.powf(b))));
                 }
-                OpCode::OpPrint => {
-                    println!("{}", self.stack.borrow_mut().pop()?);
+            }
+            OpCode::OpBitAnd => self.bitwise_op(|a, b| Some(a & b))?,
+            OpCode::OpBitOr => self.bitwise_op(|a, b| Some(a | b))?,
+            OpCode::OpBitXor => self.bitwise_op(|a, b| Some(a ^ b))?,
+            OpCode::OpShl => {
+                self.bitwise_op(|a, b| u32::try_from(b).ok().and_then(|b| a.checked_shl(b)))?
+            }
+            OpCode::OpShr => {
+                self.bitwise_op(|a, b| u32::try_from(b).ok().and_then(|b| a.checked_shr(b)))?
+            }
+            OpCode::OpEqual => {
+                let b = self.stack.borrow_mut().pop()?; // rhs
+                let a = self.stack.borrow_mut().pop()?; // lhs
+                self.stack.borrow_mut().push(Value::Boolean(a == b));
+            }
+            OpCode::OpStrictEqual => {
+                let b = self.stack.borrow_mut().pop()?; // rhs
+                let a = self.stack.borrow_mut().pop()?; // lhs
+                let same_type = std::mem::discriminant(&a) == std::mem::discriminant(&b);
+                self.stack
+                    .borrow_mut()
+                    .push(Value::Boolean(same_type && a == b));
+            }
+            OpCode::OpGreater => self.comparison_op(Ordering::is_gt)?,
+            OpCode::OpLess => self.comparison_op(Ordering::is_lt)?,
+            OpCode::OpPrint => {
+                let value = self.stack.borrow_mut().pop()?;
+                if let Some(callback) = self.print_callback.borrow_mut().as_mut() {
+                    callback(&value);
                 }
-                OpCode::OpJumpIfFalse(jump) => {
-                    let jump_offset = jump.unwrap();
-                    if self.is_falsey(self.stack.borrow().peek(0)?) {
-                        *self.ip.borrow_mut() += jump_offset;
-                    }
+                self.write_output(&value.print_repr())?;
+            }
+            OpCode::OpEcho => {
+                let value = self.stack.borrow_mut().pop()?;
+                if value != Value::Nil {
+                    self.write_output(&value.repr())?;
                 }
-                OpCode::OpJump(jump) => {
-                    let jump_offset = jump.unwrap();
+            }
+            OpCode::OpJumpIfFalse(jump) => {
+                let jump_offset = jump.unwrap();
+                if self.stack.borrow().peek(0)?.is_falsey() {
                     *self.ip.borrow_mut() += jump_offset;
                 }
-                OpCode::OpLoop(jump) => {
-                    *self.ip.borrow_mut() -= jump;
+            }
+            OpCode::OpJump(jump) => {
+                let jump_offset = jump.unwrap();
+                *self.ip.borrow_mut() += jump_offset;
+            }
+            OpCode::OpLoop(jump) => {
+                *self.ip.borrow_mut() -= jump;
+            }
+            OpCode::OpPushHandler(catch_jump, finally_jump) => {
+                let ip = *self.ip.borrow();
+                let handler = Handler {
+                    catch_ip: catch_jump.map(|jump| ip + jump),
+                    finally_ip: finally_jump.map(|jump| ip + jump),
+                    stack_size: self.stack.borrow().size,
+                    frame_count: self.frames.borrow().len(),
+                };
+                self.handlers.borrow_mut().push(handler);
+            }
+            OpCode::OpPopHandler => {
+                self.handlers.borrow_mut().pop();
+            }
+            OpCode::OpLeaveTry => {
+                let handler = self.handlers.borrow_mut().pop();
+                if let Some(
+                    handler @ Handler {
+                        finally_ip: Some(finally_ip),
+                        ..
+                    },
+                ) = handler
+                {
+                    // resume after this instruction once the finally block ends
+                    let resume_ip = Value::Number(RoxNumber(*self.ip.borrow() as f32));
+                    self.enter_finally(&handler, finally_ip, resume_ip, Completion::Jump);
                 }
             }
-        }
-    }
+            OpCode::OpEndFinally => {
+                let kind = self.stack.borrow_mut().pop()?;
+                let value = self.stack.borrow_mut().pop()?;
 
-    fn is_falsey(&self, value: Value) -> bool {
-        matches!(value, Value::Boolean(false) | Value::Nil)
-    }
+                match Completion::from_value(&kind) {
+                    Some(Completion::Throw) => return Err(InterpretError::Thrown(value)),
+                    Some(Completion::Error) => {
+                        return Err(match value {
+                            Value::Object(RoxObject {
+                                object_type: ObjectType::ObjError(error),
+                                ..
+                            }) => RuntimeError {
+                                message: error.message.to_string(),
+                                line: error.line,
+                                trace: vec![],
+                            }
+                            .into(),
+                            _ => RuntimeError::new(&value.to_string()).into(),
+                        })
+                    }
+                    Some(Completion::Return) => {
+                        self.stack.borrow_mut().push(value);
+                        self.return_from_frame()?;
+                    }
+                    Some(Completion::Jump) => {
+                        if let Value::Number(RoxNumber(resume_ip)) = value {
+                            *self.ip.borrow_mut() = resume_ip as usize;
+                        }
+                    }
+                    None => (),
+                }
+            }
+            OpCode::OpThrow => {
+                let thrown = self.stack.borrow_mut().pop()?;
+                return Err(InterpretError::Thrown(
+                    self.mark_throw_site(thrown, current_ip),
+                ));
+            }
+            OpCode::OpGetProperty(name_index) => {
+                let name =
+                    VM::read_string(&self.current_chunk().borrow().constants.values, name_index);
+                let object = self.stack.borrow_mut().pop()?;
+                let property = self.get_property(&object, &name)?;
+                self.stack.borrow_mut().push(property);
+            }
+            OpCode::OpBuildArray(count) => {
+                let mut stack = self.stack.borrow_mut();
+                let mut elements = (0..count)
+                    .map(|_| stack.pop())
+                    .collect::<Result<Vec<Value>, _>>()?;
+                elements.reverse();
 
-    fn concatenate<'a>(&self, lhs: &'a RoxString, rhs: &'a RoxString) {
-        let new_string = lhs.clone() + rhs.clone();
-        let mut new_string_obj = RoxObject::new(ObjectType::ObjString(new_string));
-        // new string is allocated so add it to objects list
-        self.objects.borrow_mut().add_object(&mut new_string_obj);
-        self.stack.borrow_mut().push(Value::Object(new_string_obj));
-    }
+                let mut array =
+                    RoxObject::new(ObjectType::ObjArray(Rc::new(RoxArray::new(elements))));
+                self.objects.borrow_mut().add_object(&mut array);
+                stack.push(Value::Object(array));
+            }
+            OpCode::OpClosure => {
+                let function = match self.stack.borrow_mut().pop()? {
+                    Value::Object(RoxObject {
+                        object_type: ObjectType::ObjFunction(function),
+                        ..
+                    }) => function,
+                    _ => return Err(RuntimeError::new("Can only close over functions.").into()),
+                };
 
-    fn check_for_strings<'a>(
-        &self,
-        lhs: &'a Value,
-        rhs: &'a Value,
-    ) -> (bool, Option<&'a RoxString>, Option<&'a RoxString>) {
-        match lhs {
-            Value::Object(obj_one) => match &obj_one.object_type {
-                ObjectType::ObjString(str_1) => match rhs {
-                    Value::Object(obj_two) => match &obj_two.object_type {
-                        ObjectType::ObjString(str_2) => (true, Some(str_1), Some(str_2)),
-                    },
-                    _ => (false, None, None),
-                },
-            },
-            _ => (false, None, None),
-        }
-    }
+                let upvalues = function
+                    .upvalues
+                    .iter()
+                    .map(|upvalue| {
+                        if upvalue.is_local {
+                            self.capture_upvalue(self.slot_base() + upvalue.index)
+                        } else {
+                            self.frame_upvalue(upvalue.index)
+                        }
+                    })
+                    .collect();
 
-    fn check_for_non_number_types(
-        &self,
-        a: Value,
-        b: Value,
-    ) -> Result<(Value, Value), InterpretError> {
-        let a = match a {
-            Value::Number(num) => Value::Number(num),
-            _ => {
-                let line = self.chunk.borrow().get_line(*self.ip.borrow() - 1);
-                return Err(InterpretError::RuntimeError(format!(
-                    "[line {}]: Cannot relate two non-number types",
-                    line
-                )));
+                let mut closure = RoxObject::new(ObjectType::ObjClosure(Rc::new(RoxClosure::new(
+                    function, upvalues,
+                ))));
+                self.objects.borrow_mut().add_object(&mut closure);
+                self.stack.borrow_mut().push(Value::Object(closure));
             }
-        };
-        let b = match b {
-            Value::Number(num) => Value::Number(num),
-            _ => {
-                let line = self.chunk.borrow().get_line(*self.ip.borrow() - 1);
-                return Err(InterpretError::RuntimeError(format!(
-                    "[line {}]: Cannot relate two non-number types",
-                    line
-                )));
+            OpCode::OpGetUpvalue(index) => {
+                let upvalue = self.frame_upvalue(index);
+                let value = match &*upvalue.borrow() {
+                    RoxUpvalue::Open(slot) => self.stack.borrow().get(*slot)?,
+                    RoxUpvalue::Closed(value) => value.clone(),
+                };
+                self.stack.borrow_mut().push(value);
             }
-        };
+            OpCode::OpSetUpvalue(index) => {
+                let upvalue = self.frame_upvalue(index);
+                let mut upvalue = upvalue.borrow_mut();
+                match &mut *upvalue {
+                    RoxUpvalue::Open(slot) => self.stack.borrow_mut().set_local(*slot)?,
+                    RoxUpvalue::Closed(value) => *value = self.stack.borrow().peek(0)?,
+                }
+            }
+            OpCode::OpCloseUpvalue => {
+                let top = self.stack.borrow().size - 1;
+                self.close_upvalues(top);
+                self.stack.borrow_mut().pop()?;
+            }
+            OpCode::OpIndexGet => {
+                let index = self.stack.borrow_mut().pop()?;
+                let array = self.stack.borrow_mut().pop()?;
+                let (array, index) = VM::array_index(&array, &index)?;
+                let element = array.get(index).expect("index was checked to be in range");
+                self.stack.borrow_mut().push(element);
+            }
+            OpCode::OpIndexSet => {
+                let value = self.stack.borrow_mut().pop()?;
+                let index = self.stack.borrow_mut().pop()?;
+                let array = self.stack.borrow_mut().pop()?;
+                let (array, index) = VM::array_index(&array, &index)?;
+                array.set(index, value.clone());
 
-        Ok((a, b))
-    }
+                // an assignment evaluates to the assigned value
+                self.stack.borrow_mut().push(value);
+            }
+            OpCode::OpPushLine => {
+                let line = self.call_site_line(current_ip);
+                self.stack
+                    .borrow_mut()
+                    .push(Value::Number(RoxNumber(line as f32)));
+            }
+        }
 
-    pub fn interpret(&self, source: &str) -> InterpretResult {
-        // read and scan tokens
-        let tokens = self.scanner.scan_tokens(source);
+        Ok(())
+    }
 
-        // make new compiler
-        let chunk = Rc::clone(&self.chunk);
-        let peekable_tokens = RefCell::new(tokens.iter().peekable());
-        let compiler = Compiler::new(chunk, peekable_tokens);
+    ///
+    /// Returns the value on top of the stack to the caller. A return
+    /// that leaves a try block with a finally clause first runs the
+    /// finally block, which resumes the return when it ends.
+    ///
+    fn return_from_frame(&self) -> Result<(), InterpretError> {
+        let frame_count = self.frames.borrow().len();
+        loop {
+            let handler = match self.handlers.borrow().last() {
+                Some(handler) if handler.frame_count == frame_count => *handler,
+                _ => break,
+            };
+            self.handlers.borrow_mut().pop();
 
-        // parse and compile tokens into opcodes
-        if !compiler.compile() {
-            return Err(InterpretError::CompileError(
-                "Compiler error in VM interpreter.".to_string(),
-            ));
+            if let Some(finally_ip) = handler.finally_ip {
+                let result = self.stack.borrow_mut().pop()?;
+                self.enter_finally(&handler, finally_ip, result, Completion::Return);
+                return Ok(());
+            }
         }
 
-        if DEBUG_MODE {
-            self.chunk.borrow().disassemble_chunk("OpCode Debug");
+        let frame = self
+            .frames
+            .borrow_mut()
+            .pop()
+            .expect("Error returning without a call frame.");
+        self.close_upvalues(frame.slot_base);
+
+        // the top-level script leaves nothing to return
+        if let Some(caller) = self.frames.borrow().last() {
+            let result = self.stack.borrow_mut().pop()?;
+            let mut stack = self.stack.borrow_mut();
+            stack.truncate(frame.slot_base);
+            stack.push(result);
+            *self.ip.borrow_mut() = caller.ip;
+            drop(stack);
+
+            self.call_hook(HookEvent::FunctionExit {
+                name: frame.name.to_string(),
+            });
         }
-        // run vm with chunk filled with compiled opcodes
-        self.run()
-    }
-}
 
-impl Default for VM {
-    fn default() -> Self {
-        Self::new()
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::error;
+    ///
+    /// Transfers control to the innermost catch or finally block, restoring
+    /// the stack to its height at the start of the try block. Errors are
+    /// propagated unchanged when no handler is installed.
+    ///
+    fn unwind(&self, error: InterpretError) -> Result<(), InterpretError> {
+        if let InterpretError::CompileError(_) = error {
+            return Err(error);
+        }
 
-    #[test]
-    fn test_negate_op() {
+        loop {
+            let handler = match self.handlers.borrow_mut().pop() {
+                Some(handler) => handler,
+                None => return Err(error),
+            };
+
+            if let Some(catch_ip) = handler.catch_ip {
+                // keep the handler so errors in the catch block run the finally block
+                self.handlers.borrow_mut().push(Handler {
+                    catch_ip: None,
+                    ..handler
+                });
+
+                let error_value = match error {
+                    InterpretError::Thrown(value) => value,
+                    InterpretError::RuntimeError(error) => VM::error_object(&error),
+                    InterpretError::CompileError(_) => return Err(error),
+                };
+
+                self.tracer.execution(format_args!(
+                    "Caught {} and jumping to {}",
+                    error_value, catch_ip
+                ));
+
+                self.restore_handler_state(&handler);
+                self.stack.borrow_mut().push(error_value);
+                *self.ip.borrow_mut() = catch_ip;
+                return Ok(());
+            }
+
+            if let Some(finally_ip) = handler.finally_ip {
+                let (value, kind) = match error {
+                    InterpretError::Thrown(value) => (value, Completion::Throw),
+                    // keep the original line for when the error is raised again
+                    InterpretError::RuntimeError(error) => {
+                        (VM::error_object(&error), Completion::Error)
+                    }
+                    InterpretError::CompileError(_) => return Err(error),
+                };
+
+                self.enter_finally(&handler, finally_ip, value, kind);
+                return Ok(());
+            }
+        }
+    }
+
+    ///
+    /// An Error object for a runtime error raised by the VM, so catch
+    /// blocks see the same message and line properties as for errors
+    /// thrown by scripts.
+    ///
+    fn error_object(error: &RuntimeError) -> Value {
+        Value::Object(RoxObject::new(ObjectType::ObjError(RoxError {
+            message: RoxString::new(&error.message),
+            line: error.line,
+        })))
+    }
+
+    fn enter_finally(&self, handler: &Handler, finally_ip: usize, value: Value, kind: Completion) {
+        self.tracer.execution(format_args!(
+            "Running finally block at {} for {:?}",
+            finally_ip, kind
+        ));
+
+        self.restore_handler_state(handler);
+        let mut stack = self.stack.borrow_mut();
+        stack.push(value);
+        stack.push(kind.to_value());
+        *self.ip.borrow_mut() = finally_ip;
+    }
+
+    fn restore_handler_state(&self, handler: &Handler) {
+        self.close_upvalues(handler.stack_size);
+        self.frames.borrow_mut().truncate(handler.frame_count);
+        self.stack.borrow_mut().truncate(handler.stack_size);
+    }
+
+    ///
+    /// Returns the open upvalue for a stack slot, creating one if no
+    /// closure has captured the slot yet so closures share a variable.
+    ///
+    fn capture_upvalue(&self, slot: usize) -> RcMut<RoxUpvalue> {
+        let mut open_upvalues = self.open_upvalues.borrow_mut();
+        let position = open_upvalues.iter().position(
+            |upvalue| matches!(*upvalue.borrow(), RoxUpvalue::Open(open) if open >= slot),
+        );
+
+        match position {
+            Some(position) if *open_upvalues[position].borrow() == RoxUpvalue::Open(slot) => {
+                Rc::clone(&open_upvalues[position])
+            }
+            _ => {
+                let upvalue = Rc::new(RefCell::new(RoxUpvalue::Open(slot)));
+                let position = position.unwrap_or(open_upvalues.len());
+                open_upvalues.insert(position, Rc::clone(&upvalue));
+                upvalue
+            }
+        }
+    }
+
+    /// Moves the values of the upvalues open at or above a stack slot into the upvalues.
+    fn close_upvalues(&self, slot: usize) {
+        let mut open_upvalues = self.open_upvalues.borrow_mut();
+        let position = open_upvalues
+            .iter()
+            .position(|upvalue| matches!(*upvalue.borrow(), RoxUpvalue::Open(open) if open >= slot))
+            .unwrap_or(open_upvalues.len());
+
+        for upvalue in open_upvalues.drain(position..) {
+            let mut upvalue = upvalue.borrow_mut();
+            if let RoxUpvalue::Open(open) = *upvalue {
+                let value = self.stack.borrow().get(open).unwrap_or(Value::Nil);
+                *upvalue = RoxUpvalue::Closed(value);
+            }
+        }
+    }
+
+    /// The upvalue at index of the closure executing in the topmost call frame.
+    fn frame_upvalue(&self, index: usize) -> RcMut<RoxUpvalue> {
+        let frames = self.frames.borrow();
+        let frame = frames
+            .last()
+            .expect("Error reading upvalue without a call frame.");
+        Rc::clone(&frame.upvalues[index])
+    }
+
+    /// Records the line an error object is first thrown from.
+    fn mark_throw_site(&self, thrown: Value, ip: usize) -> Value {
+        match thrown {
+            Value::Object(RoxObject {
+                object_type: ObjectType::ObjError(error),
+                ..
+            }) if error.line.is_none() => {
+                let line = self.current_chunk().borrow().line_at(ip);
+                Value::Object(RoxObject::new(ObjectType::ObjError(RoxError {
+                    line: Some(line),
+                    ..error
+                })))
+            }
+            _ => thrown,
+        }
+    }
+
+    ///
+    /// Checks that a value is an array and that an index is an integer
+    /// within its bounds.
+    ///
+    fn array_index(array: &Value, index: &Value) -> Result<(Rc<RoxArray>, usize), InterpretError> {
+        let array = match array {
+            Value::Object(RoxObject {
+                object_type: ObjectType::ObjArray(array),
+                ..
+            }) => Rc::clone(array),
+            _ => return Err(RuntimeError::new("Only arrays can be indexed.").into()),
+        };
+
+        let index = match index {
+            Value::Number(RoxNumber(index)) if index.fract() == 0.0 => *index,
+            _ => return Err(RuntimeError::new("Array index must be an integer.").into()),
+        };
+
+        if index < 0.0 || index as usize >= array.len() {
+            return Err(RuntimeError::new(&format!(
+                "Index {} out of range for array of length {}.",
+                index,
+                array.len()
+            ))
+            .into());
+        }
+
+        Ok((array, index as usize))
+    }
+
+    fn get_property(&self, object: &Value, name: &RoxString) -> Result<Value, InterpretError> {
+        let error = match object {
+            Value::Object(RoxObject {
+                object_type: ObjectType::ObjError(error),
+                ..
+            }) => error,
+            _ => return Err(RuntimeError::new("Only error objects have properties.").into()),
+        };
+
+        match &**name {
+            "message" => Ok(self.allocate_string(error.message.clone())),
+            "line" => Ok(error
+                .line
+                .map_or(Value::Nil, |line| Value::Number(RoxNumber(line as f32)))),
+            _ => Err(RuntimeError::new(&format!("Undefined property '{}'.", name)).into()),
+        }
+    }
+
+    fn concatenate<'a>(&self, lhs: &'a Rc<RoxString>, rhs: &'a Rc<RoxString>) {
+        // build the result directly rather than cloning both operands first
+        let new_string = self.allocate_string(RoxStringBuilder::concat(&[lhs, rhs]));
+        self.stack.borrow_mut().push(new_string);
+    }
+
+    fn allocate_string(&self, string: RoxString) -> Value {
+        let string = self.current_chunk().borrow().intern(string);
+        let mut new_string_obj = RoxObject::new(ObjectType::ObjString(string));
+        // new string is allocated so add it to objects list
+        self.objects.borrow_mut().add_object(&mut new_string_obj);
+        Value::Object(new_string_obj)
+    }
+
+    fn check_for_strings<'a>(
+        &self,
+        lhs: &'a Value,
+        rhs: &'a Value,
+    ) -> (bool, Option<&'a Rc<RoxString>>, Option<&'a Rc<RoxString>>) {
+        match lhs {
+            Value::Object(obj_one) => match &obj_one.object_type {
+                ObjectType::ObjString(str_1) => match rhs {
+                    Value::Object(obj_two) => match &obj_two.object_type {
+                        ObjectType::ObjString(str_2) => (true, Some(str_1), Some(str_2)),
+                        _ => (false, None, None),
+                    },
+                    _ => (false, None, None),
+                },
+                _ => (false, None, None),
+            },
+            _ => (false, None, None),
+        }
+    }
+
+    ///
+    /// Applies a bitwise operator to the two integral numbers on top of
+    /// the stack. The operator returns None for an out of range shift.
+    ///
+    fn bitwise_op(&self, op: fn(i64, i64) -> Option<i64>) -> Result<(), InterpretError> {
+        let b = self.stack.borrow_mut().pop()?; // rhs operand
+        let a = self.stack.borrow_mut().pop()?; // lhs operand
+
+        let (a, b) = match (a, b) {
+            (Value::Number(RoxNumber(a)), Value::Number(RoxNumber(b)))
+                if a.fract() == 0.0 && b.fract() == 0.0 =>
+            {
+                (a as i64, b as i64)
+            }
+            _ => return Err(RuntimeError::new("Operands must be integers").into()),
+        };
+
+        match op(a, b) {
+            Some(result) => {
+                self.stack
+                    .borrow_mut()
+                    .push(Value::Number(RoxNumber(result as f32)));
+                Ok(())
+            }
+            None => Err(RuntimeError::new("Shift amount out of range").into()),
+        }
+    }
+
+    ///
+    /// Replaces a string and a number on top of the stack with the
+    /// string repeated that many times.
+    ///
+    fn repeat_string(&self) -> Result<(), InterpretError> {
+        let b = self.stack.borrow().peek(0)?;
+        let a = self.stack.borrow().peek(1)?;
+        let is_string = |value: &Value| {
+            matches!(
+                value,
+                Value::Object(RoxObject {
+                    object_type: ObjectType::ObjString(_),
+                    ..
+                })
+            )
+        };
+        if !is_string(&a) && !is_string(&b) {
+            return self.check_number_operands();
+        }
+
+        let whole_count = [&a, &b].iter().any(|value| {
+            matches!(value, Value::Number(RoxNumber(count)) if *count >= 0.0 && count.fract() == 0.0)
+        });
+        match a * b {
+            Value::Object(RoxObject {
+                object_type: ObjectType::ObjString(repeated),
+                ..
+            }) => {
+                self.stack.borrow_mut().pop_n(2)?;
+                let repeated = self.allocate_string((*repeated).clone());
+                self.stack.borrow_mut().push(repeated);
+                Ok(())
+            }
+            // the count was fine, so it was the length of the result that wasn't
+            _ if whole_count => Err(RuntimeError::new(&format!(
+                "A repeated string can't be longer than {} bytes.",
+                MAX_REPEAT_LEN
+            ))
+            .into()),
+            _ => Err(RuntimeError::new(
+                "A string can only be repeated a whole, non-negative number of times.",
+            )
+            .into()),
+        }
+    }
+
+    fn peek_is_number(&self, distance: usize) -> Result<bool, InterpretError> {
+        Ok(matches!(
+            self.stack.borrow().peek(distance)?,
+            Value::Number(_)
+        ))
+    }
+
+    /// Writes a line of program output to the output sink.
+    fn write_output(&self, text: &str) -> Result<(), InterpretError> {
+        writeln!(self.output.borrow_mut(), "{}", text)
+            .map_err(|err| RuntimeError::new(&format!("Failed to write output: {}", err)).into())
+    }
+
+    ///
+    /// Checks that the two operands on top of the stack are numbers
+    /// before an arithmetic operator pops them, naming the first one
+    /// that isn't in the error.
+    ///
+    fn check_number_operands(&self) -> Result<(), InterpretError> {
+        // the lhs operand sits beneath the rhs
+        for distance in [1, 0] {
+            if !self.peek_is_number(distance)? {
+                let operand = self.stack.borrow().peek(distance)?;
+                return Err(RuntimeError::new(&format!(
+                    "Operands must be numbers, but {} is a {}.",
+                    operand.repr(),
+                    operand.type_name()
+                ))
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Pops two operands and pushes whether their ordering passes test.
+    /// Numbers and strings can be ordered among themselves, and NaN is
+    /// a number that compares false with everything.
+    ///
+    fn comparison_op(&self, test: fn(Ordering) -> bool) -> Result<(), InterpretError> {
+        let b = self.stack.borrow_mut().pop()?; // rhs operand
+        let a = self.stack.borrow_mut().pop()?; // lhs operand
+
+        let result = match (&a, &b, a.partial_cmp(&b)) {
+            (.., Some(ordering)) => test(ordering),
+            (Value::Number(_), Value::Number(_), None) => false,
+            _ => {
+                return Err(
+                    RuntimeError::new("Operands must be two numbers or two strings.").into(),
+                )
+            }
+        };
+        self.stack.borrow_mut().push(Value::Boolean(result));
+
+        Ok(())
+    }
+
+    fn check_for_zero_divisor(&self, divisor: &Value) -> Result<(), InterpretError> {
+        if let Value::Number(num) = divisor {
+            if **num == 0.0 {
+                return Err(RuntimeError::new("Cannot divide by zero").into());
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn interpret(&self, source: &str) -> InterpretResult {
+        self.interpret_source(source, SourceKind::Program)
+    }
+
+    ///
+    /// Interprets source like interpret, but reports a panic inside the
+    /// interpreter as a runtime error instead of unwinding into the host.
+    /// The VM is reset after a panic since its state can't be trusted.
+    ///
+    pub fn interpret_catching_panics(&mut self, source: &str) -> InterpretResult {
+        match panic::catch_unwind(AssertUnwindSafe(|| self.interpret(source))) {
+            Ok(result) => result,
+            Err(payload) => {
+                self.reset();
+                let detail = match payload.downcast_ref::<&str>() {
+                    Some(detail) => detail.to_string(),
+                    None => match payload.downcast_ref::<String>() {
+                        Some(detail) => detail.clone(),
+                        None => String::from("unknown cause"),
+                    },
+                };
+                Err(RuntimeError::new(&format!("internal VM panic: {}", detail)).into())
+            }
+        }
+    }
+
+    ///
+    /// Interprets a line of REPL input, echoing the repr of the value of
+    /// each top-level expression statement.
+    ///
+    pub fn interpret_repl(&self, source: &str) -> InterpretResult {
+        self.interpret_source(source, SourceKind::Repl)
+    }
+
+    ///
+    /// Interprets source holding a single expression, without a trailing
+    /// semicolon, and returns its value. Statements are a compile error.
+    ///
+    pub fn interpret_expression(&self, source: &str) -> InterpretResult {
+        self.interpret_source(source, SourceKind::Expression)
+    }
+
+    ///
+    /// Drops what an uncaught error left of the run, so the next source
+    /// interpreted starts from an empty stack at the end of the script
+    /// chunk rather than inside the failed call.
+    ///
+    fn abandon_run(&self) {
+        self.stack.borrow_mut().reset_stack();
+        self.frames.borrow_mut().clear();
+        self.handlers.borrow_mut().clear();
+        self.open_upvalues.borrow_mut().clear();
+        *self.ip.borrow_mut() = self.chunk.borrow().count();
+    }
+
+    fn interpret_source(&self, source: &str, kind: SourceKind) -> InterpretResult {
+        // read and scan tokens
+        let tokens = self.scanner.scan_tokens(source);
+
+        // make new compiler
+        let chunk = Rc::clone(&self.chunk);
+        let peekable_tokens = RefCell::new(tokens.iter().peekable());
+        let compiler = Compiler::new(chunk, peekable_tokens, self.tracer.clone());
+        compiler.echo_expressions.replace(kind == SourceKind::Repl);
+
+        // parse and compile tokens into opcodes
+        let compiled = match kind {
+            SourceKind::Expression => compiler.compile_expression(),
+            SourceKind::Program | SourceKind::Repl => compiler.compile(),
+        };
+        if !compiled {
+            let errors = compiler
+                .errors()
+                .iter()
+                .map(|error| error.to_string())
+                .collect::<Vec<_>>();
+            return Err(InterpretError::CompileError(errors.join("\n")));
+        }
+
+        if self.tracer.config().trace_compiler {
+            self.tracer.compiler(format_args!(
+                "{}",
+                self.current_chunk().borrow().disassemble("OpCode Debug")
+            ));
+        }
+        // run vm with chunk filled with compiled opcodes
+        self.frames.borrow_mut().push(CallFrame {
+            name: RoxString::new(SCRIPT_NAME),
+            chunk: Rc::clone(&self.chunk),
+            ip: 0,
+            slot_base: 0,
+            upvalues: vec![],
+        });
+        let result = self.run();
+        if result.is_err() {
+            self.abandon_run();
+        }
+
+        if let Err(InterpretError::RuntimeError(error)) = &result {
+            self.call_hook(HookEvent::RuntimeError {
+                message: error.message.clone(),
+                line: error.line.unwrap_or_default(),
+            });
+        }
+
+        result
+    }
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error;
+
+    #[test]
+    fn test_negate_op() {
         let vm = VM::new();
         let result = vm.interpret("-45;").unwrap();
 
-        assert!(result == error::InterpretOk);
+        assert!(result == error::InterpretOk(Value::Number(RoxNumber(-45.0))));
     }
 
     #[test]
@@ -407,6 +1556,1908 @@ mod tests {
         }
     }
 
+    fn global(vm: &VM, name: &str) -> Option<Value> {
+        vm.globals.borrow().get(&RoxString::new(name)).cloned()
+    }
+
+    #[test]
+    fn test_modulo_op() {
+        let vm = VM::new();
+        if let Err(msg) = vm.interpret("print 7 % 3; var a = 7 % 3; var b = -7 % 2.5;") {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "a"), Some(Value::Number(RoxNumber(1.0))));
+        assert_eq!(global(&vm, "b"), Some(Value::Number(RoxNumber(-2.0))));
+    }
+
+    #[test]
+    fn test_modulo_by_zero() {
+        let vm = VM::new();
+        assert!(vm.interpret("print 7 % 0;").is_err());
+
+        let vm = VM::new();
+        assert!(vm.interpret("print 7 / 0;").is_err());
+    }
+
+    #[test]
+    fn test_hook_line_changes() {
+        let vm = VM::new();
+        let events = Rc::new(RefCell::new(vec![]));
+        let recorded = Rc::clone(&events);
+        vm.set_hook(Box::new(move |event| {
+            recorded.borrow_mut().push(event.clone())
+        }));
+
+        if let Err(msg) = vm.interpret("var a = 1;\nvar b = 2;\n\nprint a +\n b;") {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                HookEvent::LineChange { line: 1 },
+                HookEvent::LineChange { line: 2 },
+                HookEvent::LineChange { line: 4 },
+                HookEvent::LineChange { line: 5 },
+                HookEvent::LineChange { line: 6 }, // final OP_RETURN sits on the EOF line
+            ]
+        );
+    }
+
+    #[test]
+    fn test_on_print_callback() {
+        let mut vm = VM::new();
+        vm.set_output(Rc::new(RefCell::new(Vec::new())));
+        let printed = Rc::new(RefCell::new(vec![]));
+        let sink = printed.clone();
+        vm.on_print(Box::new(move |value| sink.borrow_mut().push(value.clone())));
+
+        if let Err(msg) = vm.interpret("print 1; print true; print nil; print \"hi\";") {
+            panic!("{}", msg)
+        }
+
+        let printed = printed.borrow();
+        assert_eq!(printed.len(), 4);
+        assert_eq!(printed[0], Value::Number(RoxNumber(1.0)));
+        assert_eq!(printed[1], Value::Boolean(true));
+        assert_eq!(printed[2], Value::Nil);
+        assert_eq!(string_value(Some(printed[3].clone())), "hi");
+    }
+
+    #[test]
+    fn test_profile_counts_opcodes() {
+        let vm = VM::new();
+        assert_eq!(vm.profile(), None);
+
+        vm.enable_profiling();
+        if let Err(msg) = vm.interpret("var i = 0; while (i < 5) i += 1;") {
+            panic!("{}", msg)
+        }
+
+        let profile = vm.profile().unwrap();
+        assert_eq!(profile.get("OP_LOOP"), Some(&5));
+        assert_eq!(profile.get("OP_RETURN"), Some(&1));
+        assert_eq!(profile.get("OP_PRINT"), None);
+    }
+
+    #[test]
+    fn test_hook_runtime_error() {
+        let vm = VM::new();
+        let events = Rc::new(RefCell::new(vec![]));
+        let recorded = Rc::clone(&events);
+        vm.set_hook(Box::new(move |event| {
+            recorded.borrow_mut().push(event.clone())
+        }));
+
+        assert!(vm.interpret("print 1;\nprint -true;").is_err());
+        assert!(matches!(
+            events.borrow().last(),
+            Some(HookEvent::RuntimeError { line: 2, .. })
+        ));
+    }
+
+    fn run_else_if_chain(a: f32) -> Option<Value> {
+        let vm = VM::new();
+        let source = format!(
+            "var a = {a}; var branch;
+            if (a < 10) {{ branch = 1; }} else if (a < 20) {{ branch = 2; }} else {{ branch = 3; }}"
+        );
+        if let Err(msg) = vm.interpret(&source) {
+            panic!("{}", msg)
+        }
+
+        global(&vm, "branch")
+    }
+
+    #[test]
+    fn test_else_if_chain() {
+        assert_eq!(run_else_if_chain(5.0), Some(Value::Number(RoxNumber(1.0))));
+        assert_eq!(run_else_if_chain(15.0), Some(Value::Number(RoxNumber(2.0))));
+        assert_eq!(run_else_if_chain(25.0), Some(Value::Number(RoxNumber(3.0))));
+    }
+
+    #[test]
+    fn test_line_builtin() {
+        let vm = VM::new();
+        if let Err(msg) = vm.interpret("var a = 1;\n\nvar line = __line__;\nvar b = a +\n__line__;")
+        {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "line"), Some(Value::Number(RoxNumber(3.0))));
+        assert_eq!(global(&vm, "b"), Some(Value::Number(RoxNumber(6.0))));
+    }
+
+    #[test]
+    fn test_line_builtin_reports_call_site() {
+        let vm = VM::new();
+        let source =
+            "fun where() { return __line__; }\nvar first = where();\n\nvar second = \nwhere();";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "first"), Some(Value::Number(RoxNumber(2.0))));
+        assert_eq!(global(&vm, "second"), Some(Value::Number(RoxNumber(5.0))));
+    }
+
+    #[test]
+    fn test_line_builtin_not_assignable() {
+        let vm = VM::new();
+        assert!(matches!(
+            vm.interpret("__line__ = 4;"),
+            Err(InterpretError::CompileError(_))
+        ));
+    }
+
+    #[test]
+    fn test_catch_thrown_value() {
+        let vm = VM::new();
+        let source = "var caught; var after;
+            try { var a = 1; throw a + 41; caught = 0; } catch (e) { caught = e; }
+            after = true;";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "caught"), Some(Value::Number(RoxNumber(42.0))));
+        assert_eq!(global(&vm, "after"), Some(Value::Boolean(true)));
+    }
+
+    #[test]
+    fn test_catch_runtime_error() {
+        let vm = VM::new();
+        let source = "var caught; var message; var line;
+            { var a = 10; try {\n var b = a / 0; } catch (e) { caught = e; message = e.message; line = e.line; } }";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        // runtime errors are caught as the same Error objects scripts throw
+        match global(&vm, "caught") {
+            Some(Value::Object(RoxObject {
+                object_type: ObjectType::ObjError(error),
+                ..
+            })) => assert_eq!(error.line, Some(3)),
+            other => panic!("Expected a caught error object, got {:?}", other),
+        }
+        assert_eq!(
+            string_value(global(&vm, "message")),
+            "Cannot divide by zero"
+        );
+        assert_eq!(global(&vm, "line"), Some(Value::Number(RoxNumber(3.0))));
+    }
+
+    #[test]
+    fn test_print_caught_runtime_error() {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut vm = VM::new();
+        vm.set_output(output.clone());
+        if let Err(msg) = vm.interpret("try { -true; } catch (e) { print e; } print \"after\";") {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(
+            String::from_utf8(output.borrow().clone()).unwrap(),
+            "[line 1] Error: Operand must be a number.\nafter\n"
+        );
+    }
+
+    #[test]
+    fn test_nested_try_rethrow() {
+        let vm = VM::new();
+        let source = "var caught;
+            try { try { throw 1; } catch (e) { throw e + 1; } } catch (e) { caught = e; }";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "caught"), Some(Value::Number(RoxNumber(2.0))));
+    }
+
+    #[test]
+    fn test_constant_long() {
+        let vm = VM::new();
+        let mut source = String::from("var x;");
+        for i in 0..300 {
+            source.push_str(&format!(" x = {};", i));
+        }
+        source.push_str(" var y = x;");
+        if let Err(msg) = vm.interpret(&source) {
+            panic!("{}", msg)
+        }
+
+        assert!(vm
+            .chunk
+            .borrow()
+            .code
+            .iter()
+            .any(|op| matches!(op, OpCode::OpConstantLong(_))));
+        assert_eq!(global(&vm, "y"), Some(Value::Number(RoxNumber(299.0))));
+    }
+
+    #[test]
+    fn test_disassemble_to_string() {
+        let vm = VM::new();
+        // a negated operand isn't folded into a constant
+        if let Err(msg) = vm.interpret("1 + -2;") {
+            panic!("{}", msg)
+        }
+
+        let disassembly = vm.chunk.borrow().disassemble("test");
+        assert!(disassembly.starts_with("=+=+=+= test =+=+=+="));
+        assert!(disassembly.contains("OP_CONSTANT"));
+        assert!(disassembly.contains("OP_ADD"));
+
+        let (instruction, next_offset) = vm.chunk.borrow().disassemble_instruction(0);
+        assert!(instruction.starts_with("| 0000 |    1 |"));
+        assert!(instruction.contains("OP_CONSTANT 0   '1'"));
+        assert_eq!(next_offset, 1);
+    }
+
+    #[test]
+    fn test_disabled_trace_writes_nothing() {
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        let vm = VM::with_trace(TraceConfig::default(), sink.clone());
+        if let Err(msg) = vm.interpret("var a = 1; { var b = a + 2; print b; }") {
+            panic!("{}", msg)
+        }
+
+        assert!(sink.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_execution_trace_writes_to_sink() {
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        let config = TraceConfig {
+            trace_execution: true,
+            ..TraceConfig::default()
+        };
+        let vm = VM::with_trace(config, sink.clone());
+        if let Err(msg) = vm.interpret("1 + -2;") {
+            panic!("{}", msg)
+        }
+
+        let trace = String::from_utf8(sink.borrow().clone()).unwrap();
+        assert!(trace.contains("OP_ADD"));
+        assert!(!trace.contains("Scanned Token"));
+    }
+
+    #[test]
+    fn test_runtime_error_result() {
+        match VM::new().interpret("-true;") {
+            Err(InterpretError::RuntimeError(error)) => {
+                assert_eq!(error.message, "Operand must be a number.");
+                assert_eq!(error.line, Some(1));
+            }
+            _ => panic!("Expected a runtime error for negating a boolean"),
+        }
+
+        match VM::new().interpret("var a = 1 + \"one\";") {
+            Err(InterpretError::RuntimeError(error)) => {
+                assert_eq!(
+                    error.to_string(),
+                    "[line 1]: Operands must be numbers, but \"one\" is a string.\n[line 1] in script"
+                )
+            }
+            _ => panic!("Expected a runtime error for adding a number to a string"),
+        }
+    }
+
+    #[test]
+    fn test_runtime_error_line() {
+        let vm = VM::new();
+        let source = "var a = 0;
+            while (a < 2) a = a + 1;
+            var b = a + \"three\";
+            print b;";
+
+        match vm.interpret(source) {
+            Err(InterpretError::RuntimeError(error)) => assert_eq!(error.line, Some(3)),
+            _ => panic!("Expected a runtime error on line 3"),
+        }
+
+        let chunk = vm.chunk.borrow();
+        let loop_offset = chunk
+            .code
+            .iter()
+            .position(|op| matches!(op, OpCode::OpLoop(_)))
+            .unwrap();
+        assert_eq!(chunk.line_at(0), 1);
+        assert_eq!(chunk.line_at(loop_offset), 2);
+    }
+
+    #[test]
+    fn test_not_uses_truthiness() {
+        let vm = VM::new();
+        let source = "var a = !nil; var b = !0; var c = !\"\"; var d;
+            if (0) d = \"then\"; else d = \"else\";";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "a"), Some(Value::Boolean(true)));
+        assert_eq!(global(&vm, "b"), Some(Value::Boolean(false)));
+        assert_eq!(global(&vm, "c"), Some(Value::Boolean(false)));
+        assert_eq!(string_value(global(&vm, "d")), "then");
+    }
+
+    #[test]
+    fn test_power_is_right_associative() {
+        let vm = VM::new();
+        if let Err(msg) = vm.interpret("var a = 2 ** 3 ** 2; var b = -2 ** 2; var c = 2 * 3 ** 2;")
+        {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "a"), Some(Value::Number(RoxNumber(512.0))));
+        assert_eq!(global(&vm, "b"), Some(Value::Number(RoxNumber(-4.0))));
+        assert_eq!(global(&vm, "c"), Some(Value::Number(RoxNumber(18.0))));
+    }
+
+    #[test]
+    fn test_array_literal_and_index() {
+        let vm = VM::new();
+        let source = "var a = [1, 2, 3]; var b = a[1]; var c = a;
+            c[2] = \"three\"; var d = a[2]; var n = len(a); var e = [];";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "b"), Some(Value::Number(RoxNumber(2.0))));
+        assert_eq!(string_value(global(&vm, "d")), "three");
+        assert_eq!(global(&vm, "n"), Some(Value::Number(RoxNumber(3.0))));
+        assert_eq!(global(&vm, "e").unwrap().repr(), "[]");
+        assert_eq!(global(&vm, "a").unwrap().repr(), "[1, 2, \"three\"]");
+    }
+
+    #[test]
+    fn test_array_index_errors() {
+        for source in [
+            "[1, 2][2];",
+            "[1, 2][-1];",
+            "[1][0.5];",
+            "var a = 1; a[0] = 2;",
+        ] {
+            assert!(matches!(
+                VM::new().interpret(source),
+                Err(InterpretError::RuntimeError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_while_loop_leaves_stack_balanced() {
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        let config = TraceConfig {
+            trace_execution: true,
+            ..TraceConfig::default()
+        };
+        let vm = VM::with_trace(config, sink.clone());
+        let source = "var count = 0; while (count < 3) count = count + 1; print count;";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "count"), Some(Value::Number(RoxNumber(3.0))));
+
+        // the stack is printed as it is before each instruction runs
+        let trace = String::from_utf8(sink.borrow().clone()).unwrap();
+        let before_return = trace
+            .lines()
+            .find(|line| line.contains("OP_RETURN"))
+            .unwrap();
+        assert!(before_return.ends_with("| []"), "{}", before_return);
+    }
+
+    #[test]
+    fn test_compound_assignment() {
+        let vm = VM::new();
+        let source = "var x = 5; x += 3; var s = \"a\"; s += \"b\";
+            var y; { var l = 10; l -= 4; l *= 3; l /= 2; y = l; }";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "x"), Some(Value::Number(RoxNumber(8.0))));
+        assert_eq!(string_value(global(&vm, "s")), "ab");
+        assert_eq!(global(&vm, "y"), Some(Value::Number(RoxNumber(9.0))));
+    }
+
+    #[test]
+    fn test_modulo_and_power_assignment() {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut vm = VM::new();
+        vm.set_output(output.clone());
+        let source = "var x = 10; x %= 3; print x; var y = 2; y **= 3; print y;
+            { var l = 3; l **= 2; l %= 5; print l; }";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(output.borrow().as_slice(), b"1\n8\n4\n");
+    }
+
+    #[test]
+    fn test_increment_and_decrement() {
+        let vm = VM::new();
+        let source = "var i = 0; ++i; var a = ++i; var b = i++; var c = i;
+            var d; { var j = 5; --j; d = j-- + j; }";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "a"), Some(Value::Number(RoxNumber(2.0))));
+        assert_eq!(global(&vm, "b"), Some(Value::Number(RoxNumber(2.0))));
+        assert_eq!(global(&vm, "c"), Some(Value::Number(RoxNumber(3.0))));
+        assert_eq!(global(&vm, "d"), Some(Value::Number(RoxNumber(7.0))));
+    }
+
+    #[test]
+    fn test_increment_needs_a_variable() {
+        assert!(matches!(
+            VM::new().interpret("++5;"),
+            Err(InterpretError::CompileError(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_variable_name() {
+        // _1 scans as a malformed number rather than an identifier
+        for source in ["var _1 = 3;", "var 1 = 3;", "var = 3;"] {
+            assert!(
+                matches!(
+                    VM::new().interpret(source),
+                    Err(InterpretError::CompileError(_))
+                ),
+                "Expected a compile error for {}",
+                source
+            );
+        }
+        match VM::new().interpret("var 1 = 3;") {
+            Err(InterpretError::CompileError(message)) => {
+                assert!(message.ends_with("Expect variable name."), "{}", message)
+            }
+            other => panic!("Expected a compile error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_const_requires_name() {
+        for source in ["const = 1;", "const 5 = 1;"] {
+            match VM::new().interpret(source) {
+                Err(InterpretError::CompileError(message)) => {
+                    assert!(message.ends_with("Expect constant name."), "{}", message)
+                }
+                other => panic!("Expected a compile error for {}, got {:?}", source, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_const_cannot_be_assigned() {
+        for source in [
+            "const PI = 3; PI = 4;",
+            "const PI = 3; PI += 1;",
+            "{ const PI = 3; PI++; }",
+        ] {
+            match VM::new().interpret(source) {
+                Err(InterpretError::CompileError(message)) => {
+                    assert!(message.contains("Cannot assign to const variable 'PI'"))
+                }
+                _ => panic!("Expected a compile error for {}", source),
+            }
+        }
+    }
+
+    #[test]
+    fn test_const_can_be_read_and_redeclared() {
+        let vm = VM::new();
+        let source = "const PI = 3; var a = PI + 1; var PI = 4; PI = 5;
+            var b; { const E = 2; b = E * 2; }";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "a"), Some(Value::Number(RoxNumber(4.0))));
+        assert_eq!(global(&vm, "PI"), Some(Value::Number(RoxNumber(5.0))));
+        assert_eq!(global(&vm, "b"), Some(Value::Number(RoxNumber(4.0))));
+    }
+
+    #[test]
+    fn test_do_while_runs_body_once() {
+        let vm = VM::new();
+        let source = "var runs = 0; do { var step = 1; runs = runs + step; } while (runs > 5);
+            var n = 0; do { n = n + 1; if (n < 3) continue; } while (n < 5);";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "runs"), Some(Value::Number(RoxNumber(1.0))));
+        assert_eq!(global(&vm, "n"), Some(Value::Number(RoxNumber(5.0))));
+    }
+
+    #[test]
+    fn test_comparison_of_mixed_types_is_an_error() {
+        for source in ["true < 1;", "1 > nil;", "\"a\" < 1;"] {
+            match VM::new().interpret(source) {
+                Err(InterpretError::RuntimeError(error)) => {
+                    assert_eq!(
+                        error.message,
+                        "Operands must be two numbers or two strings."
+                    )
+                }
+                _ => panic!("Expected a runtime error comparing {}", source),
+            }
+        }
+    }
+
+    #[test]
+    fn test_strings_are_ordered() {
+        let vm = VM::new();
+        if let Err(msg) = vm.interpret("var a = \"apple\" < \"banana\"; var b = \"b\" > \"ba\";") {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "a"), Some(Value::Boolean(true)));
+        assert_eq!(global(&vm, "b"), Some(Value::Boolean(false)));
+    }
+
+    #[test]
+    fn test_division_by_zero_is_an_error() {
+        for source in ["1 / 0;", "0 / 0;", "-1 / 0;"] {
+            match VM::new().interpret(source) {
+                Err(InterpretError::RuntimeError(error)) => {
+                    assert_eq!(error.message, "Cannot divide by zero")
+                }
+                _ => panic!("Expected a runtime error for {}", source),
+            }
+        }
+    }
+
+    #[test]
+    fn test_nan_is_not_equal_to_itself() {
+        let vm = VM::new();
+        let source = "var n = (-1) ** 0.5; var same = n == n; var less = n < 1; var a = nil == n;";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "same"), Some(Value::Boolean(false)));
+        assert_eq!(global(&vm, "less"), Some(Value::Boolean(false)));
+        assert_eq!(global(&vm, "a"), Some(Value::Boolean(false)));
+    }
+
+    #[test]
+    fn test_literal_arithmetic_is_folded() {
+        let vm = VM::new();
+        let result = vm.interpret("1 + 2 * 3;").unwrap();
+
+        let chunk = vm.chunk.borrow();
+        assert!(matches!(
+            chunk.code[..],
+            [OpCode::OpConstant(0), OpCode::OpReturn(_)]
+        ));
+        assert_eq!(chunk.constants.values, vec![Value::Number(RoxNumber(7.0))]);
+        assert!(result == InterpretOk(Value::Number(RoxNumber(7.0))));
+    }
+
+    #[test]
+    fn test_folding_keeps_short_circuits_and_errors() {
+        let vm = VM::new();
+        let source = "var x = false; var a = (x and 1) + 2; var b = (x ? 1 : 2) * 3;";
+        match vm.interpret(source) {
+            Err(InterpretError::RuntimeError(error)) => {
+                assert_eq!(
+                    error.message,
+                    "Operands must be numbers, but false is a bool."
+                )
+            }
+            _ => panic!("Expected adding false to a number to fail"),
+        }
+
+        let vm = VM::new();
+        if let Err(msg) = vm.interpret("var x = false; var b = (x ? 1 : 2) * 3 - 1;") {
+            panic!("{}", msg)
+        }
+        assert_eq!(global(&vm, "b"), Some(Value::Number(RoxNumber(5.0))));
+
+        assert!(matches!(
+            VM::new().interpret("1 / 0;"),
+            Err(InterpretError::RuntimeError(_))
+        ));
+    }
+
+    #[test]
+    fn print_writes_strings_without_quotes() {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut vm = VM::new();
+        vm.set_output(output.clone());
+        assert!(vm
+            .interpret("print \"hello\"; print 1.5; print nil; print [\"a\", 2];")
+            .is_ok());
+        assert_eq!(
+            String::from_utf8(output.borrow().clone()).unwrap(),
+            "hello\n1.5\nnil\n[\"a\", 2]\n"
+        );
+    }
+
+    #[test]
+    fn unbounded_recursion_overflows_the_stack() {
+        let vm = VM::new();
+        match vm.interpret("fun recurse(n) { return recurse(n + 1); } recurse(0);") {
+            Err(InterpretError::RuntimeError(error)) => {
+                assert_eq!(error.message, "Stack overflow.");
+                assert_eq!(error.line, Some(1));
+                // only the frames at either end of the trace are listed
+                assert_eq!(error.trace.len(), 2 * TRACE_EDGE_FRAMES + 1);
+                assert_eq!(
+                    error.trace[TRACE_EDGE_FRAMES],
+                    format!("... {} frames omitted", FRAMES_MAX - 2 * TRACE_EDGE_FRAMES)
+                );
+                assert_eq!(error.trace.last().unwrap(), "[line 1] in script");
+            }
+            other => panic!("Expected a stack overflow, got {:?}", other),
+        }
+
+        // recursion within the limit still works
+        let vm = VM::new();
+        let source = format!(
+            "fun depth(n) {{ if (n == 0) return 0; return 1 + depth(n - 1); }} var d = depth({});",
+            FRAMES_MAX - 2
+        );
+        assert!(vm.interpret(&source).is_ok());
+        assert_eq!(
+            global(&vm, "d"),
+            Some(Value::Number(RoxNumber((FRAMES_MAX - 2) as f32)))
+        );
+    }
+
+    #[test]
+    fn runtime_errors_carry_a_call_trace() {
+        let vm = VM::new();
+        let source = "fun c() {\n  return -nil;\n}\nfun b() {\n  return c();\n}\nfun a() {\n  return b();\n}\na();";
+        match vm.interpret(source) {
+            Err(InterpretError::RuntimeError(error)) => {
+                assert_eq!(
+                    error.trace,
+                    vec![
+                        "[line 2] in c()",
+                        "[line 5] in b()",
+                        "[line 8] in a()",
+                        "[line 10] in script",
+                    ]
+                );
+                assert_eq!(
+                    error.to_string(),
+                    "[line 2]: Operand must be a number.\n[line 2] in c()\n[line 5] in b()\n[line 8] in a()\n[line 10] in script"
+                );
+            }
+            other => panic!("Expected a runtime error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_print_functions() {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut vm = VM::new();
+        vm.set_output(output.clone());
+        let source =
+            "fun foo() {} print foo; var x = 1; fun bar() { return x; } print bar; print clock;";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+        assert_eq!(
+            String::from_utf8(output.borrow().clone()).unwrap(),
+            "<fn foo>\n<fn bar>\n<native fn>\n"
+        );
+    }
+
+    #[test]
+    fn test_trailing_expression_is_returned() {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut vm = VM::new();
+        vm.set_output(output.clone());
+        let source = "fun f() { 1 + 2 } print f();
+            fun g() { 1 + 2; } print g();
+            fun h(x) { var y = x * 2; y + 1 } print h(4);
+            print fun (x) { if (x) { 1; } x }(true);";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+        assert_eq!(
+            String::from_utf8(output.borrow().clone()).unwrap(),
+            "3\nnil\n9\ntrue\n"
+        );
+
+        // only the body's own last statement is returned
+        assert!(matches!(
+            VM::new().interpret("fun f(x) { if (x) 1 }"),
+            Err(InterpretError::CompileError(_))
+        ));
+    }
+
+    #[test]
+    fn anonymous_functions_are_values() {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut vm = VM::new();
+        vm.set_output(output.clone());
+        let source = "var f = fun (x) { return x * 2; }; print f(21);
+            var g = fun () { return 1; }(); print fun (a, b) { return a - b; };";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+        assert_eq!(
+            String::from_utf8(output.borrow().clone()).unwrap(),
+            "42\n<fn anonymous>\n"
+        );
+        assert_eq!(global(&vm, "g"), Some(Value::Number(RoxNumber(1.0))));
+    }
+
+    #[test]
+    fn closures_capture_enclosing_locals() {
+        let vm = VM::new();
+        let source = "fun makeCounter() {
+                var count = 0;
+                fun counter() { count = count + 1; return count; }
+                return counter;
+            }
+            var counter = makeCounter();
+            counter();
+            counter();
+            var result = counter();";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+        assert_eq!(global(&vm, "result"), Some(Value::Number(RoxNumber(3.0))));
+
+        // a captured block local is closed over when its scope ends
+        let vm = VM::new();
+        let source =
+            "var f; { var x = 1; f = fun () { x = x + 1; return x; }; } var a = f(); var b = f();";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+        assert_eq!(global(&vm, "a"), Some(Value::Number(RoxNumber(2.0))));
+        assert_eq!(global(&vm, "b"), Some(Value::Number(RoxNumber(3.0))));
+    }
+
+    #[test]
+    fn reading_an_undefined_global_names_it() {
+        match VM::new().interpret("print undefinedVar;") {
+            Err(InterpretError::RuntimeError(error)) => {
+                assert_eq!(error.message, "Undefined variable 'undefinedVar'.");
+                assert_eq!(error.line, Some(1));
+            }
+            other => panic!("Expected an undefined variable error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn assigning_an_undeclared_global_is_an_error() {
+        let vm = VM::new();
+        match vm.interpret("x = 1;") {
+            Err(InterpretError::RuntimeError(error)) => {
+                assert_eq!(error.message, "Undefined variable 'x'.")
+            }
+            other => panic!("Expected an undefined variable error, got {:?}", other),
+        }
+        assert!(!vm.is_global_defined(&RoxString::new("x")));
+
+        let vm = VM::new();
+        if let Err(msg) = vm.interpret("var x; x = 1;") {
+            panic!("{}", msg)
+        }
+        assert_eq!(global(&vm, "x"), Some(Value::Number(RoxNumber(1.0))));
+    }
+
+    #[test]
+    fn print_writes_to_the_output_sink() {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut vm = VM::new();
+        vm.set_output(output.clone());
+        if let Err(msg) = vm.interpret("print 1; print 2;") {
+            panic!("{}", msg)
+        }
+        assert_eq!(output.borrow().as_slice(), b"1\n2\n");
+    }
+
+    #[test]
+    fn unary_plus_requires_a_number() {
+        assert_eq!(
+            VM::new().interpret("+5;").unwrap(),
+            InterpretOk(Value::Number(RoxNumber(5.0)))
+        );
+        assert_eq!(
+            VM::new().interpret("var x = 2; 1 + +x;").unwrap(),
+            InterpretOk(Value::Number(RoxNumber(3.0)))
+        );
+
+        match VM::new().interpret("+true;") {
+            Err(InterpretError::RuntimeError(error)) => {
+                assert_eq!(error.message, "Operand must be a number.")
+            }
+            other => panic!("Expected a runtime error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn multiplying_a_string_repeats_it() {
+        let vm = VM::new();
+        let source = "var a = \"ab\" * 3; var b = 3 * \"ab\"; var c = \"ab\" * 0;";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+        assert_eq!(string_value(global(&vm, "a")), "ababab");
+        assert_eq!(string_value(global(&vm, "b")), "ababab");
+        assert_eq!(string_value(global(&vm, "c")), "");
+
+        for source in ["\"x\" * -1;", "\"x\" * 1.5;"] {
+            match VM::new().interpret(source) {
+                Err(InterpretError::RuntimeError(error)) => assert_eq!(
+                    error.message,
+                    "A string can only be repeated a whole, non-negative number of times."
+                ),
+                other => panic!("Expected a runtime error for {}, got {:?}", source, other),
+            }
+        }
+        assert!(VM::new().interpret("\"x\" * \"y\";").is_err());
+        assert!(VM::new().interpret("\"x\" * nil;").is_err());
+
+        for source in [
+            "\"ab\" * 100000000;",
+            "1000000000000000000000000000000 * \"x\";",
+        ] {
+            match VM::new().interpret(source) {
+                Err(InterpretError::RuntimeError(error)) => assert_eq!(
+                    error.message,
+                    format!(
+                        "A repeated string can't be longer than {} bytes.",
+                        MAX_REPEAT_LEN
+                    )
+                ),
+                other => panic!("Expected a runtime error for {}, got {:?}", source, other),
+            }
+        }
+    }
+
+    #[test]
+    fn conditional_branches_bind_around_assignment() {
+        let vm = VM::new();
+        let source = "var c = false; var a = c ? 1 : 2;
+            var b; c ? b = 1 : b = 2;
+            var d; !c ? d = 3 : d = 4;
+            var e = c ? 1 : c ? 2 : 3;";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "a"), Some(Value::Number(RoxNumber(2.0))));
+        assert_eq!(global(&vm, "b"), Some(Value::Number(RoxNumber(2.0))));
+        assert_eq!(global(&vm, "d"), Some(Value::Number(RoxNumber(3.0))));
+        assert_eq!(global(&vm, "e"), Some(Value::Number(RoxNumber(3.0))));
+    }
+
+    #[test]
+    fn test_arithmetic_names_the_bad_operand() {
+        for (source, message) in [
+            ("1 + true;", "Operands must be numbers, but true is a bool."),
+            ("nil * 2;", "Operands must be numbers, but nil is a nil."),
+            (
+                "var a = 1; a - \"b\";",
+                "Operands must be numbers, but \"b\" is a string.",
+            ),
+        ] {
+            match VM::new().interpret(source) {
+                Err(InterpretError::RuntimeError(error)) => assert_eq!(error.message, message),
+                _ => panic!("Expected a runtime error for {}", source),
+            }
+        }
+    }
+
+    fn interned_string(value: Option<Value>) -> Rc<RoxString> {
+        match value {
+            Some(Value::Object(RoxObject {
+                object_type: ObjectType::ObjString(string),
+                ..
+            })) => string,
+            other => panic!("Expected a string, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_identical_strings_are_interned() {
+        let vm = VM::new();
+        let source = "var a = \"abc\"; var b = \"abc\"; var c = \"ab\" + \"c\";";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        let a = interned_string(global(&vm, "a"));
+        let b = interned_string(global(&vm, "b"));
+        let c = interned_string(global(&vm, "c"));
+        assert!(Rc::ptr_eq(&a, &b));
+        assert!(Rc::ptr_eq(&a, &c));
+    }
+
+    fn string_value(value: Option<Value>) -> String {
+        match value {
+            Some(Value::Object(RoxObject {
+                object_type: ObjectType::ObjString(string),
+                ..
+            })) => string.to_string(),
+            other => panic!("Expected a string, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_catch_thrown_string() {
+        let vm = VM::new();
+        if let Err(msg) =
+            vm.interpret("var caught; try { throw \"boom\"; } catch (e) { caught = e; }")
+        {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(string_value(global(&vm, "caught")), "boom");
+    }
+
+    #[test]
+    fn test_catch_error_object() {
+        let vm = VM::new();
+        let source = "var message; var line;
+            try {
+                throw Error(\"boom\");
+            } catch (e) { message = e.message; line = e.line; }";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(string_value(global(&vm, "message")), "boom");
+        assert_eq!(global(&vm, "line"), Some(Value::Number(RoxNumber(3.0))));
+    }
+
+    #[test]
+    fn test_rethrow_keeps_error_line() {
+        let vm = VM::new();
+        let source = "var line;
+            try {
+                try { throw Error(\"boom\"); }
+                catch (e) {
+                    throw e;
+                }
+            } catch (e) { line = e.line; }";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "line"), Some(Value::Number(RoxNumber(3.0))));
+    }
+
+    #[test]
+    fn test_undefined_error_property() {
+        let vm = VM::new();
+        assert!(matches!(
+            vm.interpret("var e = Error(\"boom\"); print e.code;"),
+            Err(InterpretError::RuntimeError(_))
+        ));
+        assert!(matches!(
+            vm.interpret("var a = 1; print a.message;"),
+            Err(InterpretError::RuntimeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_uncaught_throw() {
+        let vm = VM::new();
+        match vm.interpret("throw \"boom\";") {
+            Err(InterpretError::Thrown(value)) => assert_eq!(string_value(Some(value)), "boom"),
+            other => panic!("Expected a thrown value, got {:?}", other),
+        }
+
+        // reported as print would show the value
+        let message = VM::new()
+            .interpret("throw \"boom\";")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(message, "Uncaught exception: boom");
+    }
+
+    #[test]
+    fn test_finally_normal_path() {
+        let vm = VM::new();
+        let source = "var steps = 0;
+            try { steps = steps + 1; } finally { var a = 10; steps = steps + a; }
+            try { steps = steps + 100; } catch (e) { steps = 0; } finally { steps = steps + 1000; }";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "steps"), Some(Value::Number(RoxNumber(1111.0))));
+    }
+
+    #[test]
+    fn test_finally_after_caught_exception() {
+        let vm = VM::new();
+        let source = "var caught; var ran;
+            { var a = 1; try { throw a; } catch (e) { caught = e; } finally { ran = a + 1; } }";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "caught"), Some(Value::Number(RoxNumber(1.0))));
+        assert_eq!(global(&vm, "ran"), Some(Value::Number(RoxNumber(2.0))));
+    }
+
+    #[test]
+    fn test_finally_rethrows_uncaught_exception() {
+        let vm = VM::new();
+        let source = "var ran; var caught;
+            try {
+                try { throw 1; } finally { ran = true; }
+            } catch (e) { caught = e; }
+            try { var b = 1 / 0; } catch (e) { throw e; } finally { ran = false; }";
+
+        assert!(matches!(
+            vm.interpret(source),
+            Err(InterpretError::Thrown(_))
+        ));
+        assert_eq!(global(&vm, "caught"), Some(Value::Number(RoxNumber(1.0))));
+        assert_eq!(global(&vm, "ran"), Some(Value::Boolean(false)));
+    }
+
+    #[test]
+    fn test_finally_keeps_runtime_error() {
+        let vm = VM::new();
+        assert!(matches!(
+            vm.interpret("try { -true; } finally { }"),
+            Err(InterpretError::RuntimeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_finally_on_early_return() {
+        let vm = VM::new();
+        let source = "var ran = 0;
+            fun f(a) {
+                try { try { return a; } finally { ran = ran + 1; } } finally { ran = ran + 10; }
+                return 0;
+            }
+            fun g() {
+                try { return 1; } finally { return 2; }
+            }
+            var result = f(5);
+            var overridden = g();";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "result"), Some(Value::Number(RoxNumber(5.0))));
+        assert_eq!(global(&vm, "ran"), Some(Value::Number(RoxNumber(11.0))));
+        assert_eq!(
+            global(&vm, "overridden"),
+            Some(Value::Number(RoxNumber(2.0)))
+        );
+    }
+
+    #[test]
+    fn test_try_without_catch_or_finally() {
+        let vm = VM::new();
+        assert!(matches!(
+            vm.interpret("try { print 1; }"),
+            Err(InterpretError::CompileError(_))
+        ));
+    }
+
+    fn answer_native(_vm: &VM, _args: &[Value]) -> Result<Value, RuntimeError> {
+        Ok(Value::Number(RoxNumber(42.0)))
+    }
+
+    fn sum_native(_vm: &VM, args: &[Value]) -> Result<Value, RuntimeError> {
+        Ok(args
+            .iter()
+            .fold(Value::Number(RoxNumber(0.0)), |acc, arg| acc + arg.clone()))
+    }
+
+    #[test]
+    fn test_native_call() {
+        let vm = VM::new();
+        vm.define_native("answer", 0, answer_native);
+        vm.define_native("sum", 2, sum_native);
+        if let Err(msg) = vm.interpret("var a = answer(); var b = sum(a, 8) * 2;") {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "a"), Some(Value::Number(RoxNumber(42.0))));
+        assert_eq!(global(&vm, "b"), Some(Value::Number(RoxNumber(100.0))));
+    }
+
+    #[test]
+    fn test_native_arity_mismatch() {
+        let vm = VM::new();
+        assert!(matches!(
+            vm.interpret("clock(1);"),
+            Err(InterpretError::RuntimeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_floor_division() {
+        let vm = VM::new();
+        let source = "var a = 7 // 2; var seven = 7; var b = seven // 2; var c = -seven // 2;
+            var d = (seven + 1) // 3; // a comment after the statement";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "a"), Some(Value::Number(RoxNumber(3.0))));
+        assert_eq!(global(&vm, "b"), Some(Value::Number(RoxNumber(3.0))));
+        assert_eq!(global(&vm, "c"), Some(Value::Number(RoxNumber(-4.0))));
+        assert_eq!(global(&vm, "d"), Some(Value::Number(RoxNumber(2.0))));
+        assert!(VM::new().interpret("var zero = 0; 1 // zero;").is_err());
+    }
+
+    #[test]
+    fn test_chained_concatenation() {
+        let vm = VM::new();
+        if let Err(msg) = vm.interpret("var s = \"a\" + \"b\" + \"c\" + \"d\";") {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(string_value(global(&vm, "s")), "abcd");
+    }
+
+    #[test]
+    fn test_cached_global_access() {
+        let vm = VM::new();
+        let source = "var step = 1; var total = 0;
+            for (var i = 0; i < 100000; i += 1) total = total + step;
+            fun read() { return step; } var before = read();
+            var step = 2; var after = read();";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(
+            global(&vm, "total"),
+            Some(Value::Number(RoxNumber(100000.0)))
+        );
+        // redefining a global is seen through slots cached before it
+        assert_eq!(global(&vm, "before"), Some(Value::Number(RoxNumber(1.0))));
+        assert_eq!(global(&vm, "after"), Some(Value::Number(RoxNumber(2.0))));
+    }
+
+    #[test]
+    fn test_nil_equality() {
+        let vm = VM::new();
+        let source = "var a = nil == nil; var b = nil == 0; var c = nil == false; var d = nil == \"\"; var e = nil != 0;";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "a"), Some(Value::Boolean(true)));
+        assert_eq!(global(&vm, "b"), Some(Value::Boolean(false)));
+        assert_eq!(global(&vm, "c"), Some(Value::Boolean(false)));
+        assert_eq!(global(&vm, "d"), Some(Value::Boolean(false)));
+        assert_eq!(global(&vm, "e"), Some(Value::Boolean(true)));
+    }
+
+    #[test]
+    fn test_strict_equality() {
+        let vm = VM::new();
+        let source = "var a = 1 === 1; var b = 1 === true; var c = nil === false; \
+                      var d = \"ab\" === \"a\" + \"b\"; var e = nil === nil; \
+                      var f = 1 == 2 === false; var g = 0 === \"0\";";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "a"), Some(Value::Boolean(true)));
+        assert_eq!(global(&vm, "b"), Some(Value::Boolean(false)));
+        assert_eq!(global(&vm, "c"), Some(Value::Boolean(false)));
+        assert_eq!(global(&vm, "d"), Some(Value::Boolean(true)));
+        assert_eq!(global(&vm, "e"), Some(Value::Boolean(true)));
+        // both operators share equality precedence and group to the left
+        assert_eq!(global(&vm, "f"), Some(Value::Boolean(true)));
+        assert_eq!(global(&vm, "g"), Some(Value::Boolean(false)));
+    }
+
+    #[test]
+    fn test_error_value_is_reported() {
+        let vm = VM::new();
+        vm.define_native("broken", 0, |_, _| Ok(Value::Error));
+
+        match vm.interpret("var a = 1;\nvar b = broken() + a;") {
+            Err(InterpretError::RuntimeError(error)) => {
+                assert_eq!(error.message, "OP_CALL produced an invalid value.");
+                assert_eq!(error.line, Some(2));
+            }
+            result => panic!("expected a runtime error, got {:?}", result),
+        }
+        assert_eq!(global(&vm, "b"), None);
+    }
+
+    #[test]
+    fn test_interpret_after_uncaught_error() {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut vm = VM::new();
+        vm.set_output(output.clone());
+
+        assert!(vm
+            .interpret("var a = nil; print a + 2; print \"skipped\";")
+            .is_err());
+        assert!(vm.interpret("print 5;").is_ok());
+        assert!(vm
+            .interpret("fun f() { return -nil; } print f(); print \"skipped\";")
+            .is_err());
+        assert!(vm.interpret("print 6;").is_ok());
+        assert!(vm
+            .interpret("fun g() { try { throw 1; } finally { var x = 1; } } g();")
+            .is_err());
+        assert_eq!(
+            vm.interpret("7;").unwrap(),
+            InterpretOk(Value::Number(RoxNumber(7.0)))
+        );
+
+        assert_eq!(
+            String::from_utf8(output.borrow().clone()).unwrap(),
+            "5\n6\n"
+        );
+    }
+
+    #[test]
+    fn test_readline_native() {
+        let mut vm = VM::new();
+        vm.set_input(Rc::new(RefCell::new(io::Cursor::new("hello\n"))));
+        if let Err(msg) = vm.interpret("var first = readline(); var second = readline();") {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(string_value(global(&vm, "first")), "hello");
+        assert_eq!(global(&vm, "second"), Some(Value::Nil));
+    }
+
+    #[test]
+    fn test_num_native() {
+        let mut vm = VM::new();
+        vm.set_input(Rc::new(RefCell::new(io::Cursor::new("42\nabc\n"))));
+        let source = "var a = num(readline()); var b = num(readline());
+            var c = num(\" -2.5 \"); var d = num(7); var e = num(\"nan\");";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "a"), Some(Value::Number(RoxNumber(42.0))));
+        assert_eq!(global(&vm, "b"), Some(Value::Nil));
+        assert_eq!(global(&vm, "c"), Some(Value::Number(RoxNumber(-2.5))));
+        assert_eq!(global(&vm, "d"), Some(Value::Number(RoxNumber(7.0))));
+        assert_eq!(global(&vm, "e"), Some(Value::Nil));
+        assert!(VM::new().interpret("num(true);").is_err());
+    }
+
+    #[test]
+    fn test_assert_native() {
+        let vm = VM::new();
+        if let Err(msg) = vm.interpret("assert(true); assert(1, \"unused\");") {
+            panic!("{}", msg)
+        }
+
+        let message = VM::new()
+            .interpret("assert(false, \"boom\");")
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("Assertion failed: boom"), "{}", message);
+
+        let message = VM::new()
+            .interpret("assert(nil, 42);")
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("Assertion failed: 42"), "{}", message);
+
+        let message = VM::new()
+            .interpret("assert(false);")
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("Assertion failed."), "{}", message);
+
+        let message = VM::new().interpret("assert();").unwrap_err().to_string();
+        assert!(
+            message.contains("Expected 1 to 2 arguments but got 0."),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn test_between_native() {
+        let vm = VM::new();
+        let source = "var a = between(5, 1, 10); var b = between(5, 6, 10);
+            var c = between(1, 1, 10); var d = between(10, 1, 10);";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "a"), Some(Value::Boolean(true)));
+        assert_eq!(global(&vm, "b"), Some(Value::Boolean(false)));
+        assert_eq!(global(&vm, "c"), Some(Value::Boolean(true)));
+        assert_eq!(global(&vm, "d"), Some(Value::Boolean(true)));
+
+        let message = VM::new()
+            .interpret("between(\"5\", 1, 10);")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            message.contains("between expects number arguments."),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn test_pad_native() {
+        let vm = VM::new();
+        let source = "var a = pad(7, 3); var b = pad(\"ab\", 4); var c = pad(-1.5, 6);
+            var d = pad(\"long\", 2); var e = pad(true, 0);";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        let padded = ["a", "b", "c", "d", "e"].map(|name| string_value(global(&vm, name)));
+        assert_eq!(padded, ["  7", "ab  ", "  -1.5", "long", "true"]);
+
+        // wider than format! allows
+        if let Err(msg) = vm.interpret("var wide = pad(\"x\", 70000);") {
+            panic!("{}", msg)
+        }
+        assert_eq!(string_value(global(&vm, "wide")).len(), 70000);
+        assert!(VM::new().interpret("pad(1, -1);").is_err());
+        assert!(VM::new().interpret("pad(1, \"3\");").is_err());
+
+        let message = VM::new()
+            .interpret(&format!("pad(1, {});", 2 * MAX_REPEAT_LEN))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            message.contains(&format!("pad width can't be more than {}.", MAX_REPEAT_LEN)),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn test_str_native() {
+        let vm = VM::new();
+        let source = "var a = str(1.5); var b = str(nil); var c = str(\"ab\");
+            var d = str([1, true]); var e = str(1) + \"!\";";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        let strings = ["a", "b", "c", "d", "e"].map(|name| string_value(global(&vm, name)));
+        assert_eq!(strings, ["1.5", "nil", "ab", "[1, true]", "1!"]);
+    }
+
+    #[test]
+    fn test_defined_native() {
+        let vm = VM::new();
+        let source = "var before = defined(\"x\"); var x = 1; var after = defined(\"x\");";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "before"), Some(Value::Boolean(false)));
+        assert_eq!(global(&vm, "after"), Some(Value::Boolean(true)));
+        assert!(vm.interpret("defined(1);").is_err());
+    }
+
+    #[test]
+    fn test_typeof_native() {
+        let vm = VM::new();
+        let source = "var a = typeof(1); var b = typeof(nil); var c = typeof(\"a\");
+            var d = typeof(true); var e = typeof(clock); var f = typeof([1]);";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        let names = ["a", "b", "c", "d", "e", "f"].map(|name| string_value(global(&vm, name)));
+        assert_eq!(
+            names,
+            ["number", "nil", "string", "bool", "function", "array"]
+        );
+    }
+
+    #[test]
+    fn test_internal_panics_become_runtime_errors() {
+        let mut vm = VM::new();
+        vm.define_native("crash", 0, |_, _| panic!("crashed"));
+        match vm.interpret_catching_panics("var x = crash();") {
+            Err(InterpretError::RuntimeError(error)) => {
+                assert!(error.message.starts_with("internal VM panic: "))
+            }
+            other => panic!("Expected a runtime error, got {:?}", other),
+        }
+
+        // the VM can still be used afterwards
+        assert_eq!(
+            vm.interpret_catching_panics("1 + 2;").unwrap(),
+            InterpretOk(Value::Number(RoxNumber(3.0)))
+        );
+    }
+
+    #[test]
+    fn test_clock_native() {
+        let vm = VM::new();
+        if let Err(msg) = vm.interpret("var start = clock(); var elapsed = clock() - start;") {
+            panic!("{}", msg)
+        }
+
+        match global(&vm, "elapsed") {
+            Some(Value::Number(RoxNumber(seconds))) => assert!(seconds >= 0.0),
+            other => panic!("Expected elapsed seconds, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_string_natives() {
+        let vm = VM::new();
+        if let Err(msg) = vm.interpret("var n = len(\"hello\"); var c = char_at(\"hello\", 1);") {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "n"), Some(Value::Number(RoxNumber(5.0))));
+        assert_eq!(string_value(global(&vm, "c")), "e");
+
+        for source in [
+            "char_at(\"hello\", 5);",
+            "char_at(\"hello\", -1);",
+            "len(5);",
+        ] {
+            assert!(matches!(
+                VM::new().interpret(source),
+                Err(InterpretError::RuntimeError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_for_loop_break() {
+        let vm = VM::new();
+        let source = "var count = 0;
+            for (var i = 0; i < 10; i = i + 1) { var a = i; if (a == 3) break; count = count + 1; }
+            var after = count;";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "count"), Some(Value::Number(RoxNumber(3.0))));
+        assert_eq!(global(&vm, "after"), Some(Value::Number(RoxNumber(3.0))));
+    }
+
+    #[test]
+    fn test_for_loop_multiple_increments() {
+        let vm = VM::new();
+        let source = "var i = 0; var j = 10; var steps = 0;
+            for (; i < j; i = i + 1, j = j - 1) { steps = steps + 1; }
+            var pairs = \"\";
+            for (var a = 0; a < 2; a = a + 1, steps = steps + 1) { pairs = pairs + \"x\"; }";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "i"), Some(Value::Number(RoxNumber(5.0))));
+        assert_eq!(global(&vm, "j"), Some(Value::Number(RoxNumber(5.0))));
+        assert_eq!(global(&vm, "steps"), Some(Value::Number(RoxNumber(7.0))));
+        assert_eq!(string_value(global(&vm, "pairs")), "xx");
+    }
+
+    #[test]
+    fn test_loop_continue() {
+        let vm = VM::new();
+        let source = "var sum = 0;
+            for (var i = 0; i < 5; i = i + 1) { if (i % 2 == 0) continue; sum = sum + i; }
+            var j = 0;
+            while (j < 5) { j = j + 1; { var k = j; if (k == 2) continue; } sum = sum + 10; }";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "sum"), Some(Value::Number(RoxNumber(44.0))));
+    }
+
+    #[test]
+    fn test_nested_loop_break() {
+        let vm = VM::new();
+        let source = "var count = 0;
+            for (var i = 0; i < 3; i = i + 1) {
+                var j = 0;
+                while (true) { if (j == 2) break; j = j + 1; count = count + 1; }
+            }";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "count"), Some(Value::Number(RoxNumber(6.0))));
+    }
+
+    #[test]
+    fn test_break_runs_finally() {
+        let vm = VM::new();
+        let source = "var ran = 0; var count = 0;
+            while (true) {
+                var a = 1;
+                try { var b = 2; try { break; } catch (e) {} } finally { ran = ran + a; }
+                count = 1;
+            }
+            for (var i = 0; i < 3; i = i + 1) {
+                try { continue; } finally { ran = ran + 10; }
+            }";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "ran"), Some(Value::Number(RoxNumber(31.0))));
+        assert_eq!(global(&vm, "count"), Some(Value::Number(RoxNumber(0.0))));
+    }
+
+    #[test]
+    fn test_break_outside_loop() {
+        let vm = VM::new();
+        assert!(matches!(
+            vm.interpret("break;"),
+            Err(InterpretError::CompileError(_))
+        ));
+        assert!(matches!(
+            vm.interpret("fun f() { continue; } while (false) { f(); }"),
+            Err(InterpretError::CompileError(_))
+        ));
+    }
+
+    #[test]
+    fn test_bitwise_ops() {
+        let vm = VM::new();
+        let source = "var bit_and = 6 & 3; var shl = 1 << 4; var xor = 12 ^ 10;
+            var bit_or = 12 | 3; var shr = 256 >> 2; var mixed = 1 | 2 ^ 6 & 3 << 1;
+            var compared = 5 & 1 == 1;";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "bit_and"), Some(Value::Number(RoxNumber(2.0))));
+        assert_eq!(global(&vm, "shl"), Some(Value::Number(RoxNumber(16.0))));
+        assert_eq!(global(&vm, "xor"), Some(Value::Number(RoxNumber(6.0))));
+        assert_eq!(global(&vm, "bit_or"), Some(Value::Number(RoxNumber(15.0))));
+        assert_eq!(global(&vm, "shr"), Some(Value::Number(RoxNumber(64.0))));
+        assert_eq!(global(&vm, "mixed"), Some(Value::Number(RoxNumber(5.0))));
+        assert_eq!(global(&vm, "compared"), Some(Value::Boolean(true)));
+    }
+
+    #[test]
+    fn test_bitwise_non_integer_operands() {
+        for source in [
+            "1.5 & 1;",
+            "1 | true;",
+            "\"a\" ^ 1;",
+            "1 << -1;",
+            "1 >> 64;",
+        ] {
+            let vm = VM::new();
+            assert!(
+                matches!(vm.interpret(source), Err(InterpretError::RuntimeError(_))),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_conditional_expression() {
+        let vm = VM::new();
+        let source = "var a = 1 < 2 ? 10 : 20;
+            var b = 1 > 2 ? 10 : 20;
+            var c = false ? 1 : true ? 2 : 3;
+            var d = 1 == 1 or false ? 4 + 1 : 6;";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "a"), Some(Value::Number(RoxNumber(10.0))));
+        assert_eq!(global(&vm, "b"), Some(Value::Number(RoxNumber(20.0))));
+        assert_eq!(global(&vm, "c"), Some(Value::Number(RoxNumber(2.0))));
+        assert_eq!(global(&vm, "d"), Some(Value::Number(RoxNumber(5.0))));
+    }
+
+    #[test]
+    fn test_conditional_missing_colon() {
+        let vm = VM::new();
+        assert!(matches!(
+            vm.interpret("print true ? 1;"),
+            Err(InterpretError::CompileError(_))
+        ));
+    }
+
+    #[test]
+    fn test_function_call() {
+        let vm = VM::new();
+        let source = "fun sum(a, b, c) { var d = a + b; return d + c; }
+            var result = sum(1, 2, 3);
+            fun nothing() {}
+            var empty = nothing();";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "result"), Some(Value::Number(RoxNumber(6.0))));
+        assert_eq!(global(&vm, "empty"), Some(Value::Nil));
+    }
+
+    #[test]
+    fn test_recursive_function() {
+        let vm = VM::new();
+        let source = "fun fib(n) { if (n < 2) return n; return fib(n - 2) + fib(n - 1); }
+            var result;
+            { var a = 10; result = fib(a); }";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "result"), Some(Value::Number(RoxNumber(55.0))));
+    }
+
+    #[test]
+    fn test_function_arity_mismatch() {
+        let vm = VM::new();
+        assert!(matches!(
+            vm.interpret("fun f(a) { return a; } var g = f; g(1, 2);"),
+            Err(InterpretError::RuntimeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_parameter_requires_name() {
+        match VM::new().interpret("fun f(1) {}") {
+            Err(InterpretError::CompileError(message)) => {
+                assert!(message.contains("Expect parameter name."), "{}", message)
+            }
+            other => panic!("Expected a compile error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_default_parameters() {
+        let vm = VM::new();
+        let source = "fun greet(name, greeting = \"hello\") { return greeting + \" \" + name; }
+            var a = greet(\"bob\"); var b = greet(\"bob\", \"hi\");
+            fun f(x, y = -1, z = nil) { return z; } var g = f; var c = g(1); var d = g(1, 2, 3);";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(string_value(global(&vm, "a")), "hello bob");
+        assert_eq!(string_value(global(&vm, "b")), "hi bob");
+        assert_eq!(global(&vm, "c"), Some(Value::Nil));
+        assert_eq!(global(&vm, "d"), Some(Value::Number(RoxNumber(3.0))));
+
+        let message = VM::new()
+            .interpret("fun f(a, b = 1) {} f();")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            message.contains("Expected 1 to 2 arguments but got 0."),
+            "{}",
+            message
+        );
+        assert!(matches!(
+            VM::new().interpret("fun f(a, b = 1) {} var g = f; g(1, 2, 3);"),
+            Err(InterpretError::RuntimeError(_))
+        ));
+        assert!(matches!(
+            VM::new().interpret("fun f(a = 1, b) {}"),
+            Err(InterpretError::CompileError(_))
+        ));
+        assert!(matches!(
+            VM::new().interpret("var x = 1; fun f(a = x) {}"),
+            Err(InterpretError::CompileError(_))
+        ));
+    }
+
+    #[test]
+    fn test_rest_parameter() {
+        let vm = VM::new();
+        let source = "fun sum(first, ...rest) {
+                var total = first;
+                for (var i = 0; i < len(rest); i += 1) total += rest[i];
+                return total;
+            }
+            var a = sum(1, 2, 3, 4); var b = sum(5);
+            fun count(...all) { return len(all); } var f = count; var c = f(); var d = f(nil, nil);";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "a"), Some(Value::Number(RoxNumber(10.0))));
+        assert_eq!(global(&vm, "b"), Some(Value::Number(RoxNumber(5.0))));
+        assert_eq!(global(&vm, "c"), Some(Value::Number(RoxNumber(0.0))));
+        assert_eq!(global(&vm, "d"), Some(Value::Number(RoxNumber(2.0))));
+
+        let message = VM::new()
+            .interpret("fun f(a, ...rest) {} var g = f; g();")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            message.contains("Expected at least 1 arguments but got 0."),
+            "{}",
+            message
+        );
+        assert!(matches!(
+            VM::new().interpret("fun f(...rest, a) {}"),
+            Err(InterpretError::CompileError(_))
+        ));
+        assert!(matches!(
+            VM::new().interpret("fun f(a = 1, ...rest) {}"),
+            Err(InterpretError::CompileError(_))
+        ));
+    }
+
+    #[test]
+    fn test_call_error_messages() {
+        let message = VM::new()
+            .interpret("fun f(a) {} f(1, 2);")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            message.contains("Expected 1 arguments but got 2."),
+            "{}",
+            message
+        );
+
+        let message = VM::new()
+            .interpret("fun f(a) {} var g = f; g();")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            message.contains("Expected 1 arguments but got 0."),
+            "{}",
+            message
+        );
+
+        let message = VM::new()
+            .interpret("var x = 3; x();")
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("Can only call functions."), "{}", message);
+    }
+
+    #[test]
+    fn test_direct_call_arity_checked_at_compile_time() {
+        let vm = VM::new();
+        assert!(matches!(
+            vm.interpret("fun add(a, b) { return a + b; } add(1);"),
+            Err(InterpretError::CompileError(_))
+        ));
+        assert!(matches!(
+            vm.interpret("fun add(a, b) { return a + b; } fun f() { return add(1, 2, 3); }"),
+            Err(InterpretError::CompileError(_))
+        ));
+    }
+
+    #[test]
+    fn test_indirect_call_arity_checked_at_runtime() {
+        let vm = VM::new();
+        assert!(matches!(
+            vm.interpret("fun add(a, b) { return a + b; } var f = add; f(1);"),
+            Err(InterpretError::RuntimeError(_))
+        ));
+        let vm = VM::new();
+        assert!(matches!(
+            vm.interpret("fun add(a, b) { return a + b; } var c = true; (c ? add : add)(1);"),
+            Err(InterpretError::RuntimeError(_))
+        ));
+        let vm = VM::new();
+        if let Err(msg) = vm.interpret(
+            "fun add(a, b) { return a + b; } fun one(a) { return a; } add = one; var r = add(1);",
+        ) {
+            panic!("{}", msg)
+        }
+        assert_eq!(global(&vm, "r"), Some(Value::Number(RoxNumber(1.0))));
+    }
+
+    #[test]
+    fn test_call_non_function() {
+        let vm = VM::new();
+        assert!(matches!(
+            vm.interpret("var a = 1; a();"),
+            Err(InterpretError::RuntimeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_catch_error_thrown_in_function() {
+        let vm = VM::new();
+        let source = "fun fail(a) { throw a * 2; }
+            var caught;
+            try { fail(21); } catch (e) { caught = e; }";
+        if let Err(msg) = vm.interpret(source) {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(global(&vm, "caught"), Some(Value::Number(RoxNumber(42.0))));
+    }
+
+    #[test]
+    fn test_top_level_return() {
+        let vm = VM::new();
+        assert!(matches!(
+            vm.interpret("return 1;"),
+            Err(InterpretError::CompileError(_))
+        ));
+    }
+
+    #[test]
+    fn test_function_hooks() {
+        let vm = VM::new();
+        let events = Rc::new(RefCell::new(vec![]));
+        let recorded = Rc::clone(&events);
+        vm.set_hook(Box::new(move |event| match event {
+            HookEvent::LineChange { .. } => (),
+            _ => recorded.borrow_mut().push(event.clone()),
+        }));
+
+        if let Err(msg) = vm.interpret("fun f() { return 1; } f();") {
+            panic!("{}", msg)
+        }
+
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                HookEvent::FunctionEnter {
+                    name: "f".to_string()
+                },
+                HookEvent::FunctionExit {
+                    name: "f".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_repl_echoes_expression_statements() {
+        let vm = VM::new();
+        if let Err(msg) = vm.interpret_repl("var a = 1; a + 1; \"hi\"; { a; }") {
+            panic!("{}", msg)
+        }
+
+        let chunk = vm.chunk.borrow();
+        assert_eq!(
+            chunk
+                .code
+                .iter()
+                .filter(|op| matches!(op, OpCode::OpEcho))
+                .count(),
+            2
+        );
+    }
+
     #[test]
     fn test_local_vars() {
         let vm = VM::new();
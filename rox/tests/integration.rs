@@ -5,7 +5,7 @@ macro_rules! make_rox_test {
     ($fn_name:ident, $filename:literal) => {
         #[test]
         fn $fn_name() {
-            let config = Config::new(&mut args()).unwrap();
+            let mut config = Config::new(&mut args()).unwrap();
 
             match config.run_file_with_filename($filename) {
                 Err(msg) => panic!("{}", msg),
@@ -20,7 +20,7 @@ macro_rules! make_rox_test_panic {
         #[test]
         #[should_panic]
         fn $fn_name() {
-            let config = Config::new(&mut args()).unwrap();
+            let mut config = Config::new(&mut args()).unwrap();
 
             match config.run_file_with_filename($filename) {
                 Err(_) => panic!("Correctly panicked in test for {}", $filename),
@@ -42,3 +42,13 @@ make_rox_test!(test_if_statement_scope, "rox_tests/if_statement_scope.rox");
 make_rox_test!(test_logical_expr, "rox_tests/logical_expr.rox");
 make_rox_test!(test_while_loop, "rox_tests/while_loop.rox");
 make_rox_test!(test_for_loop, "rox_tests/for_loop.rox");
+make_rox_test!(test_modulo, "rox_tests/modulo.rox");
+make_rox_test!(test_else_if_statement, "rox_tests/else_if_statement.rox");
+make_rox_test!(test_try_catch, "rox_tests/try_catch.rox");
+make_rox_test!(test_function, "rox_tests/function.rox");
+make_rox_test!(test_finally, "rox_tests/finally.rox");
+make_rox_test!(test_break_continue, "rox_tests/break_continue.rox");
+make_rox_test!(test_conditional, "rox_tests/conditional.rox");
+make_rox_test!(test_array, "rox_tests/array.rox");
+make_rox_test!(test_do_while, "rox_tests/do_while.rox");
+make_rox_test!(test_closure, "rox_tests/closure.rox");